@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::Json;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use serde_json::Value;
+use socketioxide::socket::Sid;
+use tracing::{info, warn};
+use crate::AppState;
+use crate::auth::same_secret;
+use crate::db_model::{AdminAuditCollection, RoomStatus};
+use crate::errors::MyError;
+use crate::metrics::event_emitted;
+use crate::model::{AdminAuditResp, AdminSocket, Announcement, AnnouncementReq, Filter, RoomStatusReq, RoomStatusResp};
+
+/// Admin API settings, read from the environment <br/>
+/// - `ADMIN_TOKEN`: bearer token expected in the `Authorization` header, the admin API is not mounted without it
+/// - `ADMIN_USER`: operator owning `ADMIN_TOKEN`, recorded as the actor in the audit log (`admin` by default)
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    pub token: Option<String>,
+    pub user: String,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        let token = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.trim().is_empty());
+        let user = std::env::var("ADMIN_USER").ok().filter(|user| !user.trim().is_empty()).unwrap_or("admin".to_string());
+        Self { token, user }
+    }
+}
+
+/// Operator recorded in the audit log <br/>
+/// `name` is the `ADMIN_USER` owning the token, `claimed` the free-form `X-Admin-User` header, which anyone holding the
+/// token can set, so it is only kept as an unverified hint next to the actor
+#[derive(Debug, Clone)]
+pub struct AdminActor {
+    pub name: String,
+    pub claimed: Option<String>,
+}
+
+/// Middleware of the admin router: checks the bearer token and attaches the `AdminActor` to the request
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let token = state.admin_config.token.as_deref()
+        .ok_or(MyError::ForbiddenError("the admin API is disabled".to_string()))?;
+    let given = request.headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !same_secret(token, given) {
+        return Err(MyError::UnauthorizedError("invalid admin token".to_string()).into());
+    }
+
+    let claimed = request.headers()
+        .get("x-admin-user")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(String::from);
+    request.extensions_mut().insert(AdminActor { name: state.admin_config.user.clone(), claimed });
+
+    Ok(next.run(request).await)
+}
+
+/// Record an admin action, a failing write is only logged so the action itself is not reported as failed
+pub async fn audit(state: &AppState, actor: &AdminActor, action: &str, target: Option<String>, details: Option<String>) {
+    info!("Admin {} ({:?}) {} {:?}", actor.name, actor.claimed, action, target);
+    let entry = AdminAuditCollection {
+        id: bson::oid::ObjectId::new(),
+        actor: actor.name.clone(),
+        claimed_actor: actor.claimed.clone(),
+        action: action.to_string(),
+        target,
+        details,
+        created_at: chrono::Utc::now(),
+    };
+    if let Err(e) = state.db.insert_admin_audit(&entry, None).await {
+        warn!("Error writing admin audit: {:?}", e);
+    }
+}
+
+/// Live sockets of the default namespace, with the owned username and the rooms of each socket
+pub async fn list_sockets(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let sockets = state.io.sockets().unwrap_or_default();
+    let mut resp: Vec<AdminSocket> = Vec::with_capacity(sockets.len());
+    for socket in sockets {
+        resp.push(AdminSocket {
+            id: socket.id.to_string(),
+            user: state.socket_state.socket_user(&socket.id.to_string()).await,
+            rooms: socket.rooms().unwrap_or_default().into_iter().map(|room| room.to_string()).collect(),
+        });
+    }
+
+    Ok((StatusCode::OK, Json(resp)))
+}
+
+pub async fn disconnect_socket(
+    Path(id): Path<String>,
+    Extension(actor): Extension<AdminActor>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let sid = id.parse::<Sid>().map_err(|_| MyError::InvalidIDError(id.clone()))?;
+    let socket = state.io.get_socket(sid).ok_or(MyError::NotFoundError(id.clone()))?;
+    socket.disconnect().map_err(|e| MyError::OwnError(e.to_string()))?;
+
+    audit(&state, &actor, "disconnect_socket", Some(id), None).await;
+    Ok((StatusCode::OK, Json(serde_json::json!({ "disconnected": 1 }))))
+}
+
+/// Disconnect every socket joined to the owned username room of the user
+pub async fn disconnect_user(
+    Path(username): Path<String>,
+    Extension(actor): Extension<AdminActor>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let count = state.io.within(username.clone()).sockets().unwrap_or_default().len();
+    state.io.within(username.clone()).disconnect().ok();
+
+    audit(&state, &actor, "disconnect_user", Some(username), Some(format!("{} sockets", count))).await;
+    Ok((StatusCode::OK, Json(serde_json::json!({ "disconnected": count }))))
+}
+
+/// Emit `announcement` to every socket, or only to the sockets of `room`
+pub async fn announce(
+    Extension(actor): Extension<AdminActor>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<AnnouncementReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if data.message.trim().is_empty() {
+        return Err(MyError::BadRequestError("empty announcement".to_string()).into());
+    }
+    let announcement = Announcement {
+        message: data.message,
+        room: data.room,
+        date_time: chrono::Utc::now(),
+    };
+
+    event_emitted("announcement");
+    match &announcement.room {
+        Some(room) => state.io.within(room.clone()).emit("announcement", announcement.clone()).ok(),
+        None => state.io.emit("announcement", announcement.clone()).ok(),
+    };
+
+    audit(&state, &actor, "announce", announcement.room.clone(), Some(announcement.message.clone())).await;
+    Ok((StatusCode::OK, Json(announcement)))
+}
+
+/// Close, archive or reopen a room, the room is told with `room_status` <br/>
+/// The sockets of a closed room are removed from it, an archived room keeps them but refuses new messages
+pub async fn set_room_status(
+    Path(room): Path<String>,
+    Extension(actor): Extension<AdminActor>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<RoomStatusReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let res = state.db.set_room_status(&room, data.status, &actor.name).await?;
+    let resp = RoomStatusResp {
+        room: res.room,
+        status: res.status,
+    };
+
+    event_emitted("room_status");
+    state.io.within(room.clone()).emit("room_status", resp.clone()).ok();
+    if resp.status == RoomStatus::Closed {
+        state.io.within(room.clone()).leave(room.clone()).ok();
+    }
+
+    audit(&state, &actor, "set_room_status", Some(room), Some(format!("{:?}", resp.status).to_lowercase())).await;
+    Ok((StatusCode::OK, Json(resp)))
+}
+
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let stats = state.db.collection_stats().await?;
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+/// Page of the audit log, newest first
+pub async fn get_audit(
+    filter: Option<Query<Filter>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let Query(filter) = filter.unwrap_or_default();
+    let limit = filter.limit.unwrap_or(50).max(1) as i64;
+    let page = filter.page.unwrap_or(1).max(1) as i64;
+
+    let entries = state.db.get_admin_audit(limit, page).await?;
+    let entries = entries.into_iter().map(|entry| AdminAuditResp {
+        id: entry.id.to_hex(),
+        actor: entry.actor,
+        claimed_actor: entry.claimed_actor,
+        action: entry.action,
+        target: entry.target,
+        details: entry.details,
+        date_time: entry.created_at,
+    }).collect::<Vec<AdminAuditResp>>();
+
+    Ok((StatusCode::OK, Json(entries)))
+}
@@ -0,0 +1,131 @@
+use std::fmt;
+use std::sync::Arc;
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Json;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::AppState;
+use crate::errors::MyError;
+
+/// Query parameter carrying the session token where no header can be set, e.g. the `src` of an image
+const TOKEN_PARAM: &str = "access_token";
+
+/// Owned username of the caller of the HTTP API <br/>
+/// Each `user_handle` carrying a valid identity token issues a session token (see `SocketState::open_session`),
+/// returned in `user_handled`, which is only valid while that socket is connected and linked to the same user. The token is sent as `Authorization: Bearer <token>` (or `?access_token=`) and resolved to the owned
+/// username linked to the socket, the usernames of the payloads and query strings are never trusted
+#[derive(Debug, Clone)]
+pub struct AuthUser(pub String);
+
+/// Verifies the identity tokens sent with `user_handle`, issued by the login service holding `IDENTITY_SECRET` <br/>
+/// A token is `<username>.<expiry in unix seconds>.<signature>`, the signature being the hex HMAC-SHA256 of
+/// `<username>.<expiry>` keyed with the secret. No connection can link an owned username while the secret is unset
+#[derive(Clone, Default)]
+pub struct IdentityVerifier {
+    secret: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for IdentityVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityVerifier")
+            .field("configured", &self.secret.is_some())
+            .finish()
+    }
+}
+
+impl IdentityVerifier {
+    pub fn new(secret: Option<String>) -> Self {
+        Self { secret: secret.filter(|secret| !secret.trim().is_empty()).map(String::into_bytes) }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("IDENTITY_SECRET").ok())
+    }
+
+    /// Token proving the ownership of `username` until `expires_at` (unix seconds), `None` without a secret
+    pub fn sign(&self, username: &str, expires_at: i64) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let claims = format!("{}.{}", username, expires_at);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+        mac.update(claims.as_bytes());
+        let signature = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        Some(format!("{}.{}", claims, signature))
+    }
+
+    /// Check that `token` was signed for `username` and has not expired
+    pub fn verify(&self, token: &str, username: &str) -> Result<(), MyError> {
+        if self.secret.is_none() {
+            return Err(MyError::ForbiddenError("identity verification is not configured".to_string()));
+        }
+        let invalid = || MyError::UnauthorizedError("invalid identity token".to_string());
+        // the username may contain dots, the expiry and the signature can not
+        let mut parts = token.rsplitn(3, '.');
+        let (Some(signature), Some(expires_at), Some(subject)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let expires_at = expires_at.parse::<i64>().map_err(|_| invalid())?;
+        let expected = self.sign(subject, expires_at).unwrap_or_default();
+        let expected = expected.rsplit('.').next().unwrap_or_default();
+        // same length for every valid token, the bytes are compared without an early exit
+        if expected.len() != signature.len() || expected.bytes().zip(signature.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) != 0 {
+            return Err(invalid());
+        }
+        if expires_at < chrono::Utc::now().timestamp() {
+            return Err(MyError::UnauthorizedError("expired identity token".to_string()));
+        }
+        if !subject.eq(username) {
+            return Err(MyError::ForbiddenError(format!("the identity token is not for {}", username)));
+        }
+        Ok(())
+    }
+}
+
+/// New session token, 32 random bytes in hex, only issued to a `socket_state::VerifiedUser`
+pub fn new_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Compare a shared secret with the one given by the caller, through their digests so the comparison does not depend
+/// on the length or the content of the secret
+pub fn same_secret(expected: &str, given: &str) -> bool {
+    let expected = Sha256::digest(expected.as_bytes());
+    let given = Sha256::digest(given.as_bytes());
+    expected.iter().zip(given.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Only the digest of a token is stored, a leaked `sockets` collection does not leak live sessions
+pub fn session_digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn session_token(parts: &Parts) -> Option<String> {
+    let header = parts.headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(String::from);
+    let query = || parts.uri.query()
+        .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix(TOKEN_PARAM)?.strip_prefix('=')))
+        .map(String::from);
+    header.or_else(query).filter(|token| !token.is_empty())
+}
+
+#[axum::async_trait]
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let token = session_token(parts).ok_or(MyError::UnauthorizedError("missing session token".to_string()))?;
+        match state.db.session_user(&session_digest(&token)).await? {
+            Some(username) => Ok(AuthUser(username)),
+            None => Err(MyError::UnauthorizedError("invalid or expired session token".to_string()).into())
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use async_trait::async_trait;
+use crate::errors::MyError;
+
+/// override the standard result type for the module
+type Result<T> = std::result::Result<T, MyError>;
+
+/// Storage backend for the attachment contents <br/>
+/// Only the raw bytes are kept in the store, the metadata lives in the `attachments` collection
+/// so a backend (local disk, S3, ...) can be swapped without touching the DB layer
+#[async_trait]
+pub trait BlobStore: Send + Sync + Debug {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// Blob store writing every attachment as a single file under `root`
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    pub root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub async fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        tokio::fs::create_dir_all(&root).await?;
+        Ok(Self { root })
+    }
+
+    /// Keys are generated by the server (ObjectId hex), anything else is rejected
+    /// so a key can never point outside of the root directory
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(MyError::BadRequestError(format!("invalid blob key: {}", key)));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        tokio::fs::write(self.path(key)?, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(MyError::NotFoundError(key.to_string())),
+            Err(e) => Err(MyError::StorageError(e))
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(MyError::StorageError(e))
+        }
+    }
+}
+
+/// Upload limits, read from the environment <br/>
+/// - `MAX_UPLOAD_BYTES`: maximum size of a single file (default 10 MiB) <br/>
+/// - `ALLOWED_MIME_TYPES`: comma separated list, same `[a,b]` format as `ORIGINS`
+#[derive(Debug, Clone)]
+pub struct AttachmentConfig {
+    pub max_size: usize,
+    pub allowed_types: Vec<String>,
+}
+
+impl AttachmentConfig {
+    pub fn from_env() -> Self {
+        let max_size = std::env::var("MAX_UPLOAD_BYTES")
+            .ok()
+            .and_then(|size| size.parse::<usize>().ok())
+            .unwrap_or(10 * 1024 * 1024);
+
+        let allowed_types = std::env::var("ALLOWED_MIME_TYPES")
+            .unwrap_or("image/png,image/jpeg,image/gif,image/webp,application/pdf,text/plain".to_owned())
+            .replace(['[', ']'], "")
+            .split(',')
+            .map(|mime| mime.trim().to_lowercase())
+            .filter(|mime| !mime.is_empty())
+            .collect::<Vec<String>>();
+
+        Self { max_size, allowed_types }
+    }
+
+    pub fn is_allowed(&self, content_type: &str) -> bool {
+        // ignore parameters such as `; charset=utf-8`
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+        self.allowed_types.iter().any(|allowed| allowed.eq(&mime))
+    }
+}
+
+/// Content type of an upload read from its first bytes, the type declared by the client is not trusted <br/>
+/// Text is only recognized as valid UTF-8 without control characters, anything unknown is `application/octet-stream`
+pub fn sniff_content_type(data: &[u8]) -> &'static str {
+    const SIGNATURES: [(&[u8], &str); 5] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(signature, _)| data.starts_with(signature)) {
+        return mime;
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+
+    let text = std::str::from_utf8(data).is_ok()
+        && !data.iter().any(|byte| byte.is_ascii_control() && !matches!(byte, b'\n' | b'\r' | b'\t'));
+    if text && !data.is_empty() { "text/plain" } else { "application/octet-stream" }
+}
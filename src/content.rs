@@ -0,0 +1,394 @@
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Kind of a span found in the raw message text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityKind {
+    Mention,
+    Link,
+    CodeBlock,
+    InlineCode,
+    Bold,
+    Italic,
+    Strikethrough,
+}
+
+/// A span of the message text <br/>
+/// `offset` and `length` are counted in UTF-16 code units, i.e. the same as JavaScript string indices,
+/// so the clients can slice the raw text directly <br/>
+/// `target` is the username for mentions, the URL for links and the language (if any) for code blocks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entity {
+    pub kind: EntityKind,
+    pub offset: usize,
+    pub length: usize,
+    pub target: Option<String>,
+}
+
+/// Metadata shown by the clients below a message containing a link
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// Maximum number of links of a single message for which the previews are fetched
+pub const MAX_PREVIEWS: usize = 3;
+
+/// Parse the raw message text into entities <br/>
+/// Nothing inside code (blocks or inline) is parsed further, the markdown spans (`**bold**`, `*italic*`, `~~strike~~`)
+/// can contain mentions and links and are reported along with them, sorted by offset
+pub fn parse_entities(text: &str) -> Vec<Entity> {
+    let chars: Vec<char> = text.chars().collect();
+    // utf16[i] is the UTF-16 offset of chars[i], with one extra entry for the end of the text
+    let mut utf16 = Vec::with_capacity(chars.len() + 1);
+    let mut acc = 0;
+    for c in &chars {
+        utf16.push(acc);
+        acc += c.len_utf16();
+    }
+    utf16.push(acc);
+
+    let span = |kind: EntityKind, start: usize, end: usize, target: Option<String>| Entity {
+        kind,
+        offset: utf16[start],
+        length: utf16[end] - utf16[start],
+        target,
+    };
+
+    let mut entities: Vec<Entity> = Vec::new();
+    // (position, width) of the closing markers of the markdown spans already reported, skipped when reached
+    let mut closers: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some((_, width)) = closers.iter().find(|(at, _)| *at == i) {
+            i += width;
+            continue;
+        }
+
+        if starts_with(&chars, i, "```") {
+            if let Some(close) = find(&chars, i + 3, "```", false) {
+                let info: String = chars[i + 3..close].iter().take_while(|c| **c != '\n').collect();
+                let language = Some(info.trim().to_string()).filter(|l| !l.is_empty() && !l.contains(char::is_whitespace));
+                entities.push(span(EntityKind::CodeBlock, i, close + 3, language));
+                i = close + 3;
+                continue;
+            }
+        }
+
+        if chars[i] == '`' {
+            if let Some(close) = find(&chars, i + 1, "`", true) {
+                if close > i + 1 {
+                    entities.push(span(EntityKind::InlineCode, i, close + 1, None));
+                    i = close + 1;
+                    continue;
+                }
+            }
+        }
+
+        let boundary = i == 0 || !(chars[i - 1].is_alphanumeric() || chars[i - 1] == '_');
+
+        if boundary && (starts_with(&chars, i, "http://") || starts_with(&chars, i, "https://")) {
+            let mut end = i;
+            while end < chars.len() && !chars[end].is_whitespace() && !matches!(chars[end], '<' | '>' | '`') {
+                end += 1;
+            }
+            while end > i && matches!(chars[end - 1], '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '}' | '\'' | '"' | '*' | '~') {
+                end -= 1;
+            }
+            let url: String = chars[i..end].iter().collect();
+            if !url.ends_with("://") {
+                entities.push(span(EntityKind::Link, i, end, Some(url)));
+                i = end;
+                continue;
+            }
+        }
+
+        if boundary && chars[i] == '@' {
+            let mut end = i + 1;
+            while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_' || chars[end] == '-') {
+                end += 1;
+            }
+            if end > i + 1 {
+                let name: String = chars[i + 1..end].iter().collect();
+                entities.push(span(EntityKind::Mention, i, end, Some(name)));
+                i = end;
+                continue;
+            }
+        }
+
+        for (marker, kind) in [("**", EntityKind::Bold), ("~~", EntityKind::Strikethrough), ("*", EntityKind::Italic)] {
+            if !starts_with(&chars, i, marker) {
+                continue;
+            }
+            let width = marker.chars().count();
+            let close = find(&chars, i + width, marker, true)
+                .filter(|close| *close > i + width && !chars[i + width].is_whitespace() && !chars[close - 1].is_whitespace());
+            if let Some(close) = close {
+                entities.push(span(kind, i, close + width, None));
+                closers.push((close, width));
+                i += width - 1;
+            }
+            break;
+        }
+
+        i += 1;
+    }
+
+    entities.sort_by_key(|entity| entity.offset);
+    entities
+}
+
+/// Targets of the mention entities, deduplicated in order of appearance
+pub fn mentions(entities: &[Entity]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for entity in entities.iter().filter(|e| e.kind == EntityKind::Mention) {
+        if let Some(name) = &entity.target {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// URLs of the link entities, deduplicated in order of appearance
+pub fn links(entities: &[Entity]) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for entity in entities.iter().filter(|e| e.kind == EntityKind::Link) {
+        if let Some(url) = &entity.target {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+    }
+    urls
+}
+
+fn starts_with(chars: &[char], at: usize, pattern: &str) -> bool {
+    pattern.chars().enumerate().all(|(idx, p)| chars.get(at + idx) == Some(&p))
+}
+
+fn find(chars: &[char], from: usize, pattern: &str, single_line: bool) -> Option<usize> {
+    let mut idx = from;
+    while idx < chars.len() {
+        if single_line && chars[idx] == '\n' {
+            return None;
+        }
+        if starts_with(chars, idx, pattern) {
+            return Some(idx);
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// Source of the link previews <br/>
+/// The fetcher is called off the message path, returning `None` simply means no preview for that URL
+#[async_trait]
+pub trait LinkPreviewFetcher: Send + Sync + Debug {
+    async fn fetch(&self, url: &str) -> Option<LinkPreview>;
+}
+
+/// Fetcher used when previews are disabled (or offline), never returns a preview
+#[derive(Debug, Default, Clone)]
+pub struct NoopPreviewFetcher;
+
+#[async_trait]
+impl LinkPreviewFetcher for NoopPreviewFetcher {
+    async fn fetch(&self, _url: &str) -> Option<LinkPreview> {
+        None
+    }
+}
+
+/// Fetch the page and read the OpenGraph tags (falling back to `<title>`) <br/>
+/// *NOTE:* Only `http(s)` URLs on public hosts are fetched, hosts given as loopback or private IPs and `localhost` are refused,
+/// on the requested URL and on every redirect.
+/// The hostnames are not resolved up front, so this is not a complete SSRF protection and should be combined with egress rules
+#[derive(Debug, Clone)]
+pub struct HttpPreviewFetcher {
+    client: reqwest::Client,
+    max_bytes: usize,
+}
+
+impl HttpPreviewFetcher {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() > 3 {
+                    attempt.error("too many redirects")
+                } else if !Self::is_public(attempt.url()) {
+                    // the redirect response is returned as is, which is not a success so no preview is made
+                    attempt.stop()
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .user_agent("socketioxide-chat-link-preview")
+            .build()
+            .unwrap();
+
+        Self { client, max_bytes: 256 * 1024 }
+    }
+
+    /// Only http(s) URLs to a public host are fetched, an IPv4-mapped IPv6 address is checked as the IPv4 one
+    pub(crate) fn is_public(url: &reqwest::Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+        let host = match url.host_str() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return false
+        };
+        match host.parse::<IpAddr>().map(|ip| ip.to_canonical()) {
+            Ok(ip) => !(ip.is_loopback() || ip.is_unspecified() || is_private(ip)),
+            Err(_) => !host.eq_ignore_ascii_case("localhost") && !host.ends_with(".localhost")
+        }
+    }
+}
+
+impl Default for HttpPreviewFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64,
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+#[async_trait]
+impl LinkPreviewFetcher for HttpPreviewFetcher {
+    async fn fetch(&self, url: &str) -> Option<LinkPreview> {
+        let parsed = reqwest::Url::parse(url).ok()?;
+        if !Self::is_public(&parsed) {
+            return None;
+        }
+
+        let mut resp = self.client.get(parsed).send().await.ok()?;
+        if !resp.status().is_success() {
+            return None;
+        }
+        let is_html = resp.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.contains("text/html"))
+            .unwrap_or(false);
+        if !is_html {
+            return None;
+        }
+
+        let mut body: Vec<u8> = Vec::new();
+        while let Ok(Some(chunk)) = resp.chunk().await {
+            body.extend_from_slice(&chunk);
+            if body.len() >= self.max_bytes {
+                break;
+            }
+        }
+
+        let preview = parse_preview(url, &String::from_utf8_lossy(&body));
+        if preview.title.is_none() && preview.description.is_none() && preview.image.is_none() {
+            None
+        } else {
+            Some(preview)
+        }
+    }
+}
+
+/// Read the OpenGraph `<meta>` tags of the html page <br/>
+/// The tags are searched in an ASCII lowercase copy, which keeps the byte offsets of `html` so they can be used to slice it
+pub(crate) fn parse_preview(url: &str, html: &str) -> LinkPreview {
+    let mut preview = LinkPreview {
+        url: url.to_string(),
+        ..Default::default()
+    };
+
+    let lower = html.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(start) = lower[from..].find("<meta").map(|idx| idx + from) {
+        let end = match lower[start..].find('>') {
+            Some(idx) => start + idx,
+            None => break
+        };
+        let tag = &html[start..end];
+        from = end;
+
+        let key = attribute(tag, "property").or_else(|| attribute(tag, "name")).map(|key| key.to_lowercase());
+        let content = attribute(tag, "content");
+        match (key.as_deref(), content) {
+            (Some("og:title"), Some(content)) => preview.title = Some(content),
+            (Some("og:description"), Some(content)) => preview.description = Some(content),
+            (Some("description"), Some(content)) if preview.description.is_none() => preview.description = Some(content),
+            (Some("og:image"), Some(content)) => preview.image = Some(content),
+            (Some("og:site_name"), Some(content)) => preview.site_name = Some(content),
+            _ => {}
+        }
+    }
+
+    if preview.title.is_none() {
+        let open_end = lower.find("<title").and_then(|start| lower[start..].find('>').map(|idx| start + idx + 1));
+        if let Some(open_end) = open_end {
+            if let Some(end) = lower[open_end..].find("</title").map(|idx| open_end + idx) {
+                let title = decode_entities(html[open_end..end].trim());
+                if !title.is_empty() {
+                    preview.title = Some(title);
+                }
+            }
+        }
+    }
+
+    preview
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(idx) = lower[from..].find(name).map(|idx| idx + from) {
+        from = idx + name.len();
+        // make sure `name` is a whole attribute name (e.g. not `og:name`)
+        if idx > 0 && !lower.as_bytes()[idx - 1].is_ascii_whitespace() {
+            continue;
+        }
+        let rest = lower[from..].trim_start();
+        if !rest.starts_with('=') {
+            continue;
+        }
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let (quote, value) = match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => (Some(q), &value[1..]),
+            _ => (None, value)
+        };
+        let value = match quote {
+            Some(q) => value.split(q).next().unwrap_or(""),
+            // an unquoted value ends at a space, a `/` right before the end of the tag closes it (`<meta ... />`)
+            None => match value.split(char::is_whitespace).next().unwrap_or("") {
+                last if last.len() == value.len() => last.strip_suffix('/').unwrap_or(last),
+                value => value
+            }
+        };
+        return Some(decode_entities(value));
+    }
+    None
+}
+
+fn decode_entities(value: &str) -> String {
+    value.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
@@ -1,103 +1,364 @@
 use bson::oid::ObjectId;
 use chrono::Utc;
-use mongodb::bson::{doc, Document};
-use mongodb::{bson, ClientSession, Collection, IndexModel};
-use mongodb::options::{CountOptions, FindOptions};
+use std::collections::HashMap;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::{bson, ClientSession, Collection, Database};
+use mongodb::options::{CountOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument, UpdateModifications, UpdateOptions};
+use mongodb::results::{DeleteResult, InsertOneResult, UpdateResult};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tracing::info;
-use crate::db_model::{MessageCollection, PrivateMessageCollection, RoomCollection, SocketCollection, UserCollection};
+use crate::content::LinkPreview;
+use crate::db_model::{AdminAuditCollection, AttachmentCollection, ContactCollection, ContactStatus, DeliveryStatus, DmPolicy, Focus, GroupDmCollection, GroupMessageCollection, HeldMessageCollection, ModerationAuditCollection, MentionCollection, MessageCollection, NotificationCollection, PrivateMessageCollection, RemovedMessage, ReportAction, ReportCollection, ReportKind, ReportStatus, ReviewStatus, RoomCollection, RoomMemberCollection, RoomRole, RoomStatus, RoomStatusCollection, SocketCollection, UserCollection, UserProfile, UsernameHistoryCollection, UsernameKind};
 use crate::errors::MyError;
-use crate::model::{InPrivate, Message, PaginationResponse, SocketResponse, User};
+use crate::metrics::observe_db;
+use crate::moderation::MessageKind;
+use crate::model::{BlockList, BlockListKind, CollectionStats, TransactionSupport, Message, PaginationResponse, ProfilePatch, Restriction, SocketResponse, ThreadResponse, User, UserRelation};
 
 /// override the standard result type for the module
 type Result<T> = std::result::Result<T, MyError>;
 
 #[derive(Clone, Debug)]
 pub struct DB {
+    pub database: Option<Database>,
+    /// set by `detect_transactions`, the units of work run without a transaction while `false`
+    pub transactions: bool,
     pub sockets_collection: Option<Collection<SocketCollection>>,
     pub messages_collection: Option<Collection<MessageCollection>>,
     pub private_messages_collection: Option<Collection<PrivateMessageCollection>>,
     pub users_collection: Option<Collection<UserCollection>>,
     pub room_collection: Option<Collection<RoomCollection>>,
+    pub attachments_collection: Option<Collection<AttachmentCollection>>,
+    pub room_members_collection: Option<Collection<RoomMemberCollection>>,
+    pub mentions_collection: Option<Collection<MentionCollection>>,
+    pub notifications_collection: Option<Collection<NotificationCollection>>,
+    pub moderation_audit_collection: Option<Collection<ModerationAuditCollection>>,
+    pub held_messages_collection: Option<Collection<HeldMessageCollection>>,
+    pub reports_collection: Option<Collection<ReportCollection>>,
+    pub room_status_collection: Option<Collection<RoomStatusCollection>>,
+    pub admin_audit_collection: Option<Collection<AdminAuditCollection>>,
+    pub username_history_collection: Option<Collection<UsernameHistoryCollection>>,
+    pub contacts_collection: Option<Collection<ContactCollection>>,
+    pub group_dms_collection: Option<Collection<GroupDmCollection>>,
+    pub group_messages_collection: Option<Collection<GroupMessageCollection>>,
 }
 
-impl DB {
-    pub async fn connect_mongo() -> Result<DB> {
-        let client = mongodb::Client::with_uri_str(std::env::var("MONGO_URI").unwrap_or("mongodb://localhost:27017".to_owned())).await?;
-        let db = client.database("socketioxide");
-        let sockets_collection = Some(db.collection("sockets"));
-        let messages_collection = Some(db.collection("messages"));
-        let private_messages_collection = Some(db.collection("private_messages"));
-        let users_collection = Some(db.collection("users"));
-        let room_collection = Some(db.collection("rooms"));
+/// How often each instance refreshes `updated_at` of the sockets it holds (see `touch_sockets`)
+pub const SOCKET_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(30);
+/// Sockets not refreshed for this long belong to an instance which went down without a disconnect,
+/// their focus and session are ignored
+const SOCKET_TTL_SECS: i64 = 90;
+
+/// `updated_at` condition matching the sockets still refreshed by their instance
+fn fresh_socket() -> Document {
+    doc! {"$gte": Utc::now() - chrono::Duration::seconds(SOCKET_TTL_SECS)}
+}
+
+/// Check for the duplicate key (11000) error raised by the unique indexes, reported as a write error by the inserts
+/// and updates and as a command error by `find_one_and_update`
+pub(crate) fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    match e.kind.as_ref() {
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error)) => write_error.code == 11000,
+        mongodb::error::ErrorKind::Command(command_error) => command_error.code == 11000,
+        _ => false
+    }
+}
+
+/// The `Collection` operations of the session-aware `DB` methods, run inside `session` when the unit of work has one
+/// and as plain operations otherwise (see `UnitOfWork`)
+trait InSession<T> {
+    async fn insert_one_in(&self, doc: &T, session: Option<&mut ClientSession>) -> mongodb::error::Result<InsertOneResult>;
+
+    async fn find_one_in(&self, filter: Document, options: Option<FindOneOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>>;
+
+    /// Every document matching `filter`, the cursor of a session has to be driven with the session so it is drained here
+    async fn find_all_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<Vec<T>>;
+
+    async fn update_one_in(&self, filter: Document, update: Document, options: Option<UpdateOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<UpdateResult>;
+
+    async fn update_many_in(&self, filter: Document, update: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<UpdateResult>;
+
+    async fn find_one_and_update_in(&self, filter: Document, update: impl Into<UpdateModifications>, options: Option<FindOneAndUpdateOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>>;
+
+    async fn find_one_and_delete_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>>;
+
+    async fn delete_many_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<DeleteResult>;
+}
+
+impl<T> InSession<T> for Collection<T>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    async fn insert_one_in(&self, doc: &T, session: Option<&mut ClientSession>) -> mongodb::error::Result<InsertOneResult> {
+        match session {
+            Some(session) => self.insert_one_with_session(doc, None, session).await,
+            None => self.insert_one(doc, None).await
+        }
+    }
+
+    async fn find_one_in(&self, filter: Document, options: Option<FindOneOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>> {
+        match session {
+            Some(session) => self.find_one_with_session(filter, options, session).await,
+            None => self.find_one(filter, options).await
+        }
+    }
+
+    async fn find_all_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<Vec<T>> {
+        let mut docs: Vec<T> = Vec::new();
+        match session {
+            Some(session) => {
+                let mut cursor = self.find_with_session(filter, None, session).await?;
+                while cursor.advance(session).await? {
+                    docs.push(cursor.deserialize_current()?);
+                }
+            }
+            None => {
+                let mut cursor = self.find(filter, None).await?;
+                while cursor.advance().await? {
+                    docs.push(cursor.deserialize_current()?);
+                }
+            }
+        }
+        Ok(docs)
+    }
+
+    async fn update_one_in(&self, filter: Document, update: Document, options: Option<UpdateOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<UpdateResult> {
+        match session {
+            Some(session) => self.update_one_with_session(filter, update, options, session).await,
+            None => self.update_one(filter, update, options).await
+        }
+    }
 
-        let id_idx = IndexModel::builder()
-            .keys(doc! {"_id": 1})
-            .build();
-
-        let created_at_idx = IndexModel::builder()
-            .keys(doc! {"created_at": -1})
-            .build();
-
-        if let Some(sockets_collection) = &sockets_collection {
-            sockets_collection.create_index(id_idx.clone(), None).await?;
-            sockets_collection.create_index(created_at_idx.clone(), None).await?;
-            sockets_collection.create_index(
-                IndexModel::builder().keys(doc! {"username": "text"}).build(),
-                None,
-            ).await?;
+    async fn update_many_in(&self, filter: Document, update: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<UpdateResult> {
+        match session {
+            Some(session) => self.update_many_with_session(filter, update, None, session).await,
+            None => self.update_many(filter, update, None).await
         }
+    }
 
-        if let Some(messages_collection) = &messages_collection {
-            messages_collection.create_index(id_idx.clone(), None).await?;
-            messages_collection.create_index(created_at_idx.clone(), None).await?;
-            messages_collection.create_index(
-                IndexModel::builder().keys(doc! {"room": "text"}).build(),
-                None,
-            ).await?;
+    async fn find_one_and_update_in(&self, filter: Document, update: impl Into<UpdateModifications>, options: Option<FindOneAndUpdateOptions>, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>> {
+        match session {
+            Some(session) => self.find_one_and_update_with_session(filter, update, options, session).await,
+            None => self.find_one_and_update(filter, update, options).await
         }
+    }
 
-        if let Some(private_messages_collection) = &private_messages_collection {
-            private_messages_collection.create_index(id_idx.clone(), None).await?;
-            private_messages_collection.create_index(created_at_idx.clone(), None).await?;
-            private_messages_collection.create_index(
-                IndexModel::builder().keys(doc! {"sender": "text"}).build(),
-                None,
-            ).await?;
+    async fn find_one_and_delete_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<Option<T>> {
+        match session {
+            Some(session) => self.find_one_and_delete_with_session(filter, None, session).await,
+            None => self.find_one_and_delete(filter, None).await
         }
+    }
 
-        if let Some(users_collection) = &users_collection {
-            users_collection.create_index(id_idx.clone(), None).await?;
-            users_collection.create_index(created_at_idx.clone(), None).await?;
-            users_collection.create_index(
-                IndexModel::builder().keys(doc! {"owned_uname": "text"}).build(),
-                None,
-            ).await?;
+    async fn delete_many_in(&self, filter: Document, session: Option<&mut ClientSession>) -> mongodb::error::Result<DeleteResult> {
+        match session {
+            Some(session) => self.delete_many_with_session(filter, None, session).await,
+            None => self.delete_many(filter, None).await
         }
+    }
+}
+
+/// Replace `old` by `new` in every document of `collection` whose `field` holds it, `set_field` is `field.$` for arrays
+async fn rename_references<T>(
+    collection: &Option<Collection<T>>,
+    field: &str,
+    set_field: &str,
+    old: &str,
+    new: &str,
+    session: Option<&mut ClientSession>,
+) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    if let Some(collection) = collection {
+        collection.update_many_in(doc! {field: old}, doc! {"$set": {set_field: new}}, session).await?;
+    }
+    Ok(())
+}
+
+/// Rename the `target` of the documents of `kind` pointing to an owned username, e.g. the receiver of a held private message
+async fn rename_targets<T>(collection: &Option<Collection<T>>, kind: Bson, old: &str, new: &str, session: Option<&mut ClientSession>) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    if let Some(collection) = collection {
+        collection.update_many_in(doc! {"kind": kind, "target": old}, doc! {"$set": {"target": new}}, session).await?;
+    }
+    Ok(())
+}
 
-        if let Some(room_collection) = &room_collection {
-            room_collection.create_index(id_idx.clone(), None).await?;
-            room_collection.create_index(created_at_idx.clone(), None).await?;
-            room_collection.create_index(
-                IndexModel::builder().keys(doc! {"owned_username": "text"}).build(),
-                None,
-            ).await?;
+/// `collStats` of the collection, falling back to the estimated document count when the command is not allowed
+async fn collection_stats<T: Send + Sync>(collection: &Option<Collection<T>>) -> Result<Option<CollectionStats>> {
+    let collection = match collection {
+        Some(collection) => collection,
+        None => return Ok(None)
+    };
+    let documents = collection.estimated_document_count(None).await?;
+    let database = collection.client().database(&collection.namespace().db);
+
+    // the figures are returned as int32, int64 or double depending on the server version
+    let number = |value: Option<&bson::Bson>| match value {
+        Some(bson::Bson::Int32(v)) => Some(*v as i64),
+        Some(bson::Bson::Int64(v)) => Some(*v),
+        Some(bson::Bson::Double(v)) => Some(*v as i64),
+        _ => None
+    };
+    let (size, storage_size, indexes) = match database.run_command(doc! {"collStats": collection.name()}, None).await {
+        Ok(stats) => (number(stats.get("size")), number(stats.get("storageSize")), number(stats.get("nindexes"))),
+        Err(_) => (None, None, None)
+    };
+
+    Ok(Some(CollectionStats {
+        name: collection.name().to_string(),
+        documents,
+        size,
+        storage_size,
+        indexes,
+    }))
+}
+
+/// Parse a client provided hex ID into an `ObjectId`
+fn parse_oid(id: &str) -> Result<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| MyError::InvalidIDError(id.to_owned()))
+}
+
+/// Errors after which a unit of work can be run again from the start: a lost race on a unique index, or a
+/// transaction aborted by a conflicting write
+fn is_retryable(e: &MyError) -> bool {
+    match e {
+        MyError::MongoDuplicateError(_) => true,
+        MyError::MongoError(e) => is_duplicate_key(e) || e.contains_label(mongodb::error::TRANSIENT_TRANSACTION_ERROR),
+        _ => false
+    }
+}
+
+/// Multi-document operation of the store, started with `DB::begin` <br/>
+/// The session-aware `DB` methods take `unit.session()`: inside a transaction when the deployment supports them,
+/// `None` otherwise so the steps simply run one after the other
+pub struct UnitOfWork {
+    session: Option<ClientSession>,
+}
+
+impl UnitOfWork {
+    pub fn session(&mut self) -> Option<&mut ClientSession> {
+        self.session.as_mut()
+    }
+
+    /// Commit when `res` is `Ok`, abort otherwise, and pass `res` through
+    pub async fn finish<T>(mut self, res: Result<T>) -> Result<T> {
+        let Some(session) = self.session.as_mut() else {
+            return res;
+        };
+        match res {
+            Ok(value) => {
+                // the outcome of a commit interrupted by a network error is unknown, committing again is safe
+                if let Err(e) = session.commit_transaction().await {
+                    if !e.contains_label(mongodb::error::UNKNOWN_TRANSACTION_COMMIT_RESULT) {
+                        return Err(MyError::MongoError(e));
+                    }
+                    session.commit_transaction().await?;
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                session.abort_transaction().await.ok();
+                Err(e)
+            }
         }
+    }
+}
+
+impl DB {
+    pub async fn connect_mongo() -> Result<DB> {
+        let uri = std::env::var("MONGO_URI").unwrap_or("mongodb://localhost:27017".to_owned());
+        Self::connect(&uri, "socketioxide").await
+    }
+
+    /// Connect to the `database` of the deployment at `uri`, the integration tests use a database per test <br/>
+    /// The indexes are created by the migrations, see `migrations::Migrator`
+    pub async fn connect(uri: &str, database: &str) -> Result<DB> {
+        let client = mongodb::Client::with_uri_str(uri).await?;
+        let db = client.database(database);
+        let sockets_collection = Some(db.collection("sockets"));
+        let messages_collection = Some(db.collection("messages"));
+        let private_messages_collection = Some(db.collection("private_messages"));
+        let users_collection = Some(db.collection("users"));
+        let room_collection = Some(db.collection("rooms"));
+        let attachments_collection = Some(db.collection("attachments"));
+        let room_members_collection = Some(db.collection("room_members"));
+        let mentions_collection = Some(db.collection("mentions"));
+        let notifications_collection = Some(db.collection("notifications"));
+        let moderation_audit_collection = Some(db.collection("moderation_audit"));
+        let held_messages_collection = Some(db.collection("held_messages"));
+        let reports_collection = Some(db.collection("reports"));
+        let room_status_collection = Some(db.collection("room_status"));
+        let admin_audit_collection = Some(db.collection("admin_audit"));
+        let username_history_collection = Some(db.collection("username_history"));
+        let contacts_collection = Some(db.collection("contacts"));
+        let group_dms_collection = Some(db.collection("group_dms"));
+        let group_messages_collection = Some(db.collection("group_messages"));
 
         Ok(DB {
+            database: Some(db),
+            transactions: false,
             sockets_collection,
             messages_collection,
             private_messages_collection,
             users_collection,
             room_collection,
+            attachments_collection,
+            room_members_collection,
+            mentions_collection,
+            notifications_collection,
+            moderation_audit_collection,
+            held_messages_collection,
+            reports_collection,
+            room_status_collection,
+            admin_audit_collection,
+            username_history_collection,
+            contacts_collection,
+            group_dms_collection,
+            group_messages_collection,
         })
     }
+    /// Probe the deployment once at startup, transactions need a replica set or a sharded cluster
+    pub async fn detect_transactions(&mut self) -> Result<TransactionSupport> {
+        let support = self.transaction_support().await?;
+        self.transactions = support.supported;
+        Ok(support)
+    }
+
+    /// Start a unit of work, see `UnitOfWork`
+    pub async fn begin(&self) -> Result<UnitOfWork> {
+        if !self.transactions {
+            return Ok(UnitOfWork { session: None });
+        }
+        let collection = self.users_collection.as_ref().ok_or(MyError::OwnError(String::from("Users collection not found")))?;
+        let mut session = collection.client().start_session(None).await?;
+        session.start_transaction(None).await?;
+        Ok(UnitOfWork { session: Some(session) })
+    }
+
     pub fn message_to_doc(&self, message: &Message) -> Result<MessageCollection> {
+        let reply_to = match &message.reply_to {
+            Some(id) => Some(parse_oid(id)?),
+            None => None
+        };
+        let attachments = message.attachments.iter()
+            .map(|id| parse_oid(id))
+            .collect::<Result<Vec<ObjectId>>>()?;
+
         Ok(MessageCollection {
             id: mongodb::bson::oid::ObjectId::new(),
             sender: String::from(&message.sender),
             room: String::from(&message.room),
             message: String::from(&message.message),
+            reply_to,
+            reply_count: 0,
+            last_reply_at: None,
+            attachments,
+            entities: message.entities.clone(),
+            previews: message.previews.clone(),
             created_at: message.date_time,
             updated_at: chrono::Utc::now(),
         })
@@ -106,92 +367,366 @@ impl DB {
     #[allow(dead_code)]
     pub fn doc_to_message(&self, doc: Document) -> Result<Message> {
         Ok(Message {
+            id: doc.get_object_id("_id").ok().map(|id| id.to_hex()),
             sender: doc.get_str("sender").unwrap().to_string(),
             room: doc.get_str("room").unwrap().to_string(),
             message: doc.get_str("message").unwrap().to_string(),
+            reply_to: doc.get_object_id("reply_to").ok().map(|id| id.to_hex()),
+            reply_count: doc.get_i64("reply_count").unwrap_or(0),
+            last_reply_at: doc.get_datetime("last_reply_at").ok().map(|dt| dt.to_chrono()),
+            attachments: doc.get_array("attachments").map(|ids| {
+                ids.iter().filter_map(|id| id.as_object_id()).map(|id| id.to_hex()).collect()
+            }).unwrap_or_default(),
+            entities: doc.get("entities").cloned().and_then(|e| bson::from_bson(e).ok()).unwrap_or_default(),
+            previews: doc.get("previews").cloned().and_then(|p| bson::from_bson(p).ok()).unwrap_or_default(),
             date_time: doc.get_datetime("created_at").unwrap().to_chrono(),
         })
     }
+
+    pub fn collection_to_message(&self, doc: MessageCollection) -> Message {
+        Message {
+            id: Some(doc.id.to_hex()),
+            sender: doc.sender,
+            room: doc.room,
+            message: doc.message,
+            reply_to: doc.reply_to.map(|id| id.to_hex()),
+            reply_count: doc.reply_count,
+            last_reply_at: doc.last_reply_at.map(|dt| dt.to_chrono()),
+            attachments: doc.attachments.iter().map(|id| id.to_hex()).collect(),
+            entities: doc.entities,
+            previews: doc.previews,
+            date_time: doc.created_at,
+        }
+    }
 }
 
 impl DB {
-    pub async fn insert_message(&self, message: Message) -> Result<MessageCollection> {
-        if let Some(collection) = &self.messages_collection {
-            let doc = self.message_to_doc(&message).unwrap();
-            let insert_res = match collection.insert_one(&doc, None).await {
-                Ok(res) => res,
-                Err(e) => return Err(MyError::MongoError(e))
-            };
+    pub async fn insert_message(&self, message: Message, mut session: Option<&mut ClientSession>) -> Result<MessageCollection> {
+        observe_db("insert_message", async {
+            if let Some(collection) = &self.messages_collection {
+                let doc = self.message_to_doc(&message)?;
+                let insert_res = match collection.insert_one_in(&doc, session.as_deref_mut()).await {
+                    Ok(res) => res,
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
 
-            let oid = insert_res.inserted_id.as_object_id().unwrap();
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
 
-            let resp = self.find_message_oid(oid).await?;
-            Ok(resp)
-        } else {
-            Err(MyError::OwnError(String::from("Messages collection not found")))
-        }
+                let resp = self.find_doc_by_oid(&self.messages_collection, oid, session).await?;
+                Ok(resp)
+            } else {
+                Err(MyError::OwnError(String::from("Messages collection not found")))
+            }
+        }).await
     }
 
     pub async fn get_messages(&self, room: Option<String>) -> Result<Option<Vec<Message>>> {
-        if let Some(collection) = &self.messages_collection {
-            let filter = match room {
-                Some(room) => doc! {"room": room},
-                None => doc! {}
-            };
+        observe_db("get_messages", async {
+            if let Some(collection) = &self.messages_collection {
+                // thread replies are only served through `get_thread`
+                let filter = match room {
+                    Some(room) => doc! {"room": room, "reply_to": null},
+                    None => doc! {"reply_to": null}
+                };
 
-            let find_options = FindOptions::builder()
-                .sort(doc! {"created_at": -1})
-                .limit(20)
-                .build();
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": -1})
+                    .limit(20)
+                    .build();
 
-            let mut cursor = match collection.find(filter, find_options).await {
-                Ok(res) => res,
-                Err(e) => return Err(MyError::MongoError(e))
-            };
+                let mut cursor = match collection.find(filter, find_options).await {
+                    Ok(res) => res,
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
+
+                let mut results: Vec<Message> = Vec::new();
 
-            let mut results: Vec<Message> = Vec::new();
+                while cursor.advance().await? {
+                    let doc = cursor.deserialize_current().unwrap();
+                    results.push(self.collection_to_message(doc));
+                }
 
-            while cursor.advance().await? {
-                let doc = cursor.deserialize_current().unwrap();
-                results.push(Message {
-                    sender: doc.sender,
-                    room: doc.room,
-                    message: doc.message,
-                    date_time: doc.created_at,
-                });
+                if results.clone().is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(results))
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Messages collection not found")))
             }
+        }).await
+    }
+
+    /// Insert a reply into the thread of `message.reply_to` and bump the reply count of the root message <br/>
+    /// Replying to a reply attaches the message to the root of that thread, so threads stay one level deep <br/>
+    /// Returns the inserted reply and the updated root message
+    pub async fn insert_reply(&self, mut message: Message) -> Result<(MessageCollection, MessageCollection)> {
+        observe_db("insert_reply", async {
+            if let Some(collection) = &self.messages_collection {
+                let parent_id = match &message.reply_to {
+                    Some(id) => parse_oid(id)?,
+                    None => return Err(MyError::OwnError(String::from("Reply has no parent message")))
+                };
+
+                let parent = match collection.find_one(doc! {"_id": parent_id}, None).await? {
+                    Some(parent) => parent,
+                    None => return Err(MyError::NotFoundError(parent_id.to_hex()))
+                };
+                if parent.room.ne(&message.room) {
+                    return Err(MyError::OwnError(String::from("Parent message belongs to a different room")));
+                }
+                let root_id = parent.reply_to.unwrap_or(parent.id);
+                message.reply_to = Some(root_id.to_hex());
+
+                // the reply and the reply count of the root are written as one unit of work
+                let mut unit = self.begin().await?;
+                let res = async {
+                    let reply = self.insert_message(message, unit.session()).await?;
+
+                    let options = FindOneAndUpdateOptions::builder()
+                        .return_document(ReturnDocument::After)
+                        .build();
+                    let root = collection.find_one_and_update_in(
+                        doc! {"_id": root_id},
+                        doc! {
+                            "$inc": {"reply_count": 1_i64},
+                            "$set": {
+                                "last_reply_at": reply.created_at,
+                                "updated_at": Utc::now()
+                            }
+                        },
+                        Some(options),
+                        unit.session(),
+                    ).await?;
 
-            if results.clone().is_empty() {
-                Ok(None)
+                    match root {
+                        Some(root) => Ok((reply, root)),
+                        None => Err(MyError::NotFoundError(root_id.to_hex()))
+                    }
+                }.await;
+                unit.finish(res).await
             } else {
-                Ok(Some(results))
+                Err(MyError::OwnError(String::from("Messages collection not found")))
             }
-        } else {
-            Err(MyError::OwnError(String::from("Messages collection not found")))
-        }
+        }).await
     }
 
-    pub async fn insert_private_message(&self, message_collection: PrivateMessageCollection) -> Result<PrivateMessageCollection> {
-        if let Some(collection) = &self.private_messages_collection {
-            let insert_res = match collection.insert_one(&message_collection, None).await {
-                Ok(res) => res,
-                Err(e) => return Err(MyError::MongoError(e))
-            };
-            let oid = insert_res.inserted_id.as_object_id().unwrap();
+    /// Fetch the root message of a thread with a page of its replies, oldest first
+    pub async fn get_thread(&self, root_id: String, limit: i64, page: i64) -> Result<ThreadResponse> {
+        observe_db("get_thread", async {
+            if let Some(collection) = &self.messages_collection {
+                let oid = parse_oid(&root_id)?;
 
-            let verified = self.find_private_message_oid(oid).await?;
+                let root = match collection.find_one(doc! {"_id": oid}, None).await? {
+                    Some(root) => root,
+                    None => return Err(MyError::NotFoundError(root_id))
+                };
+                if root.reply_to.is_some() {
+                    return Err(MyError::OwnError(String::from("Message is a reply and not the root of a thread")));
+                }
 
-            Ok(verified)
-        } else {
-            Err(MyError::OwnError(String::from("Private Messages collection not found")))
-        }
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": 1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(doc! {"reply_to": oid}, find_options).await?;
+                let mut replies: Vec<Message> = Vec::new();
+                while cursor.advance().await? {
+                    let doc = cursor.deserialize_current()?;
+                    replies.push(self.collection_to_message(doc));
+                }
+
+                let total = collection.count_documents(doc! {"reply_to": oid}, None).await? as i64;
+                let pages = (total + limit - 1) / limit;
+
+                Ok(ThreadResponse {
+                    root: self.collection_to_message(root),
+                    replies: PaginationResponse {
+                        data: replies,
+                        curr_page: page,
+                        total_pages: pages,
+                        total_records: total,
+                        next_page: if page < pages { Some(page + 1) } else { None },
+                        prev_page: if page > 1 { Some(page - 1) } else { None },
+                    },
+                })
+            } else {
+                Err(MyError::OwnError(String::from("Messages collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn set_message_previews(&self, id: &str, previews: &[LinkPreview]) -> Result<()> {
+        observe_db("set_message_previews", async {
+            if let Some(collection) = &self.messages_collection {
+                let oid = parse_oid(id)?;
+                let previews = bson::to_bson(previews)?;
+                collection.update_one(
+                    doc! {"_id": oid},
+                    doc! {"$set": {"previews": previews, "updated_at": Utc::now()}},
+                    None,
+                ).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Store a private message queued for an offline receiver along with its `dm` notification, as one unit of work
+    pub async fn insert_queued_private_message(&self, message: PrivateMessageCollection, notification: NotificationCollection) -> Result<PrivateMessageCollection> {
+        observe_db("insert_queued_private_message", async {
+            let mut unit = self.begin().await?;
+            let res = async {
+                let message = self.insert_private_message(message, unit.session()).await?;
+                self.insert_notification(notification, unit.session()).await?;
+                Ok(message)
+            }.await;
+            unit.finish(res).await
+        }).await
+    }
+
+    pub async fn insert_private_message(&self, message_collection: PrivateMessageCollection, mut session: Option<&mut ClientSession>) -> Result<PrivateMessageCollection> {
+        observe_db("insert_private_message", async {
+            if let Some(collection) = &self.private_messages_collection {
+                let insert_res = match collection.insert_one_in(&message_collection, session.as_deref_mut()).await {
+                    Ok(res) => res,
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+
+                let verified = self.find_doc_by_oid(&self.private_messages_collection, oid, session).await?;
+
+                Ok(verified)
+            } else {
+                Err(MyError::OwnError(String::from("Private Messages collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_attachment(&self, attachment: AttachmentCollection) -> Result<AttachmentCollection> {
+        observe_db("insert_attachment", async {
+            if let Some(collection) = &self.attachments_collection {
+                let insert_res = collection.insert_one(&attachment, None).await?;
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+
+                self.find_doc_by_oid(&self.attachments_collection, oid, None).await
+            } else {
+                Err(MyError::OwnError(String::from("Attachments collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn find_attachment(&self, id: &str) -> Result<AttachmentCollection> {
+        observe_db("find_attachment", async {
+            if let Some(collection) = &self.attachments_collection {
+                let oid = parse_oid(id)?;
+                match collection.find_one(doc! {"_id": oid}, None).await? {
+                    Some(attachment) => Ok(attachment),
+                    None => Err(MyError::NotFoundError(id.to_owned()))
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Attachments collection not found")))
+            }
+        }).await
+    }
+
+    /// Bind the attachments referenced by a message to the room or the DM participants it was sent to <br/>
+    /// Only the uploader can reference an attachment and it can not be moved to another conversation once bound,
+    /// nor bound at all once it is a public avatar
+    pub async fn link_attachments(&self, ids: &[String], sender: &str, room: Option<String>, participants: Vec<String>) -> Result<()> {
+        observe_db("link_attachments", async {
+            if let Some(collection) = &self.attachments_collection {
+                for id in ids {
+                    let attachment = self.find_attachment(id).await?;
+                    if attachment.uploader.ne(sender) {
+                        return Err(MyError::ForbiddenError(format!("attachment {} was not uploaded by {}", id, sender)));
+                    }
+
+                    if attachment.public {
+                        return Err(MyError::ForbiddenError(format!("attachment {} is an avatar", id)));
+                    }
+                    let unbound = attachment.room.is_none() && attachment.participants.is_empty();
+                    let same_target = attachment.room.eq(&room) && participants.iter().all(|p| attachment.participants.contains(p));
+                    if !unbound && !same_target {
+                        return Err(MyError::ForbiddenError(format!("attachment {} is already bound to another conversation", id)));
+                    }
+
+                    collection.update_one(
+                        doc! {"_id": attachment.id},
+                        doc! {
+                            "$set": {"room": room.clone(), "updated_at": Utc::now()},
+                            "$addToSet": {"participants": {"$each": participants.clone()}}
+                        },
+                        None,
+                    ).await?;
+                }
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Attachments collection not found")))
+            }
+        }).await
+    }
+
+    /// Take the private messages queued while the receiver was offline, oldest first <br/>
+    /// Each message moves from `Queued` to `Sent` in the update reading it, so a message is flushed to a single socket
+    /// even when several sockets of the receiver send `user_handle` together
+    pub async fn take_queued_private_messages(&self, receiver: &str) -> Result<Vec<PrivateMessageCollection>> {
+        observe_db("take_queued_private_messages", async {
+            if let Some(collection) = &self.private_messages_collection {
+                let options = FindOneAndUpdateOptions::builder()
+                    .sort(doc! {"created_at": 1, "_id": 1})
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let filter = doc! {"receiver": receiver, "status": bson::to_bson(&DeliveryStatus::Queued)?};
+                let update = doc! {"$set": {"status": bson::to_bson(&DeliveryStatus::Sent)?, "updated_at": Utc::now()}};
+
+                let mut messages: Vec<PrivateMessageCollection> = Vec::new();
+                while let Some(message) = collection.find_one_and_update(filter.clone(), update.clone(), options.clone()).await? {
+                    messages.push(message);
+                }
+                Ok(messages)
+            } else {
+                Err(MyError::OwnError(String::from("Private Messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Mark the messages of the receiver as delivered, returning the ones that were still pending <br/>
+    /// Each message is read by the update moving it to `Delivered`, so a concurrent confirmation does not report it twice
+    pub async fn mark_private_messages_delivered(&self, receiver: &str, ids: &[String]) -> Result<Vec<PrivateMessageCollection>> {
+        observe_db("mark_private_messages_delivered", async {
+            if let Some(collection) = &self.private_messages_collection {
+                let oids = ids.iter().map(|id| parse_oid(id)).collect::<Result<Vec<ObjectId>>>()?;
+                let status = bson::to_bson(&[DeliveryStatus::Sent, DeliveryStatus::Queued])?;
+                let delivered = bson::to_bson(&DeliveryStatus::Delivered)?;
+
+                let mut pending: Vec<PrivateMessageCollection> = Vec::new();
+                for oid in oids {
+                    let filter = doc! {"_id": oid, "receiver": receiver, "status": {"$in": status.clone()}};
+                    let update = doc! {"$set": {"status": delivered.clone(), "updated_at": Utc::now()}};
+                    if let Some(message) = collection.find_one_and_update(filter, update, None).await? {
+                        pending.push(message);
+                    }
+                }
+                Ok(pending)
+            } else {
+                Err(MyError::OwnError(String::from("Private Messages collection not found")))
+            }
+        }).await
     }
 
     pub async fn find_message_oid(&self, oid: ObjectId) -> Result<MessageCollection> {
-        self.find_doc_by_oid(&self.messages_collection, oid, None).await
+        observe_db("find_message_oid", async {
+            self.find_doc_by_oid(&self.messages_collection, oid, None).await
+        }).await
     }
     pub async fn find_private_message_oid(&self, oid: ObjectId) -> Result<PrivateMessageCollection> {
-        self.find_doc_by_oid(&self.private_messages_collection, oid, None).await
+        observe_db("find_private_message_oid", async {
+            self.find_doc_by_oid(&self.private_messages_collection, oid, None).await
+        }).await
     }
 
     pub async fn find_doc_by_oid<T>(&self, collection: &Option<Collection<T>>, oid: ObjectId, session: Option<&mut ClientSession>) -> Result<T>
@@ -231,313 +766,1722 @@ impl DB {
     /// Create a DB entry for socket id mapped to name
     ///
     pub async fn insert_socket_name(&self, username: String, socket: String) -> Result<SocketCollection> {
-        if let Some(collection) = &self.sockets_collection {
-            let doc = SocketCollection {
-                id: ObjectId::new(),
-                socket,
-                username,
-                created_at: chrono::Utc::now(),
-                updated_at: chrono::Utc::now(),
-            };
-            let insert_res = match collection.insert_one(&doc, None).await {
-                Ok(res) => res,
-                Err(e) => return Err(MyError::MongoError(e))
-            };
+        observe_db("insert_socket_name", async {
+            if let Some(collection) = &self.sockets_collection {
+                let doc = SocketCollection {
+                    id: ObjectId::new(),
+                    socket,
+                    username,
+                    owned_username: None,
+                    session: None,
+                    focus: None,
+                    created_at: chrono::Utc::now(),
+                    updated_at: chrono::Utc::now(),
+                };
+                let insert_res = match collection.insert_one(&doc, None).await {
+                    Ok(res) => res,
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
 
-            let oid = insert_res.inserted_id.as_object_id().unwrap();
-            let resp = self.find_doc_by_oid(&self.sockets_collection, oid, None).await?;
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+                let resp = self.find_doc_by_oid(&self.sockets_collection, oid, None).await?;
 
-            Ok(resp)
-        } else {
-            Err(MyError::OwnError(String::from("Sockets collection not found")))
-        }
+                Ok(resp)
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
     }
 
     /// get the list of sockets
     pub async fn get_sockets(&self, limit: i64, page: i64) -> Result<PaginationResponse<SocketResponse>> {
-        if let Some(collection) = &self.sockets_collection {
-            let filter = FindOptions::builder()
-                .limit(limit)
-                .skip(u64::try_from((page - 1) * limit).unwrap())
-                .sort(doc! {"updated_at": -1, "created_at": -1})
-                .build();
-
-            let mut cursor = match collection.find(None, filter).await {
-                Ok(res) => res,
-                Err(e) => return Err(MyError::MongoError(e))
-            };
-
-            let mut sockets_list: Vec<SocketResponse> = Vec::new();
+        observe_db("get_sockets", async {
+            if let Some(collection) = &self.sockets_collection {
+                let filter = FindOptions::builder()
+                    .limit(limit)
+                    .skip(u64::try_from((page - 1) * limit).unwrap())
+                    .sort(doc! {"updated_at": -1, "created_at": -1})
+                    .build();
 
-            while cursor.advance().await? {
-                // let doc = cursor.current().to_owned().to_document().unwrap();
-                // let socket = bson::from_document::<SocketCollection>(doc).unwrap();
-                let socket = cursor.deserialize_current().unwrap();
-
-                // info!("Socket: {:?}", &socket);
-                sockets_list.push(SocketResponse {
-                    id: socket.id.to_string(),
-                    socket: socket.socket.to_string(),
-                    username: socket.username.to_string(),
-                    created_at: socket.created_at,
-                    updated_at: socket.updated_at,
-                });
-            }
+                let mut cursor = match collection.find(None, filter).await {
+                    Ok(res) => res,
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
 
-            let count_options = CountOptions::builder()
-                .skip(u64::try_from(((page - 1) + 1) * limit).unwrap())
-                .build();
-            let next = if collection.count_documents(None, count_options).await? as i64 > 1 {
-                Some(page + 1)
-            } else {
-                None
-            };
-            let prev = if page > 1 {
-                Some(page - 1)
-            } else {
-                None
-            };
-            let pages = collection.estimated_document_count(None).await.unwrap() as i64 / limit;
-            let total = collection.estimated_document_count(None).await.unwrap() as i64;
-
-            let response = PaginationResponse {
-                data: sockets_list,
-                curr_page: page,
-                next_page: next,
-                prev_page: prev,
-                total_pages: pages,
-                total_records: total,
-            };
-            Ok(response)
-        } else {
-            Err(MyError::OwnError(String::from("Sockets collection not found")))
-        }
-    }
+                let mut sockets_list: Vec<SocketResponse> = Vec::new();
 
-    /// This is the function to read, validate and insert the data in form of a non-transaction <br/>
-    /// Multiple re-rendering of the state in frontend or the operations in the function may cause multiple writes or retryable writing of data <br/>
-    /// Those kind of operations might not be supported by the MongoDB driver provided<br/>
-    pub async fn handle_user(&self, user: User) -> Result<UserCollection> {
-        if let Some(collection) = &self.users_collection {
+                while cursor.advance().await? {
+                    // let doc = cursor.current().to_owned().to_document().unwrap();
+                    // let socket = bson::from_document::<SocketCollection>(doc).unwrap();
+                    let socket = cursor.deserialize_current().unwrap();
 
-            // let mut session = collection.client().start_session(None).await?;
-            // session.start_transaction(None).await?;
-
-            let user = UserCollection {
-                id: bson::oid::ObjectId::new(),
-                owned_uname: user.username.clone(),
-                cur_gen_uname: user.generated_username.clone(),
-                last_username: "".to_string(),
-                online: true,
-                updated_at: chrono::Utc::now(),
-                created_at: chrono::Utc::now(),
-            };
+                    // info!("Socket: {:?}", &socket);
+                    sockets_list.push(SocketResponse {
+                        id: socket.id.to_string(),
+                        socket: socket.socket.to_string(),
+                        username: socket.username.to_string(),
+                        created_at: socket.created_at,
+                        updated_at: socket.updated_at,
+                    });
+                }
 
-            let found_res = collection.find_one(doc! {"owned_uname": user.owned_uname.clone()}, None).await.unwrap();
-            let final_res;
+                let count_options = CountOptions::builder()
+                    .skip(u64::try_from(((page - 1) + 1) * limit).unwrap())
+                    .build();
+                let next = if collection.count_documents(None, count_options).await? as i64 > 1 {
+                    Some(page + 1)
+                } else {
+                    None
+                };
+                let prev = if page > 1 {
+                    Some(page - 1)
+                } else {
+                    None
+                };
+                let pages = collection.estimated_document_count(None).await.unwrap() as i64 / limit;
+                let total = collection.estimated_document_count(None).await.unwrap() as i64;
 
-            if let Some(res) = found_res {
-                let last = res.cur_gen_uname.clone();
+                let response = PaginationResponse {
+                    data: sockets_list,
+                    curr_page: page,
+                    next_page: next,
+                    prev_page: prev,
+                    total_pages: pages,
+                    total_records: total,
+                };
+                Ok(response)
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
 
-                collection.update_one(doc! {
-                    "owned_uname": user.owned_uname.clone()
-                }, doc! {
+    /// Link the generated username of a connection to the owned username, creating the user on its first link <br/>
+    /// A single upsert on the unique `owned_uname` index, so concurrent links of a new name can not create two users.
+    /// The update is a pipeline: `last_username` is computed from the stored document inside the same write
+    pub async fn handle_user(&self, user: User, session: Option<&mut ClientSession>) -> Result<UserCollection> {
+        observe_db("handle_user", async {
+            if let Some(collection) = &self.users_collection {
+                let now = bson::DateTime::from_chrono(Utc::now());
+                let update = vec![doc! {
                     "$set": {
-                        "cur_gen_uname": user.cur_gen_uname.clone(),
-                        "online": user.online,
-                        "last_username": last,
-                        "updated_at": Utc::now()
+                        "owned_uname": user.username.clone(),
+                        "last_username": {"$ifNull": ["$cur_gen_uname", ""]},
+                        "cur_gen_uname": user.generated_username.clone(),
+                        "online": true,
+                        "blocked": {"$ifNull": ["$blocked", {"$literal": []}]},
+                        "muted": {"$ifNull": ["$muted", {"$literal": []}]},
+                        "muted_until": {"$ifNull": ["$muted_until", null]},
+                        "banned": {"$ifNull": ["$banned", false]},
+                        "updated_at": now,
+                        "created_at": {"$ifNull": ["$created_at", now]},
                     }
-                }, None).await?;
+                }];
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build();
 
-                final_res = self.find_doc_by_oid(&self.users_collection, res.id, None).await?;
-            } else {
-                let inserted = collection.insert_one(&user, None).await?;
+                let filter = doc! {"owned_uname": user.username.clone()};
+                let duplicate = |e: mongodb::error::Error| if is_duplicate_key(&e) { MyError::MongoDuplicateError(e) } else { MyError::MongoError(e) };
+                let res = match session {
+                    // an error aborts the transaction, the caller runs the whole unit again (see `link_user`)
+                    Some(session) => collection.find_one_and_update_in(filter, update, Some(options), Some(session)).await.map_err(duplicate)?,
+                    None => match collection.find_one_and_update(filter.clone(), update.clone(), options.clone()).await {
+                        // two upserts raced on a new name and this one lost, the user exists now so the retry updates it
+                        Err(e) if is_duplicate_key(&e) => collection.find_one_and_update(filter, update, options).await.map_err(duplicate)?,
+                        res => res?,
+                    }
+                };
 
-                final_res = self.find_doc_by_oid(&self.users_collection, inserted.inserted_id.as_object_id().unwrap(), None).await?;
+                res.ok_or(MyError::NotFoundError(user.username))
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
             }
+        }).await
+    }
 
-            // session.commit_transaction().await?;
-
-            Ok(final_res)
+    /// Link the owned username of `user`, make sure its private room exists and record the replaced generated username,
+    /// as one unit of work <br/>
+    /// A unit aborted by a concurrent link of the same name is run once more, it then finds the user in place
+    pub async fn link_user(&self, user: User) -> Result<UserCollection> {
+        observe_db("link_user", async {
+            let mut retried = false;
+            loop {
+                let mut unit = self.begin().await?;
+                let res = async {
+                    let linked = self.handle_user(user.clone(), unit.session()).await?;
+                    self.ensure_private_room(&linked.owned_uname, unit.session()).await?;
+                    // `last_username` only differs from `cur_gen_uname` when this link replaced it
+                    if !linked.last_username.is_empty() && linked.last_username.ne(&linked.cur_gen_uname) {
+                        self.insert_username_history(UsernameHistoryCollection {
+                            id: ObjectId::new(),
+                            user_id: linked.id,
+                            kind: UsernameKind::Generated,
+                            username: linked.last_username.clone(),
+                            replaced_by: linked.cur_gen_uname.clone(),
+                            redirect_until: None,
+                            created_at: Utc::now(),
+                        }, unit.session()).await?;
+                    }
+                    Ok(linked)
+                }.await;
+                match unit.finish(res).await {
+                    Err(e) if !retried && is_retryable(&e) => retried = true,
+                    res => return res
+                }
+            }
+        }).await
+    }
 
-            // let insert_res = match collection.insert_one(&user, None).await {
-            //     Ok(res) => res,
-            //     Err(e) => return Err(MyError::MongoError(e))
-            // };
-            // let oid = insert_res.inserted_id.as_object_id().unwrap();
-            //
-            // let verified = self.find_doc_by_oid(&self.users_collection, oid).await?;
-            //
-            // Ok(verified)
+    /// Create the private room of `username` when missing, an existing room is left as is
+    async fn ensure_private_room(&self, username: &str, session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.room_collection {
+            let room = RoomCollection {
+                id: ObjectId::new(),
+                room_name: None,
+                owned_username: username.to_string(),
+                updated_at: Utc::now(),
+                created_at: Utc::now(),
+            };
+            let filter = doc! {"owned_username": username};
+            let update = doc! {"$setOnInsert": bson::to_document(&room)?};
+            let options = UpdateOptions::builder().upsert(true).build();
+            collection.update_one_in(filter, update, Some(options), session).await?;
+            Ok(())
         } else {
-            Err(MyError::OwnError(String::from("Users collection not found")))
+            Err(MyError::OwnError(String::from("Room collection not found")))
         }
     }
 
-    pub async fn check_user_exists(&self, username: String) -> Result<Option<User>> {
-        if let Some(collection) = &self.users_collection {
-            collection.find_one(doc! {"owned_uname": username}, None).await
-                .map(|res| {
-                    match res {
-                        Some(doc) => {
-                            Some(User {
-                                username: doc.owned_uname,
-                                generated_username: doc.cur_gen_uname,
-                            })
-                        }
-                        None => None
-                    }
-                })
-                .map_err(MyError::from)
+    async fn insert_username_history(&self, entry: UsernameHistoryCollection, session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.username_history_collection {
+            collection.insert_one_in(&entry, session).await?;
+            Ok(())
         } else {
-            Err(MyError::OwnError(String::from("Users collection not found")))
+            Err(MyError::OwnError(String::from("Username history collection not found")))
         }
     }
-    pub async fn remove_socket(&self, user: User) {
-        if let Some(collection) = &self.sockets_collection {
-            let res = collection.delete_one(doc! {"username": user.generated_username}, None).await.unwrap();
-            info!("Removed: {:?}", res);
-        }
 
-        // mark the status as offline in users collection
-        if let Some(collection) = &self.users_collection {
-            collection.update_one(doc! {"owned_uname": user.username}, doc! {"$set": {"online": false, "updated_at": Utc::now()}}, None).await.unwrap();
-        }
+    /// Current owned username of `username`, following the redirect of a renamed user during its grace period
+    pub async fn resolve_username(&self, username: &str) -> Result<Option<String>> {
+        observe_db("resolve_username", async {
+            if let (Some(users), Some(history)) = (&self.users_collection, &self.username_history_collection) {
+                if users.find_one(doc! {"owned_uname": username}, None).await?.is_some() {
+                    return Ok(Some(username.to_string()));
+                }
+                match self.active_redirect(history, username, None).await? {
+                    Some(redirect) => Ok(users.find_one(doc! {"_id": redirect.user_id}, None).await?.map(|user| user.owned_uname)),
+                    None => Ok(None)
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
     }
 
-    pub async fn handle_private_joined(&self, user: InPrivate) -> Result<RoomCollection> {
-        if let Some(collection) = &self.users_collection {
-            match collection.find_one(doc! {"owned_uname": user.username.clone()}, None).await? {
-                Some(res) => {
-
-                    if let Some(collection) = &self.room_collection {
-                        match collection.find_one(doc! {"owned_username": res.owned_uname}, None).await? {
-                            Some(room) => {
-                                let res = collection.find_one_and_update(
-                                    doc! {
-                                        "owned_username": room.owned_username
-                                    },
-                                    doc! {
-                                        "$set": {
-                                            "in_private": true,
-                                            "updated_at": Utc::now()
-                                        }
-                                    }, None).await?;
-
-                                if let Some(res) = res {
-                                    Ok(res)
-                                } else {
-                                    Err(MyError::OwnError("Error while updating the room collection document".to_string()))
-                                }
-                            }
-                            None => {
-                                let room = RoomCollection {
-                                    id: ObjectId::new(),
-                                    room_name: None,
-                                    in_private: true,
-                                    owned_username: user.username.clone(),
-                                    updated_at: Utc::now(),
-                                    created_at: Utc::now(),
-                                };
-                                collection.insert_one(&room, None).await?;
-                                Ok(room)
-                            }
-                        }
-                    } else {
-                        Err(MyError::OwnError(String::from("Room collection not found")))
+    /// Latest redirect of the old owned username `username` still in its grace period
+    async fn active_redirect(&self, history: &Collection<UsernameHistoryCollection>, username: &str, session: Option<&mut ClientSession>) -> Result<Option<UsernameHistoryCollection>> {
+        let filter = doc! {
+            "username": username,
+            "kind": bson::to_bson(&UsernameKind::Owned)?,
+            "redirect_until": {"$gt": Utc::now()}
+        };
+        let options = FindOneOptions::builder().sort(doc! {"created_at": -1}).build();
+        Ok(history.find_one_in(filter, Some(options), session).await?)
+    }
+
+    /// Every username `username` went by, newest first
+    pub async fn username_history(&self, username: &str) -> Result<Vec<UsernameHistoryCollection>> {
+        observe_db("username_history", async {
+            if let (Some(users), Some(history)) = (&self.users_collection, &self.username_history_collection) {
+                let user = users.find_one(doc! {"owned_uname": username}, None).await?
+                    .ok_or(MyError::NotFoundError(username.to_string()))?;
+                let options = FindOptions::builder().sort(doc! {"created_at": -1}).build();
+                let mut cursor = history.find(doc! {"user_id": user.id}, options).await?;
+                let mut entries: Vec<UsernameHistoryCollection> = Vec::new();
+                while cursor.advance().await? {
+                    entries.push(cursor.deserialize_current()?);
+                }
+                Ok(entries)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// Rename the owned username `old` to `new` and move every reference to it, as one unit of work <br/>
+    /// The rename is refused until `cooldown` elapsed since the previous one, and `old` keeps redirecting to the user
+    /// for `grace`. The room messages keep the name they were sent with
+    pub async fn rename_user(&self, old: &str, new: &str, cooldown: chrono::Duration, grace: chrono::Duration) -> Result<UserCollection> {
+        observe_db("rename_user", async {
+            let (Some(users), Some(history)) = (&self.users_collection, &self.username_history_collection) else {
+                return Err(MyError::OwnError(String::from("Users collection not found")));
+            };
+            let mut unit = self.begin().await?;
+            let res = async {
+                let now = Utc::now();
+                let user = users.find_one_in(doc! {"owned_uname": old}, None, unit.session()).await?
+                    .ok_or(MyError::NotFoundError(old.to_string()))?;
+
+                if let Some(renamed_at) = user.renamed_at.map(|at| at.to_chrono()).filter(|at| *at + cooldown > now) {
+                    return Err(MyError::ConflictError(format!("the username can be changed again after {}", renamed_at + cooldown)));
+                }
+                if self.active_redirect(history, new, unit.session()).await?.is_some_and(|redirect| redirect.user_id.ne(&user.id)) {
+                    return Err(MyError::ConflictError(format!("username {} is taken", new)));
+                }
+
+                let filter = doc! {"_id": user.id, "owned_uname": old};
+                let update = doc! {"$set": {"owned_uname": new, "renamed_at": now, "updated_at": now}};
+                let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+                let renamed = match users.find_one_and_update_in(filter, update, Some(options), unit.session()).await {
+                    Ok(renamed) => renamed.ok_or(MyError::NotFoundError(old.to_string()))?,
+                    Err(e) if is_duplicate_key(&e) => return Err(MyError::ConflictError(format!("username {} is taken", new))),
+                    Err(e) => return Err(MyError::MongoError(e))
+                };
+
+                // taking back an own old name ends its redirect
+                let filter = doc! {"user_id": user.id, "username": new, "redirect_until": {"$gt": now}};
+                history.update_many_in(filter, doc! {"$set": {"redirect_until": now}}, unit.session()).await?;
+                self.insert_username_history(UsernameHistoryCollection {
+                    id: ObjectId::new(),
+                    user_id: user.id,
+                    kind: UsernameKind::Owned,
+                    username: old.to_string(),
+                    replaced_by: new.to_string(),
+                    redirect_until: Some(bson::DateTime::from_chrono(now + grace)),
+                    created_at: now,
+                }, unit.session()).await?;
+
+                rename_references(&self.room_collection, "owned_username", "owned_username", old, new, unit.session()).await?;
+                rename_references(&self.private_messages_collection, "sender", "sender", old, new, unit.session()).await?;
+                rename_references(&self.private_messages_collection, "receiver", "receiver", old, new, unit.session()).await?;
+                rename_references(&self.room_members_collection, "username", "username", old, new, unit.session()).await?;
+                rename_references(&self.mentions_collection, "username", "username", old, new, unit.session()).await?;
+                rename_references(&self.notifications_collection, "username", "username", old, new, unit.session()).await?;
+                rename_references(&self.attachments_collection, "uploader", "uploader", old, new, unit.session()).await?;
+                rename_references(&self.attachments_collection, "participants", "participants.$", old, new, unit.session()).await?;
+                rename_references(&self.users_collection, "blocked", "blocked.$", old, new, unit.session()).await?;
+                rename_references(&self.users_collection, "muted", "muted.$", old, new, unit.session()).await?;
+                self.rename_contacts(old, new, unit.session()).await?;
+                self.rename_group_dms(old, new, unit.session()).await?;
+                rename_references(&self.group_messages_collection, "sender", "sender", old, new, unit.session()).await?;
+                rename_references(&self.group_messages_collection, "pending", "pending.$", old, new, unit.session()).await?;
+                rename_references(&self.sockets_collection, "owned_username", "owned_username", old, new, unit.session()).await?;
+                self.rename_focus(old, new, unit.session()).await?;
+                // the moderators act on `reported_user`, a stale name would leave the reported user out of reach
+                rename_references(&self.reports_collection, "reporter", "reporter", old, new, unit.session()).await?;
+                rename_references(&self.reports_collection, "reported_user", "reported_user", old, new, unit.session()).await?;
+                rename_targets(&self.reports_collection, bson::to_bson(&ReportKind::User)?, old, new, unit.session()).await?;
+                rename_references(&self.held_messages_collection, "sender", "sender", old, new, unit.session()).await?;
+                rename_targets(&self.held_messages_collection, bson::to_bson(&MessageKind::Private)?, old, new, unit.session()).await?;
+                rename_references(&self.moderation_audit_collection, "sender", "sender", old, new, unit.session()).await?;
+                rename_targets(&self.moderation_audit_collection, bson::to_bson(&MessageKind::Private)?, old, new, unit.session()).await?;
+                Ok(renamed)
+            }.await;
+            unit.finish(res).await
+        }).await
+    }
+
+    pub async fn find_user(&self, username: &str) -> Result<UserCollection> {
+        observe_db("find_user", async {
+            if let Some(collection) = &self.users_collection {
+                collection.find_one(doc! {"owned_uname": username}, None).await?
+                    .ok_or(MyError::NotFoundError(username.to_string()))
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// Apply the `patch` validated by `SocketState::update_profile` to the profile of `username`, as one unit of work
+    /// along with opening a new avatar to everyone <br/>
+    /// The avatar must be an image uploaded by the user and not attached to any message, so making it public
+    /// never exposes the files of a conversation
+    pub async fn update_profile(&self, username: &str, patch: &ProfilePatch) -> Result<UserCollection> {
+        observe_db("update_profile", async {
+            let (Some(users), Some(attachments)) = (&self.users_collection, &self.attachments_collection) else {
+                return Err(MyError::OwnError(String::from("Users collection not found")));
+            };
+            // an empty string clears the field
+            let text = |value: &String| if value.trim().is_empty() { Bson::Null } else { Bson::String(value.trim().to_string()) };
+            let avatar = match patch.avatar.as_deref().map(str::trim) {
+                Some("") => Some(None),
+                Some(id) => Some(Some(parse_oid(id)?)),
+                None => None
+            };
+
+            let mut update = doc! {"updated_at": Utc::now()};
+            if let Some(display_name) = &patch.display_name {
+                update.insert("profile.display_name", text(display_name));
+            }
+            if let Some(bio) = &patch.bio {
+                update.insert("profile.bio", text(bio));
+            }
+            if let Some(avatar) = avatar {
+                update.insert("profile.avatar", avatar);
+            }
+            if let Some(status_text) = &patch.status_text {
+                update.insert("profile.status_text", text(status_text));
+                update.insert("profile.status_until", patch.status_until.map(bson::DateTime::from_chrono));
+            } else if let Some(status_until) = patch.status_until {
+                update.insert("profile.status_until", bson::DateTime::from_chrono(status_until));
+            }
+            if let Some(timezone) = &patch.timezone {
+                update.insert("profile.timezone", text(timezone));
+            }
+
+            let mut unit = self.begin().await?;
+            let res = async {
+                if let Some(Some(avatar)) = avatar {
+                    let filter = doc! {
+                        "_id": avatar,
+                        "uploader": username,
+                        "room": Bson::Null,
+                        "participants": {"$size": 0},
+                        "content_type": {"$regex": "^image/"},
+                    };
+                    let update = doc! {"$set": {"public": true, "updated_at": Utc::now()}};
+                    let res = attachments.update_one_in(filter, update, None, unit.session()).await?;
+                    if res.matched_count == 0 {
+                        return Err(MyError::BadRequestError(format!("avatar {} must be an image uploaded by {} and not attached to a message", avatar, username)));
                     }
                 }
-                None => {
-                    Err(MyError::OwnError("User not found".to_string()))
+
+                let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+                let filter = doc! {"owned_uname": username};
+                let user = users.find_one_and_update_in(filter, doc! {"$set": update}, Some(options), unit.session()).await?
+                    .ok_or(MyError::NotFoundError(username.to_string()))?;
+                Ok(user)
+            }.await;
+            unit.finish(res).await
+        }).await
+    }
+
+    /// Profiles of the existing users among `usernames`, by owned username
+    pub async fn profiles(&self, usernames: Vec<String>) -> Result<HashMap<String, UserProfile>> {
+        observe_db("profiles", async {
+            if let Some(collection) = &self.users_collection {
+                let mut cursor = collection.find(doc! {"owned_uname": {"$in": usernames}}, None).await?;
+                let mut profiles: HashMap<String, UserProfile> = HashMap::new();
+                while cursor.advance().await? {
+                    let user = cursor.deserialize_current()?;
+                    profiles.insert(user.owned_uname, user.profile);
                 }
+                Ok(profiles)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
             }
-        } else {
-            Err(MyError::OwnError(String::from("Users collection not found")))
-        }
+        }).await
     }
-    pub async fn handle_private_left(&self, user: InPrivate) -> Result<RoomCollection> {
-        if let Some(collection) = &self.room_collection {
-            match collection.find_one(doc! {"owned_username": user.username.clone()}, None).await? {
-                Some(room) => {
-                    let res = collection.find_one_and_update(
-                        doc! {
-                            "owned_username": room.owned_username
-                        },
-                        doc! {
-                            "$set": {
-                                "in_private": false,
-                                "updated_at": Utc::now()
-                            }
-                        }, None).await?;
-                    if let Some(res) = res {
-                        Ok(res)
-                    } else {
-                        Err(MyError::OwnError("Error while updating the room collection document".to_string()))
+
+    /// Send a friend request from `requester` to `addressee`, a pending request the other way is accepted instead
+    pub async fn send_contact_request(&self, requester: &str, addressee: &str) -> Result<ContactCollection> {
+        observe_db("send_contact_request", async {
+            if let Some(collection) = &self.contacts_collection {
+                let key = ContactCollection::key(requester, addressee);
+                match collection.find_one(doc! {"key": key.clone()}, None).await? {
+                    Some(contact) if contact.status == ContactStatus::Accepted => {
+                        Err(MyError::ConflictError(format!("{} is already a contact", addressee)))
+                    }
+                    Some(contact) if contact.requester.eq(requester) => {
+                        Err(MyError::ConflictError(format!("a request to {} is already pending", addressee)))
+                    }
+                    Some(_) => self.respond_contact_request(requester, addressee, true).await,
+                    None => {
+                        let mut users = vec![requester.to_string(), addressee.to_string()];
+                        users.sort();
+                        let contact = ContactCollection {
+                            id: ObjectId::new(),
+                            key,
+                            users,
+                            requester: requester.to_string(),
+                            addressee: addressee.to_string(),
+                            status: ContactStatus::Pending,
+                            updated_at: Utc::now(),
+                            created_at: Utc::now(),
+                        };
+                        match collection.insert_one(&contact, None).await {
+                            Ok(_) => Ok(contact),
+                            // both users sent a request at the same time
+                            Err(e) if is_duplicate_key(&e) => Err(MyError::ConflictError(format!("a request with {} is already pending", addressee))),
+                            Err(e) => Err(MyError::MongoError(e))
+                        }
                     }
                 }
-                None => {
-                    Err(MyError::OwnError("The provided document does not exist in room collection.".to_string()))
+            } else {
+                Err(MyError::OwnError(String::from("Contacts collection not found")))
+            }
+        }).await
+    }
+
+    /// Accept or decline the pending request `requester` sent to `addressee`, a declined request is deleted
+    pub async fn respond_contact_request(&self, addressee: &str, requester: &str, accept: bool) -> Result<ContactCollection> {
+        observe_db("respond_contact_request", async {
+            if let Some(collection) = &self.contacts_collection {
+                let filter = doc! {
+                    "key": ContactCollection::key(addressee, requester),
+                    "addressee": addressee,
+                    "status": bson::to_bson(&ContactStatus::Pending)?
+                };
+                let contact = if accept {
+                    let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+                    let update = doc! {"$set": {"status": bson::to_bson(&ContactStatus::Accepted)?, "updated_at": Utc::now()}};
+                    collection.find_one_and_update(filter, update, options).await?
+                } else {
+                    collection.find_one_and_delete(filter, None).await?
+                };
+                contact.ok_or(MyError::NotFoundError(format!("request from {}", requester)))
+            } else {
+                Err(MyError::OwnError(String::from("Contacts collection not found")))
+            }
+        }).await
+    }
+
+    /// Delete the pair of `username` and `other`, either a request `username` sent or an accepted contact
+    pub async fn delete_contact(&self, username: &str, other: &str, status: ContactStatus) -> Result<ContactCollection> {
+        observe_db("delete_contact", async {
+            if let Some(collection) = &self.contacts_collection {
+                let mut filter = doc! {"key": ContactCollection::key(username, other), "status": bson::to_bson(&status)?};
+                if status == ContactStatus::Pending {
+                    filter.insert("requester", username);
                 }
+                collection.find_one_and_delete(filter, None).await?
+                    .ok_or(MyError::NotFoundError(other.to_string()))
+            } else {
+                Err(MyError::OwnError(String::from("Contacts collection not found")))
+            }
+        }).await
+    }
+
+    /// Contacts and pending requests of `username`, most recently updated first
+    pub async fn contacts(&self, username: &str) -> Result<Vec<ContactCollection>> {
+        observe_db("contacts", async {
+            if let Some(collection) = &self.contacts_collection {
+                let options = FindOptions::builder().sort(doc! {"updated_at": -1}).build();
+                let mut cursor = collection.find(doc! {"users": username}, options).await?;
+                let mut contacts: Vec<ContactCollection> = Vec::new();
+                while cursor.advance().await? {
+                    contacts.push(cursor.deserialize_current()?);
+                }
+                Ok(contacts)
+            } else {
+                Err(MyError::OwnError(String::from("Contacts collection not found")))
+            }
+        }).await
+    }
+
+    /// Whether `sender` may send a private message to `receiver` under the DM policy of the receiver
+    pub async fn dm_allowed(&self, receiver: &str, sender: &str) -> Result<bool> {
+        observe_db("dm_allowed", async {
+            if let (Some(users), Some(contacts)) = (&self.users_collection, &self.contacts_collection) {
+                let policy = users.find_one(doc! {"owned_uname": receiver}, None).await?
+                    .map(|user| user.dm_policy)
+                    .unwrap_or_default();
+                if policy == DmPolicy::Everyone {
+                    return Ok(true);
+                }
+                let filter = doc! {"key": ContactCollection::key(receiver, sender), "status": bson::to_bson(&ContactStatus::Accepted)?};
+                Ok(contacts.count_documents(filter, None).await? > 0)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn set_dm_policy(&self, username: &str, policy: DmPolicy) -> Result<DmPolicy> {
+        observe_db("set_dm_policy", async {
+            if let Some(collection) = &self.users_collection {
+                let update = doc! {"$set": {"dm_policy": bson::to_bson(&policy)?, "updated_at": Utc::now()}};
+                let res = collection.update_one(doc! {"owned_uname": username}, update, None).await?;
+                if res.matched_count == 0 {
+                    return Err(MyError::NotFoundError(username.to_string()));
+                }
+                Ok(policy)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// Move the pairs of `old` to `new`, the `key` depends on the order of the names so each pair is rewritten
+    async fn rename_contacts(&self, old: &str, new: &str, mut session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.contacts_collection {
+            let contacts = collection.find_all_in(doc! {"users": old}, session.as_deref_mut()).await?;
+            for contact in contacts {
+                let other = contact.other(old);
+                let mut users = vec![new.to_string(), other.clone()];
+                users.sort();
+                let rename = |name: &String| if name.eq(old) { new.to_string() } else { name.clone() };
+                let update = doc! {"$set": {
+                    "key": ContactCollection::key(new, &other),
+                    "users": users,
+                    "requester": rename(&contact.requester),
+                    "addressee": rename(&contact.addressee),
+                }};
+                collection.update_one_in(doc! {"_id": contact.id}, update, None, session.as_deref_mut()).await?;
             }
-        } else {
-            Err(MyError::OwnError(String::from("Room collection not found")))
         }
+        Ok(())
     }
-    pub async fn check_private_exists(&self, user: User ) -> Result<RoomCollection> {
-        if let Some(collection) = &self.room_collection {
-            match collection.find_one(doc! {"owned_username": user.username.clone()}, None).await? {
-                Some(room) => {
-                    Ok(room)
+
+    /// Group DM of the sorted `participants`, created on the first call
+    pub async fn create_group_dm(&self, creator: &str, participants: Vec<String>, name: Option<String>) -> Result<GroupDmCollection> {
+        observe_db("create_group_dm", async {
+            if let Some(collection) = &self.group_dms_collection {
+                let key = GroupDmCollection::key(&participants);
+                let group = GroupDmCollection {
+                    id: ObjectId::new(),
+                    key: key.clone(),
+                    participants,
+                    name,
+                    created_by: creator.to_string(),
+                    updated_at: Utc::now(),
+                    created_at: Utc::now(),
+                };
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let update = doc! {"$setOnInsert": bson::to_document(&group)?};
+                let res = match collection.find_one_and_update(doc! {"key": key.clone()}, update, options).await {
+                    // the same group was created concurrently, it exists now
+                    Err(e) if is_duplicate_key(&e) => collection.find_one(doc! {"key": key}, None).await?,
+                    res => res?,
+                };
+                res.ok_or(MyError::NotFoundError(group.id.to_hex()))
+            } else {
+                Err(MyError::OwnError(String::from("Group DMs collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn find_group_dm(&self, id: &str) -> Result<GroupDmCollection> {
+        observe_db("find_group_dm", async {
+            let oid = parse_oid(id)?;
+            self.find_doc_by_oid(&self.group_dms_collection, oid, None).await
+        }).await
+    }
+
+    /// Group DMs of `username`, the most recently active first
+    pub async fn group_dms(&self, username: &str) -> Result<Vec<GroupDmCollection>> {
+        observe_db("group_dms", async {
+            if let Some(collection) = &self.group_dms_collection {
+                let options = FindOptions::builder().sort(doc! {"updated_at": -1}).build();
+                let mut cursor = collection.find(doc! {"participants": username}, options).await?;
+                let mut groups: Vec<GroupDmCollection> = Vec::new();
+                while cursor.advance().await? {
+                    groups.push(cursor.deserialize_current()?);
+                }
+                Ok(groups)
+            } else {
+                Err(MyError::OwnError(String::from("Group DMs collection not found")))
+            }
+        }).await
+    }
+
+    /// Store a group message along with the `dm` notifications of the offline participants, as one unit of work
+    pub async fn insert_group_message(&self, message: GroupMessageCollection, notifications: Vec<NotificationCollection>) -> Result<GroupMessageCollection> {
+        observe_db("insert_group_message", async {
+            let (Some(messages), Some(groups)) = (&self.group_messages_collection, &self.group_dms_collection) else {
+                return Err(MyError::OwnError(String::from("Group messages collection not found")));
+            };
+            let mut unit = self.begin().await?;
+            let res = async {
+                let touch = doc! {"$set": {"updated_at": Utc::now()}};
+                messages.insert_one_in(&message, unit.session()).await?;
+                groups.update_one_in(doc! {"_id": message.group_id}, touch, None, unit.session()).await?;
+                for notification in notifications {
+                    self.insert_notification(notification, unit.session()).await?;
+                }
+                Ok(message)
+            }.await;
+            unit.finish(res).await
+        }).await
+    }
+
+    /// Group messages not yet confirmed by `username`, oldest first
+    pub async fn get_undelivered_group_messages(&self, username: &str) -> Result<Vec<GroupMessageCollection>> {
+        observe_db("get_undelivered_group_messages", async {
+            if let Some(collection) = &self.group_messages_collection {
+                let options = FindOptions::builder().sort(doc! {"created_at": 1, "_id": 1}).build();
+                let mut cursor = collection.find(doc! {"pending": username}, options).await?;
+                let mut messages: Vec<GroupMessageCollection> = Vec::new();
+                while cursor.advance().await? {
+                    messages.push(cursor.deserialize_current()?);
                 }
-                None => {
-                    Err(MyError::OwnError("the user has not initiated the private chats yet".to_string()))
+                Ok(messages)
+            } else {
+                Err(MyError::OwnError(String::from("Group messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Clear `username` from the queue of the messages, returning the ones it had not confirmed yet
+    pub async fn mark_group_messages_delivered(&self, username: &str, ids: &[String]) -> Result<Vec<GroupMessageCollection>> {
+        observe_db("mark_group_messages_delivered", async {
+            if let Some(collection) = &self.group_messages_collection {
+                let oids = ids.iter().map(|id| parse_oid(id)).collect::<Result<Vec<ObjectId>>>()?;
+                let filter = doc! {"_id": {"$in": oids}, "pending": username};
+
+                let mut cursor = collection.find(filter.clone(), None).await?;
+                let mut pending: Vec<GroupMessageCollection> = Vec::new();
+                while cursor.advance().await? {
+                    pending.push(cursor.deserialize_current()?);
                 }
+
+                collection.update_many(filter, doc! {"$pull": {"pending": username}, "$set": {"updated_at": Utc::now()}}, None).await?;
+                Ok(pending)
+            } else {
+                Err(MyError::OwnError(String::from("Group messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Page of the messages of the group, newest first, without the messages of the `hidden` senders
+    pub async fn get_group_messages(&self, group_id: ObjectId, hidden: &[String], limit: i64, page: i64) -> Result<PaginationResponse<GroupMessageCollection>> {
+        observe_db("get_group_messages", async {
+            if let Some(collection) = &self.group_messages_collection {
+                let filter = doc! {"group_id": group_id, "sender": {"$nin": hidden}};
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": -1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(filter.clone(), find_options).await?;
+                let mut messages: Vec<GroupMessageCollection> = Vec::new();
+                while cursor.advance().await? {
+                    messages.push(cursor.deserialize_current()?);
+                }
+
+                let total = collection.count_documents(filter, None).await? as i64;
+                let pages = (total + limit - 1) / limit;
+
+                Ok(PaginationResponse {
+                    data: messages,
+                    curr_page: page,
+                    total_pages: pages,
+                    total_records: total,
+                    next_page: if page < pages { Some(page + 1) } else { None },
+                    prev_page: if page > 1 { Some(page - 1) } else { None },
+                })
+            } else {
+                Err(MyError::OwnError(String::from("Group messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Point the sockets viewing the DM with `old` to `new`
+    async fn rename_focus(&self, old: &str, new: &str, session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.sockets_collection {
+            let filter = doc! {"focus.kind": "dm", "focus.target": old};
+            collection.update_many_in(filter, doc! {"$set": {"focus.target": new}}, session).await?;
+        }
+        Ok(())
+    }
+
+    /// Move the group DMs of `old` to `new`, the `key` depends on the order of the names so each group is rewritten
+    async fn rename_group_dms(&self, old: &str, new: &str, mut session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.group_dms_collection {
+            let groups = collection.find_all_in(doc! {"participants": old}, session.as_deref_mut()).await?;
+            for group in groups {
+                let mut participants = group.participants.iter()
+                    .map(|participant| if participant.eq(old) { new.to_string() } else { participant.clone() })
+                    .collect::<Vec<String>>();
+                participants.sort();
+                let created_by = if group.created_by.eq(old) { new.to_string() } else { group.created_by };
+                let update = doc! {"$set": {
+                    "key": GroupDmCollection::key(&participants),
+                    "participants": participants,
+                    "created_by": created_by,
+                }};
+                collection.update_one_in(doc! {"_id": group.id}, update, None, session.as_deref_mut()).await?;
             }
-        } else {
-            Err(MyError::OwnError(String::from("Room collection not found")))
         }
+        Ok(())
+    }
+
+    /// Owned usernames `username` exchanged private messages with
+    pub async fn dm_contacts(&self, username: &str) -> Result<Vec<String>> {
+        observe_db("dm_contacts", async {
+            if let Some(collection) = &self.private_messages_collection {
+                let mut contacts = collection.distinct("receiver", doc! {"sender": username}, None).await?;
+                contacts.extend(collection.distinct("sender", doc! {"receiver": username}, None).await?);
+                let mut contacts = contacts.into_iter()
+                    .filter_map(|contact| contact.as_str().map(String::from))
+                    .filter(|contact| contact.ne(username))
+                    .collect::<Vec<String>>();
+                contacts.sort();
+                contacts.dedup();
+                Ok(contacts)
+            } else {
+                Err(MyError::OwnError(String::from("Private messages collection not found")))
+            }
+        }).await
     }
 
-    pub async fn test_transaction(&self) -> Result<User> {
+    pub async fn check_user_exists(&self, username: String) -> Result<Option<User>> {
+        observe_db("check_user_exists", async {
+            if let Some(collection) = &self.users_collection {
+                collection.find_one(doc! {"owned_uname": username}, None).await
+                    .map(|res| {
+                        match res {
+                            Some(doc) => {
+                                Some(User {
+                                    username: doc.owned_uname,
+                                    generated_username: doc.cur_gen_uname,
+                                })
+                            }
+                            None => None
+                        }
+                    })
+                    .map_err(MyError::from)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+    pub async fn remove_socket(&self, user: User) {
+        if let Some(collection) = &self.sockets_collection {
+            let res = collection.delete_one(doc! {"username": user.generated_username}, None).await.unwrap();
+            info!("Removed: {:?}", res);
+        }
+
+        // mark the status as offline in users collection
         if let Some(collection) = &self.users_collection {
-            let mut session = collection.client().start_session(None).await?;
-            session.start_transaction(None).await?;
-
-            let user = UserCollection {
-                id: bson::oid::ObjectId::new(),
-                owned_uname: "test".to_string(),
-                cur_gen_uname: "test".to_string(),
-                last_username: "".to_string(),
-                online: true,
-                updated_at: chrono::Utc::now(),
-                created_at: chrono::Utc::now(),
+            collection.update_one(doc! {"owned_uname": user.username}, doc! {"$set": {"online": false, "updated_at": Utc::now()}}, None).await.unwrap();
+        }
+    }
+
+    /// Record the owned username linked to the socket, making its focus visible to the other instances, and the digest
+    /// of the session token issued to it
+    pub async fn link_socket(&self, socket: &str, owned_username: &str, session: &str) -> Result<()> {
+        observe_db("link_socket", async {
+            if let Some(collection) = &self.sockets_collection {
+                collection.update_one(
+                    doc! {"socket": socket},
+                    doc! {"$set": {"owned_username": owned_username, "session": session, "updated_at": Utc::now()}},
+                    None,
+                ).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Owned username of the socket holding the session, `None` once the token was revoked with `end_socket_session`
+    /// or when the instance holding the socket stopped refreshing it
+    pub async fn session_user(&self, session: &str) -> Result<Option<String>> {
+        observe_db("session_user", async {
+            if let Some(collection) = &self.sockets_collection {
+                let filter = doc! {"session": session, "owned_username": {"$type": "string"}, "updated_at": fresh_socket()};
+                let socket = collection.find_one(filter, None).await?;
+                Ok(socket.and_then(|socket| socket.owned_username))
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Revoke the session token of the socket, on disconnect
+    pub async fn end_socket_session(&self, socket: &str) -> Result<()> {
+        observe_db("end_socket_session", async {
+            if let Some(collection) = &self.sockets_collection {
+                collection.update_one(doc! {"socket": socket}, doc! {"$unset": {"session": ""}}, None).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Refresh `updated_at` of the sockets held by this instance, see `SOCKET_HEARTBEAT`
+    pub async fn touch_sockets(&self, sockets: &[String]) -> Result<u64> {
+        observe_db("touch_sockets", async {
+            if let Some(collection) = &self.sockets_collection {
+                let res = collection.update_many(doc! {"socket": {"$in": sockets}}, doc! {"$set": {"updated_at": Utc::now()}}, None).await?;
+                Ok(res.modified_count)
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Set the conversation the socket is viewing, `None` clears it
+    pub async fn set_socket_focus(&self, socket: &str, focus: Option<&Focus>) -> Result<()> {
+        observe_db("set_socket_focus", async {
+            if let Some(collection) = &self.sockets_collection {
+                let update = match focus {
+                    Some(focus) => doc! {"$set": {"focus": bson::to_bson(focus)?, "updated_at": Utc::now()}},
+                    None => doc! {"$unset": {"focus": ""}, "$set": {"updated_at": Utc::now()}}
+                };
+                collection.update_one(doc! {"socket": socket}, update, None).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Conversations viewed by the sockets of `username` on every instance, the stale sockets are left out
+    pub async fn user_focus(&self, username: &str) -> Result<Vec<Focus>> {
+        observe_db("user_focus", async {
+            if let Some(collection) = &self.sockets_collection {
+                let mut cursor = collection.find(doc! {"owned_username": username, "focus": {"$ne": null}, "updated_at": fresh_socket()}, None).await?;
+                let mut focus: Vec<Focus> = Vec::new();
+                while cursor.advance().await? {
+                    if let Some(viewed) = cursor.deserialize_current()?.focus {
+                        if !focus.contains(&viewed) {
+                            focus.push(viewed);
+                        }
+                    }
+                }
+                Ok(focus)
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Whether a live socket of `username`, on any instance, is viewing `focus`
+    pub async fn is_focused(&self, username: &str, focus: &Focus) -> Result<bool> {
+        observe_db("is_focused", async {
+            if let Some(collection) = &self.sockets_collection {
+                let options = CountOptions::builder().limit(1).build();
+                let filter = doc! {"owned_username": username, "focus": bson::to_bson(focus)?, "updated_at": fresh_socket()};
+                Ok(collection.count_documents(filter, options).await? > 0)
+            } else {
+                Err(MyError::OwnError(String::from("Sockets collection not found")))
+            }
+        }).await
+    }
+
+    /// Mark the unread notifications and mentions of `username` about the conversation as read <br/>
+    /// Returns the number of notifications and of mentions marked
+    pub async fn mark_conversation_read(&self, username: &str, focus: &Focus) -> Result<(u64, u64)> {
+        observe_db("mark_conversation_read", async {
+            let filter = match focus {
+                Focus::Room(room) => doc! {"kind": "mention", "reference": room},
+                Focus::Group(group) => doc! {"kind": "dm", "reference": group},
+                Focus::Dm(peer) => {
+                    // the notifications of the group DMs are `dm` ones as well, told apart by their reference
+                    let groups = self.group_dms(username).await?.into_iter().map(|group| group.id.to_hex()).collect::<Vec<String>>();
+                    doc! {"kind": "dm", "sender": peer, "reference": {"$nin": groups}}
+                }
             };
 
-            let inserted = collection.insert_one_with_session(&user, None, &mut session).await?;
-            let resp;
-            if let Some(oid) = inserted.inserted_id.as_object_id() {
-                if let Some(found) = collection.find_one_with_session(doc! {"_id": oid}, None, &mut session).await? {
-                    resp = User {
-                        username: found.owned_uname,
-                        generated_username: found.cur_gen_uname,
-                    };
+            let notifications = if let Some(collection) = &self.notifications_collection {
+                let mut filter = filter;
+                filter.insert("username", username);
+                filter.insert("read", false);
+                collection.update_many(filter, doc! {"$set": {"read": true, "updated_at": Utc::now()}}, None).await?.modified_count
+            } else {
+                return Err(MyError::OwnError(String::from("Notifications collection not found")));
+            };
+
+            let mentions = match (focus, &self.mentions_collection) {
+                (Focus::Room(room), Some(collection)) => collection.update_many(
+                    doc! {"username": username, "room": room, "read": false},
+                    doc! {"$set": {"read": true, "updated_at": Utc::now()}},
+                    None,
+                ).await?.modified_count,
+                (Focus::Room(_), None) => return Err(MyError::OwnError(String::from("Mentions collection not found"))),
+                _ => 0
+            };
+
+            Ok((notifications, mentions))
+        }).await
+    }
+
+    /// Record `username` as a member of `room`, the first member allowed to own it (`may_own`) becomes its owner <br/>
+    /// Generated usernames never own a room, they are gone on disconnect
+    pub async fn join_room_member(&self, room: &str, username: &str, may_own: bool) -> Result<RoomMemberCollection> {
+        observe_db("join_room_member", async {
+            if let Some(collection) = &self.room_members_collection {
+                if let Some(member) = collection.find_one(doc! {"room": room, "username": username}, None).await? {
+                    return Ok(member);
+                }
+
+                let owned = !may_own || collection.count_documents(doc! {"room": room, "role": bson::to_bson(&RoomRole::Owner)?}, None).await? > 0;
+                let mut member = RoomMemberCollection {
+                    id: ObjectId::new(),
+                    room: room.to_string(),
+                    username: username.to_string(),
+                    role: if owned { RoomRole::Member } else { RoomRole::Owner },
+                    updated_at: Utc::now(),
+                    created_at: Utc::now(),
+                };
+
+                loop {
+                    match collection.insert_one(&member, None).await {
+                        Ok(_) => return Ok(member),
+                        Err(e) if is_duplicate_key(&e) => {
+                            // joined concurrently from another socket, the other insert wins
+                            if let Some(existing) = collection.find_one(doc! {"room": room, "username": username}, None).await? {
+                                return Ok(existing);
+                            }
+                            // another user became the owner first, the unique index on the owners (migration 9) rejected this one
+                            if member.role != RoomRole::Owner {
+                                return Err(MyError::MongoDuplicateError(e));
+                            }
+                            member.role = RoomRole::Member;
+                        }
+                        Err(e) => return Err(MyError::MongoError(e))
+                    }
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Room members collection not found")))
+            }
+        }).await
+    }
+
+    /// Whether a socket joined `room` at least once, the room then routes messages under that name
+    pub async fn room_exists(&self, room: &str) -> Result<bool> {
+        observe_db("room_exists", async {
+            if let Some(collection) = &self.room_members_collection {
+                let options = CountOptions::builder().limit(1).build();
+                Ok(collection.count_documents(doc! {"room": room}, options).await? > 0)
+            } else {
+                Err(MyError::OwnError(String::from("Room members collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn room_member_role(&self, room: &str, username: &str) -> Result<Option<RoomRole>> {
+        observe_db("room_member_role", async {
+            if let Some(collection) = &self.room_members_collection {
+                let member = collection.find_one(doc! {"room": room, "username": username}, None).await?;
+                Ok(member.map(|member| member.role))
+            } else {
+                Err(MyError::OwnError(String::from("Room members collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn room_member_names(&self, room: &str) -> Result<Vec<String>> {
+        observe_db("room_member_names", async {
+            if let Some(collection) = &self.room_members_collection {
+                let mut cursor = collection.find(doc! {"room": room}, None).await?;
+                let mut names: Vec<String> = Vec::new();
+                while cursor.advance().await? {
+                    names.push(cursor.deserialize_current()?.username);
+                }
+                Ok(names)
+            } else {
+                Err(MyError::OwnError(String::from("Room members collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn set_room_role(&self, room: &str, username: &str, role: RoomRole) -> Result<RoomMemberCollection> {
+        observe_db("set_room_role", async {
+            if let Some(collection) = &self.room_members_collection {
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let role = bson::to_bson(&role)?;
+                let res = collection.find_one_and_update(
+                    doc! {"room": room, "username": username},
+                    doc! {"$set": {"role": role, "updated_at": Utc::now()}},
+                    options,
+                ).await?;
+
+                res.ok_or(MyError::NotFoundError(format!("{} in room {}", username, room)))
+            } else {
+                Err(MyError::OwnError(String::from("Room members collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_mentions(&self, mentions: &[MentionCollection]) -> Result<()> {
+        observe_db("insert_mentions", async {
+            if let Some(collection) = &self.mentions_collection {
+                if !mentions.is_empty() {
+                    collection.insert_many(mentions, None).await?;
+                }
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Mentions collection not found")))
+            }
+        }).await
+    }
+
+    /// Latest 50 mentions of the user, newest first
+    pub async fn get_mentions(&self, username: &str, unread_only: bool) -> Result<Vec<MentionCollection>> {
+        observe_db("get_mentions", async {
+            if let Some(collection) = &self.mentions_collection {
+                let filter = if unread_only {
+                    doc! {"username": username, "read": false}
                 } else {
-                    resp = User {
-                        username: "".to_string(),
-                        generated_username: "".to_string(),
-                    };
+                    doc! {"username": username}
+                };
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": -1})
+                    .limit(50)
+                    .build();
+
+                let mut cursor = collection.find(filter, find_options).await?;
+                let mut mentions: Vec<MentionCollection> = Vec::new();
+                while cursor.advance().await? {
+                    mentions.push(cursor.deserialize_current()?);
+                }
+                Ok(mentions)
+            } else {
+                Err(MyError::OwnError(String::from("Mentions collection not found")))
+            }
+        }).await
+    }
+
+    /// Mark the mentions as read, every unread mention of the user when `ids` is empty <br/>
+    /// Returns the number of updated mentions
+    pub async fn mark_mentions_read(&self, username: &str, ids: &[String]) -> Result<u64> {
+        observe_db("mark_mentions_read", async {
+            if let Some(collection) = &self.mentions_collection {
+                let mut filter = doc! {"username": username, "read": false};
+                if !ids.is_empty() {
+                    let oids = ids.iter().map(|id| parse_oid(id)).collect::<Result<Vec<ObjectId>>>()?;
+                    filter.insert("_id", doc! {"$in": oids});
                 }
 
-                collection.find_one_and_delete_with_session(doc! {"_id": oid}, None, &mut session).await?;
+                let res = collection.update_many(filter, doc! {"$set": {"read": true, "updated_at": Utc::now()}}, None).await?;
+                Ok(res.modified_count)
             } else {
-                return Err(MyError::OwnError(String::from("Error in inserting the user")));
+                Err(MyError::OwnError(String::from("Mentions collection not found")))
             }
+        }).await
+    }
 
-            session.commit_transaction().await?;
-            Ok(resp)
+    pub async fn insert_notification(&self, notification: NotificationCollection, mut session: Option<&mut ClientSession>) -> Result<NotificationCollection> {
+        observe_db("insert_notification", async {
+            if let Some(collection) = &self.notifications_collection {
+                let insert_res = collection.insert_one_in(&notification, session.as_deref_mut()).await?;
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+
+                self.find_doc_by_oid(&self.notifications_collection, oid, session).await
+            } else {
+                Err(MyError::OwnError(String::from("Notifications collection not found")))
+            }
+        }).await
+    }
+
+    /// Page of the inbox of the user, newest first
+    pub async fn get_notifications(&self, username: &str, unread_only: bool, limit: i64, page: i64) -> Result<Vec<NotificationCollection>> {
+        observe_db("get_notifications", async {
+            if let Some(collection) = &self.notifications_collection {
+                let filter = if unread_only {
+                    doc! {"username": username, "read": false}
+                } else {
+                    doc! {"username": username}
+                };
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": -1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(filter, find_options).await?;
+                let mut notifications: Vec<NotificationCollection> = Vec::new();
+                while cursor.advance().await? {
+                    notifications.push(cursor.deserialize_current()?);
+                }
+                Ok(notifications)
+            } else {
+                Err(MyError::OwnError(String::from("Notifications collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn count_unread_notifications(&self, username: &str) -> Result<u64> {
+        observe_db("count_unread_notifications", async {
+            if let Some(collection) = &self.notifications_collection {
+                Ok(collection.count_documents(doc! {"username": username, "read": false}, None).await?)
+            } else {
+                Err(MyError::OwnError(String::from("Notifications collection not found")))
+            }
+        }).await
+    }
+
+    /// Mark the notifications as read, every unread notification of the user when `ids` is empty <br/>
+    /// Returns the number of updated notifications
+    pub async fn mark_notifications_read(&self, username: &str, ids: &[String]) -> Result<u64> {
+        observe_db("mark_notifications_read", async {
+            if let Some(collection) = &self.notifications_collection {
+                let mut filter = doc! {"username": username, "read": false};
+                if !ids.is_empty() {
+                    let oids = ids.iter().map(|id| parse_oid(id)).collect::<Result<Vec<ObjectId>>>()?;
+                    filter.insert("_id", doc! {"$in": oids});
+                }
+
+                let res = collection.update_many(filter, doc! {"$set": {"read": true, "updated_at": Utc::now()}}, None).await?;
+                Ok(res.modified_count)
+            } else {
+                Err(MyError::OwnError(String::from("Notifications collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn get_block_list(&self, username: &str) -> Result<BlockList> {
+        observe_db("get_block_list", async {
+            if let Some(collection) = &self.users_collection {
+                match collection.find_one(doc! {"owned_uname": username}, None).await? {
+                    Some(user) => Ok(BlockList {
+                        username: user.owned_uname,
+                        blocked: user.blocked,
+                        muted: user.muted,
+                    }),
+                    None => Err(MyError::NotFoundError(username.to_string()))
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// Add or remove `target` from the `blocked` or `muted` list of the user
+    pub async fn update_block_list(&self, username: &str, target: &str, list: BlockListKind, add: bool) -> Result<BlockList> {
+        observe_db("update_block_list", async {
+            if let Some(collection) = &self.users_collection {
+                if username.eq(target) {
+                    return Err(MyError::BadRequestError("users can not block or mute themselves".to_string()));
+                }
+                let field = match list {
+                    BlockListKind::Blocked => "blocked",
+                    BlockListKind::Muted => "muted",
+                };
+                let operator = if add { "$addToSet" } else { "$pull" };
+
+                let res = collection.update_one(
+                    doc! {"owned_uname": username},
+                    doc! {operator: {field: target}, "$set": {"updated_at": Utc::now()}},
+                    None,
+                ).await?;
+                if res.matched_count == 0 {
+                    return Err(MyError::NotFoundError(username.to_string()));
+                }
+
+                self.get_block_list(username).await
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// How `username` treats the messages of `sender`
+    pub async fn user_relation(&self, username: &str, sender: &str) -> Result<UserRelation> {
+        observe_db("user_relation", async {
+            if let Some(collection) = &self.users_collection {
+                let user = collection.find_one(doc! {"owned_uname": username}, None).await?;
+                Ok(match user {
+                    Some(user) if user.blocked.iter().any(|b| b.eq(sender)) => UserRelation::Blocked,
+                    Some(user) if user.muted.iter().any(|m| m.eq(sender)) => UserRelation::Muted,
+                    _ => UserRelation::Normal,
+                })
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// The users among `candidates` who have `sender` in their `list` (blocked or muted)
+    pub async fn users_listing(&self, sender: &str, candidates: &[String], list: BlockListKind) -> Result<Vec<String>> {
+        observe_db("users_listing", async {
+            if let Some(collection) = &self.users_collection {
+                let field = match list {
+                    BlockListKind::Blocked => "blocked",
+                    BlockListKind::Muted => "muted",
+                };
+                let mut cursor = collection.find(doc! {"owned_uname": {"$in": candidates}, field: sender}, None).await?;
+                let mut users: Vec<String> = Vec::new();
+                while cursor.advance().await? {
+                    users.push(cursor.deserialize_current()?.owned_uname);
+                }
+                Ok(users)
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_moderation_audit(&self, audit: &ModerationAuditCollection) -> Result<()> {
+        observe_db("insert_moderation_audit", async {
+            if let Some(collection) = &self.moderation_audit_collection {
+                collection.insert_one(audit, None).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Moderation audit collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_held_message(&self, held: HeldMessageCollection) -> Result<HeldMessageCollection> {
+        observe_db("insert_held_message", async {
+            if let Some(collection) = &self.held_messages_collection {
+                let insert_res = collection.insert_one(&held, None).await?;
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+
+                self.find_doc_by_oid(&self.held_messages_collection, oid, None).await
+            } else {
+                Err(MyError::OwnError(String::from("Held messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Page of the held messages, oldest first, the pending ones when `status` is `None`
+    pub async fn get_held_messages(&self, status: Option<ReviewStatus>, limit: i64, page: i64) -> Result<Vec<HeldMessageCollection>> {
+        observe_db("get_held_messages", async {
+            if let Some(collection) = &self.held_messages_collection {
+                let filter = doc! {"status": bson::to_bson(&status.unwrap_or(ReviewStatus::Pending))?};
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": 1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(filter, find_options).await?;
+                let mut held: Vec<HeldMessageCollection> = Vec::new();
+                while cursor.advance().await? {
+                    held.push(cursor.deserialize_current()?);
+                }
+                Ok(held)
+            } else {
+                Err(MyError::OwnError(String::from("Held messages collection not found")))
+            }
+        }).await
+    }
+
+    /// Move the held message from `from` to `to` in the update reading it, so two moderators can not both review it <br/>
+    /// `reviewed_by` is set to `moderator`, or cleared when the message goes back to pending
+    pub async fn review_held_message(&self, id: &str, moderator: Option<&str>, from: ReviewStatus, to: ReviewStatus) -> Result<HeldMessageCollection> {
+        observe_db("review_held_message", async {
+            if let Some(collection) = &self.held_messages_collection {
+                let oid = parse_oid(id)?;
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let res = collection.find_one_and_update(
+                    doc! {"_id": oid, "status": bson::to_bson(&from)?},
+                    doc! {"$set": {"status": bson::to_bson(&to)?, "reviewed_by": moderator, "updated_at": Utc::now()}},
+                    options,
+                ).await?;
+
+                match res {
+                    Some(held) => Ok(held),
+                    None => match collection.find_one(doc! {"_id": oid}, None).await? {
+                        Some(held) => Err(MyError::ConflictError(format!("held message {} is already {:?}", id, held.status).to_lowercase())),
+                        None => Err(MyError::NotFoundError(id.to_string()))
+                    }
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Held messages collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_report(&self, report: ReportCollection) -> Result<ReportCollection> {
+        observe_db("insert_report", async {
+            if let Some(collection) = &self.reports_collection {
+                let insert_res = collection.insert_one(&report, None).await?;
+                let oid = insert_res.inserted_id.as_object_id().unwrap();
+
+                self.find_doc_by_oid(&self.reports_collection, oid, None).await
+            } else {
+                Err(MyError::OwnError(String::from("Reports collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn find_report(&self, id: &str) -> Result<ReportCollection> {
+        observe_db("find_report", async {
+            if let Some(collection) = &self.reports_collection {
+                let oid = parse_oid(id)?;
+                collection.find_one(doc! {"_id": oid}, None).await?
+                    .ok_or(MyError::NotFoundError(id.to_string()))
+            } else {
+                Err(MyError::OwnError(String::from("Reports collection not found")))
+            }
+        }).await
+    }
+
+    /// Page of the review queue, oldest first, every open and claimed report when `status` is `None`
+    pub async fn get_reports(&self, status: Option<ReportStatus>, limit: i64, page: i64) -> Result<Vec<ReportCollection>> {
+        observe_db("get_reports", async {
+            if let Some(collection) = &self.reports_collection {
+                let filter = match status {
+                    Some(status) => doc! {"status": bson::to_bson(&status)?},
+                    None => doc! {"status": {"$in": [bson::to_bson(&ReportStatus::Open)?, bson::to_bson(&ReportStatus::Claimed)?]}},
+                };
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": 1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(filter, find_options).await?;
+                let mut reports: Vec<ReportCollection> = Vec::new();
+                while cursor.advance().await? {
+                    reports.push(cursor.deserialize_current()?);
+                }
+                Ok(reports)
+            } else {
+                Err(MyError::OwnError(String::from("Reports collection not found")))
+            }
+        }).await
+    }
+
+    /// Assign an open report to the moderator, a report claimed by another moderator is a conflict
+    pub async fn claim_report(&self, id: &str, moderator: &str) -> Result<ReportCollection> {
+        observe_db("claim_report", async {
+            if let Some(collection) = &self.reports_collection {
+                let oid = parse_oid(id)?;
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let res = collection.find_one_and_update(
+                    doc! {
+                        "_id": oid,
+                        "$or": [
+                            {"status": bson::to_bson(&ReportStatus::Open)?},
+                            {"status": bson::to_bson(&ReportStatus::Claimed)?, "moderator": moderator},
+                        ]
+                    },
+                    doc! {"$set": {"status": bson::to_bson(&ReportStatus::Claimed)?, "moderator": moderator, "updated_at": Utc::now()}},
+                    options,
+                ).await?;
+
+                match res {
+                    Some(report) => Ok(report),
+                    None => {
+                        let report = self.find_report(id).await?;
+                        Err(MyError::ConflictError(format!("report {} is already {:?}", id, report.status).to_lowercase()))
+                    }
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Reports collection not found")))
+            }
+        }).await
+    }
+
+    /// Close a report which is open or claimed by the same moderator
+    pub async fn resolve_report(&self, id: &str, moderator: &str, action: ReportAction, note: Option<String>, session: Option<&mut ClientSession>) -> Result<ReportCollection> {
+        observe_db("resolve_report", async {
+            if let Some(collection) = &self.reports_collection {
+                let oid = parse_oid(id)?;
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let filter = doc! {
+                    "_id": oid,
+                    "$or": [
+                        {"status": bson::to_bson(&ReportStatus::Open)?},
+                        {"status": bson::to_bson(&ReportStatus::Claimed)?, "moderator": moderator},
+                    ]
+                };
+                let update = doc! {
+                    "$set": {
+                        "status": bson::to_bson(&ReportStatus::Resolved)?,
+                        "moderator": moderator,
+                        "action": bson::to_bson(&action)?,
+                        "note": note,
+                        "updated_at": Utc::now()
+                    }
+                };
+                match collection.find_one_and_update_in(filter, update, Some(options), session).await? {
+                    Some(report) => Ok(report),
+                    None => {
+                        let report = self.find_report(id).await?;
+                        Err(MyError::ConflictError(format!("report {} is {:?} by {}", id, report.status, report.moderator.unwrap_or_default()).to_lowercase()))
+                    }
+                }
+            } else {
+                Err(MyError::OwnError(String::from("Reports collection not found")))
+            }
+        }).await
+    }
+
+    /// Apply the `action` of the moderator, close the report and record it in the admin audit log, as one unit of work <br/>
+    /// The removed message, if any, is returned so the clients can be told once the unit is committed
+    pub async fn resolve_report_with_action(
+        &self,
+        id: &str,
+        moderator: &str,
+        action: ReportAction,
+        note: Option<String>,
+        mute_until: chrono::DateTime<Utc>,
+    ) -> Result<(ReportCollection, Option<RemovedMessage>)> {
+        observe_db("resolve_report_with_action", async {
+            let report = self.find_report(id).await?;
+            if action == ReportAction::DeleteMessage && report.kind == ReportKind::User {
+                return Err(MyError::BadRequestError("a user report has no message to delete".to_string()));
+            }
+            let taken = match report.status {
+                ReportStatus::Resolved => true,
+                ReportStatus::Claimed => report.moderator.as_deref() != Some(moderator),
+                ReportStatus::Open => false,
+            };
+            if taken {
+                return Err(MyError::ConflictError(format!("report {} is {:?} by {}", id, report.status, report.moderator.unwrap_or_default()).to_lowercase()));
+            }
+
+            let mut unit = self.begin().await?;
+            let res = async {
+                // the action goes first so that without transactions a failed action leaves the report open to be retried,
+                // a resolution racing in between still turns into a conflict in `resolve_report`
+                let removed = match (action, report.kind) {
+                    (ReportAction::DeleteMessage, ReportKind::Message) => {
+                        Some(RemovedMessage::Room(self.delete_message(&report.target, unit.session()).await?))
+                    }
+                    (ReportAction::DeleteMessage, ReportKind::Private) => {
+                        Some(RemovedMessage::Private(self.delete_private_message(&report.target, unit.session()).await?))
+                    }
+                    (ReportAction::DeleteMessage, ReportKind::User) => None,
+                    (ReportAction::Mute, _) => {
+                        self.mute_user(&report.reported_user, mute_until, unit.session()).await?;
+                        None
+                    }
+                    (ReportAction::Ban, _) => {
+                        self.ban_user(&report.reported_user, unit.session()).await?;
+                        None
+                    }
+                    (ReportAction::Dismiss, _) => None,
+                };
+                let report = self.resolve_report(id, moderator, action, note, unit.session()).await?;
+                let audit = AdminAuditCollection {
+                    id: ObjectId::new(),
+                    actor: moderator.to_string(),
+                    claimed_actor: None,
+                    action: "resolve_report".to_string(),
+                    target: Some(id.to_string()),
+                    details: Some(format!("{:?} on {}", action, report.reported_user)),
+                    created_at: Utc::now(),
+                };
+                self.insert_admin_audit(&audit, unit.session()).await?;
+                Ok((report, removed))
+            }.await;
+            unit.finish(res).await
+        }).await
+    }
+
+    /// Delete a room message, the replies go along with a thread root and the reply count of the root is kept in sync
+    pub async fn delete_message(&self, id: &str, mut session: Option<&mut ClientSession>) -> Result<MessageCollection> {
+        observe_db("delete_message", async {
+            if let Some(collection) = &self.messages_collection {
+                let oid = parse_oid(id)?;
+                let message = collection.find_one_and_delete_in(doc! {"_id": oid}, session.as_deref_mut()).await?
+                    .ok_or(MyError::NotFoundError(id.to_string()))?;
+
+                match message.reply_to {
+                    Some(root_id) => {
+                        let filter = doc! {"_id": root_id, "reply_count": {"$gt": 0_i64}};
+                        let update = doc! {"$inc": {"reply_count": -1_i64}, "$set": {"updated_at": Utc::now()}};
+                        collection.update_one_in(filter, update, None, session).await?;
+                    }
+                    None => {
+                        collection.delete_many_in(doc! {"reply_to": oid}, session).await?;
+                    }
+                }
+                Ok(message)
+            } else {
+                Err(MyError::OwnError(String::from("Messages collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn delete_private_message(&self, id: &str, session: Option<&mut ClientSession>) -> Result<PrivateMessageCollection> {
+        observe_db("delete_private_message", async {
+            if let Some(collection) = &self.private_messages_collection {
+                let oid = parse_oid(id)?;
+                collection.find_one_and_delete_in(doc! {"_id": oid}, session).await?
+                    .ok_or(MyError::NotFoundError(id.to_string()))
+            } else {
+                Err(MyError::OwnError(String::from("Private messages collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn mute_user(&self, username: &str, until: chrono::DateTime<Utc>, session: Option<&mut ClientSession>) -> Result<()> {
+        observe_db("mute_user", async {
+            self.restrict_user(username, doc! {"muted_until": until}, session).await
+        }).await
+    }
+
+    pub async fn ban_user(&self, username: &str, session: Option<&mut ClientSession>) -> Result<()> {
+        observe_db("ban_user", async {
+            self.restrict_user(username, doc! {"banned": true, "online": false}, session).await
+        }).await
+    }
+
+    async fn restrict_user(&self, username: &str, mut update: Document, session: Option<&mut ClientSession>) -> Result<()> {
+        if let Some(collection) = &self.users_collection {
+            update.insert("updated_at", Utc::now());
+            let filter = doc! {"owned_uname": username};
+            let res = collection.update_one_in(filter, doc! {"$set": update}, None, session).await?;
+            if res.matched_count == 0 {
+                return Err(MyError::NotFoundError(username.to_string()));
+            }
+            Ok(())
         } else {
             Err(MyError::OwnError(String::from("Users collection not found")))
         }
     }
+
+    /// Active moderator restriction of the user, an expired mute is no restriction
+    pub async fn user_restriction(&self, username: &str) -> Result<Option<Restriction>> {
+        observe_db("user_restriction", async {
+            if let Some(collection) = &self.users_collection {
+                let user = collection.find_one(doc! {"owned_uname": username}, None).await?;
+                Ok(match user {
+                    Some(user) if user.banned => Some(Restriction::Banned),
+                    Some(user) => user.muted_until
+                        .map(|until| until.to_chrono())
+                        .filter(|until| *until > Utc::now())
+                        .map(Restriction::Muted),
+                    None => None,
+                })
+            } else {
+                Err(MyError::OwnError(String::from("Users collection not found")))
+            }
+        }).await
+    }
+
+    /// Status of the room, `Open` unless an admin changed it
+    pub async fn room_status(&self, room: &str) -> Result<RoomStatus> {
+        observe_db("room_status", async {
+            if let Some(collection) = &self.room_status_collection {
+                let res = collection.find_one(doc! {"room": room}, None).await?;
+                Ok(res.map(|res| res.status).unwrap_or_default())
+            } else {
+                Err(MyError::OwnError(String::from("Room status collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn set_room_status(&self, room: &str, status: RoomStatus, updated_by: &str) -> Result<RoomStatusCollection> {
+        observe_db("set_room_status", async {
+            if let Some(collection) = &self.room_status_collection {
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let res = collection.find_one_and_update(
+                    doc! {"room": room},
+                    doc! {
+                        "$set": {"status": bson::to_bson(&status)?, "updated_by": updated_by, "updated_at": Utc::now()},
+                        "$setOnInsert": {"created_at": Utc::now()}
+                    },
+                    options,
+                ).await?;
+
+                res.ok_or(MyError::OwnError(String::from("Error while updating the room status")))
+            } else {
+                Err(MyError::OwnError(String::from("Room status collection not found")))
+            }
+        }).await
+    }
+
+    pub async fn insert_admin_audit(&self, audit: &AdminAuditCollection, session: Option<&mut ClientSession>) -> Result<()> {
+        observe_db("insert_admin_audit", async {
+            if let Some(collection) = &self.admin_audit_collection {
+                collection.insert_one_in(audit, session).await?;
+                Ok(())
+            } else {
+                Err(MyError::OwnError(String::from("Admin audit collection not found")))
+            }
+        }).await
+    }
+
+    /// Page of the admin audit log, newest first
+    pub async fn get_admin_audit(&self, limit: i64, page: i64) -> Result<Vec<AdminAuditCollection>> {
+        observe_db("get_admin_audit", async {
+            if let Some(collection) = &self.admin_audit_collection {
+                let find_options = FindOptions::builder()
+                    .sort(doc! {"created_at": -1})
+                    .skip(u64::try_from((page - 1) * limit).unwrap_or(0))
+                    .limit(limit)
+                    .build();
+
+                let mut cursor = collection.find(None, find_options).await?;
+                let mut entries: Vec<AdminAuditCollection> = Vec::new();
+                while cursor.advance().await? {
+                    entries.push(cursor.deserialize_current()?);
+                }
+                Ok(entries)
+            } else {
+                Err(MyError::OwnError(String::from("Admin audit collection not found")))
+            }
+        }).await
+    }
+
+    /// Document count and storage figures of every collection
+    pub async fn collection_stats(&self) -> Result<Vec<CollectionStats>> {
+        observe_db("collection_stats", async {
+            let stats = vec![
+                collection_stats(&self.sockets_collection).await?,
+                collection_stats(&self.messages_collection).await?,
+                collection_stats(&self.private_messages_collection).await?,
+                collection_stats(&self.users_collection).await?,
+                collection_stats(&self.room_collection).await?,
+                collection_stats(&self.attachments_collection).await?,
+                collection_stats(&self.room_members_collection).await?,
+                collection_stats(&self.mentions_collection).await?,
+                collection_stats(&self.notifications_collection).await?,
+                collection_stats(&self.moderation_audit_collection).await?,
+                collection_stats(&self.held_messages_collection).await?,
+                collection_stats(&self.reports_collection).await?,
+                collection_stats(&self.room_status_collection).await?,
+                collection_stats(&self.admin_audit_collection).await?,
+                collection_stats(&self.username_history_collection).await?,
+                collection_stats(&self.contacts_collection).await?,
+                collection_stats(&self.group_dms_collection).await?,
+                collection_stats(&self.group_messages_collection).await?,
+            ];
+            Ok(stats.into_iter().flatten().collect())
+        }).await
+    }
+
+    /// Round trip of a `ping` command
+    pub async fn ping(&self) -> Result<std::time::Duration> {
+        observe_db("ping", async {
+            if let Some(database) = &self.database {
+                let started = std::time::Instant::now();
+                database.run_command(doc! {"ping": 1}, None).await?;
+                Ok(started.elapsed())
+            } else {
+                Err(MyError::OwnError(String::from("Database not found")))
+            }
+        }).await
+    }
+
+    /// Detect whether multi-document transactions can be used, without writing anything <br/>
+    /// The topology comes from `hello`, the support is confirmed by reading inside an aborted transaction
+    pub async fn transaction_support(&self) -> Result<TransactionSupport> {
+        observe_db("transaction_support", async {
+            if let (Some(database), Some(collection)) = (&self.database, &self.users_collection) {
+                let hello = database.run_command(doc! {"hello": 1}, None).await?;
+                let topology = if hello.get_str("msg").map(|msg| msg.eq("isdbgrid")).unwrap_or(false) {
+                    "sharded"
+                } else if hello.contains_key("setName") {
+                    "replica_set"
+                } else {
+                    "standalone"
+                };
+
+                let mut session = collection.client().start_session(None).await?;
+                let res = async {
+                    session.start_transaction(None).await?;
+                    collection.find_one_with_session(None, None, &mut session).await?;
+                    session.abort_transaction().await
+                }.await;
+
+                Ok(TransactionSupport {
+                    supported: res.is_ok(),
+                    topology: topology.to_string(),
+                    error: res.err().map(|e| e.to_string()),
+                })
+            } else {
+                Err(MyError::OwnError(String::from("Database not found")))
+            }
+        }).await
+    }
 }
\ No newline at end of file
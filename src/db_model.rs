@@ -1,13 +1,24 @@
 use chrono::DateTime;
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use crate::content::{Entity, LinkPreview};
+use crate::moderation::{Decision, MessageKind, Verdict};
 
+/// Connection of a user, the cross instance presence of the sockets <br/>
+/// `owned_username` is set once the socket sent `user_handle` and `focus` follows the `focus` event, it is cleared on disconnect <br/>
+/// `session` is the digest of the session token issued by `user_handle`, see `auth::AuthUser`
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SocketCollection {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub socket: String,
     pub username: String,
+    #[serde(default)]
+    pub owned_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub focus: Option<Focus>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<chrono::Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
@@ -21,6 +32,20 @@ pub struct MessageCollection {
     pub room: String,
     pub sender: String,
     pub message: String,
+    /// root message of the thread, `None` for messages posted directly in the room
+    #[serde(default)]
+    pub reply_to: Option<ObjectId>,
+    #[serde(default)]
+    pub reply_count: i64,
+    #[serde(default)]
+    pub last_reply_at: Option<bson::DateTime>,
+    /// IDs of the documents in `attachments` collection
+    #[serde(default)]
+    pub attachments: Vec<ObjectId>,
+    #[serde(default)]
+    pub entities: Vec<Entity>,
+    #[serde(default)]
+    pub previews: Vec<LinkPreview>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<chrono::Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
@@ -34,12 +59,73 @@ pub struct PrivateMessageCollection {
     pub sender: String,
     pub receiver: String,
     pub message: String,
+    #[serde(default)]
+    pub attachments: Vec<ObjectId>,
+    #[serde(default)]
+    pub status: DeliveryStatus,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updated_at: DateTime<chrono::Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<chrono::Utc>,
 }
 
+/// Group DM between 3 or more users <br/>
+/// The participants are fixed at creation, so a set of users has a single group
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupDmCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// the sorted participants joined by a newline, unique
+    pub key: String,
+    /// owned usernames, sorted
+    pub participants: Vec<String>,
+    pub name: Option<String>,
+    pub created_by: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+impl GroupDmCollection {
+    /// `participants` must be sorted
+    pub fn key(participants: &[String]) -> String {
+        participants.join("\n")
+    }
+}
+
+/// Message of a group DM, queued for each participant until confirmed with `group_delivered`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupMessageCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub group_id: ObjectId,
+    pub sender: String,
+    pub message: String,
+    #[serde(default)]
+    pub attachments: Vec<ObjectId>,
+    /// participants other than the sender which have not confirmed the message yet
+    pub pending: Vec<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// Delivery state of a private message <br/>
+/// `Sent` and `Queued` messages stay in the queue of the receiver until the receiver confirms them with `private_delivered`,
+/// `Queued` meaning the receiver had no connected socket when the message was sent <br/>
+/// The messages stored before the delivery tracking default to `Delivered`, so they are never flushed again
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Sent,
+    Queued,
+    #[default]
+    Delivered,
+    Failed,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserCollection {
     #[serde(rename = "_id")]
@@ -48,21 +134,393 @@ pub struct UserCollection {
     pub cur_gen_uname: String,
     pub last_username: String,
     pub online: bool,
+    /// owned usernames the user does not accept DMs, notifications and mentions from
+    #[serde(default)]
+    pub blocked: Vec<String>,
+    /// owned usernames whose DMs are still delivered but never notified
+    #[serde(default)]
+    pub muted: Vec<String>,
+    /// set by a moderator, the user can not send messages until then
+    #[serde(default)]
+    pub muted_until: Option<bson::DateTime>,
+    /// set by a moderator, the user can not link a connection anymore
+    #[serde(default)]
+    pub banned: bool,
+    /// time of the last rename of the owned username, the next one waits for the cooldown
+    #[serde(default)]
+    pub renamed_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub profile: UserProfile,
+    #[serde(default)]
+    pub dm_policy: DmPolicy,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updated_at: DateTime<chrono::Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<chrono::Utc>,
 }
 
+/// Conversation a socket is viewing: a room, the DM with another user or a group DM by its ID
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", content = "target", rename_all = "lowercase")]
+pub enum Focus {
+    Room(String),
+    Dm(String),
+    Group(String),
+}
+
+/// Who can send private messages to the user, enforced by `handle_private`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DmPolicy {
+    #[default]
+    Everyone,
+    ContactsOnly,
+}
+
+/// A friend request stays `Pending` until the addressee accepts it, declined and cancelled requests are deleted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContactStatus {
+    Pending,
+    Accepted,
+}
+
+/// One document per pair of users, whichever sent the request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContactCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// the two owned usernames sorted and joined by a newline, unique so a pair has a single request
+    pub key: String,
+    /// the two owned usernames, sorted
+    pub users: Vec<String>,
+    pub requester: String,
+    pub addressee: String,
+    pub status: ContactStatus,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+impl ContactCollection {
+    pub fn key(a: &str, b: &str) -> String {
+        if a <= b { format!("{}\n{}", a, b) } else { format!("{}\n{}", b, a) }
+    }
+
+    /// The user of the pair other than `username`
+    pub fn other(&self, username: &str) -> String {
+        if self.requester.eq(username) { self.addressee.clone() } else { self.requester.clone() }
+    }
+}
+
+/// Profile of a user, every field is optional and set with `PATCH /api/users/:username`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    /// image attachment uploaded by the user, readable by everyone once set
+    pub avatar: Option<ObjectId>,
+    pub status_text: Option<String>,
+    /// the status text is left out of the responses once passed
+    pub status_until: Option<bson::DateTime>,
+    /// IANA name such as `Europe/Paris`, or an offset such as `+05:30`
+    pub timezone: Option<String>,
+}
+
+/// Whether a history entry is about an owned username or a generated one
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsernameKind {
+    Owned,
+    Generated,
+}
+
+/// One document per username a user left behind <br/>
+/// An owned username keeps redirecting to the user until `redirect_until`, nobody else can claim it meanwhile
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsernameHistoryCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// `_id` of the user in `users`
+    pub user_id: ObjectId,
+    pub kind: UsernameKind,
+    pub username: String,
+    pub replaced_by: String,
+    #[serde(default)]
+    pub redirect_until: Option<bson::DateTime>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RoomCollection {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub room_name: Option<String>,
-    pub in_private: bool,
     pub owned_username: String,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updated_at: DateTime<chrono::Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: DateTime<chrono::Utc>,
-}
\ No newline at end of file
+}
+
+/// Metadata of an uploaded file, the content itself is kept in the `BlobStore` under `storage_key` <br/>
+/// `room` and `participants` are filled once the attachment is referenced by a message and drive the download access check
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub size: i64,
+    pub content_type: String,
+    /// SHA-256 of the content, hex encoded
+    pub checksum: String,
+    pub uploader: String,
+    pub storage_key: String,
+    pub room: Option<String>,
+    pub participants: Vec<String>,
+    /// set for avatars, readable by everyone
+    #[serde(default)]
+    pub public: bool,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// Role of a user inside a room, the first user joining a room becomes its owner
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomRole {
+    Owner,
+    Moderator,
+    Member,
+}
+
+impl RoomRole {
+    /// Owners and moderators can use `@here` and `@room`
+    pub fn can_mention_all(&self) -> bool {
+        matches!(self, RoomRole::Owner | RoomRole::Moderator)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomMemberCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub room: String,
+    pub username: String,
+    pub role: RoomRole,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// `User` for `@owned_username`, `Here` for the members connected to the room and `Room` for every member of the room
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MentionKind {
+    User,
+    Here,
+    Room,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MentionCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// owned username of the mentioned user
+    pub username: String,
+    pub sender: String,
+    pub room: String,
+    pub message_id: ObjectId,
+    pub message: String,
+    pub kind: MentionKind,
+    pub read: bool,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Dm,
+    Mention,
+    Invite,
+    Moderation,
+    Contact,
+}
+
+/// Entry of the notification inbox of `username`, kept until read so offline users get it on their next `user_handle`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub username: String,
+    pub kind: NotificationKind,
+    pub sender: Option<String>,
+    pub message: String,
+    /// what the notification points to, e.g. the room of a mention
+    pub reference: Option<String>,
+    pub read: bool,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// One document per moderated message, with the decision of every filter of the pipeline
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModerationAuditCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub kind: MessageKind,
+    pub sender: String,
+    /// room or receiver of the message
+    pub target: String,
+    pub verdict: Verdict,
+    pub decisions: Vec<Decision>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Message held by the moderation pipeline, waiting for a moderator
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeldMessageCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub kind: MessageKind,
+    pub sender: String,
+    pub target: String,
+    pub message: String,
+    pub reply_to: Option<String>,
+    pub attachments: Vec<String>,
+    pub reason: String,
+    pub status: ReviewStatus,
+    /// Moderator who approved or rejected the message
+    #[serde(default)]
+    pub reviewed_by: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+/// What a report points to, the `target` of the report is the ID of the message or the owned username
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportKind {
+    Message,
+    Private,
+    User,
+}
+
+/// `Open` reports wait in the review queue until a moderator claims them
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Open,
+    Claimed,
+    Resolved,
+}
+
+/// Action taken by the moderator resolving a report
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportAction {
+    Dismiss,
+    DeleteMessage,
+    Mute,
+    Ban,
+}
+
+/// Message removed by a report resolution, see `DB::resolve_report_with_action`
+#[derive(Debug)]
+pub enum RemovedMessage {
+    Room(MessageCollection),
+    Private(PrivateMessageCollection),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub reporter: String,
+    pub kind: ReportKind,
+    pub target: String,
+    /// owned username of the author of the reported message, or the reported user
+    pub reported_user: String,
+    /// room of the reported message, `None` for private messages and users
+    pub room: Option<String>,
+    pub reason: String,
+    pub status: ReportStatus,
+    pub moderator: Option<String>,
+    pub action: Option<ReportAction>,
+    pub note: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// `Closed` rooms can not be joined nor written to, `Archived` rooms keep their history readable
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomStatus {
+    #[default]
+    Open,
+    Closed,
+    Archived,
+}
+
+/// Status of a room set through the admin API, rooms without a document are open
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomStatusCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub room: String,
+    pub status: RoomStatus,
+    pub updated_by: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<chrono::Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// One document per action taken through the admin API or by a moderator resolving a report
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminAuditCollection {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub actor: String,
+    /// `X-Admin-User` header of the request, unverified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// One document per migration applied to the database, see `migrations::Migrator`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MigrationCollection {
+    #[serde(rename = "_id")]
+    pub version: i64,
+    pub name: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub applied_at: DateTime<chrono::Utc>,
+}
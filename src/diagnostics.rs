@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::Extension;
+use serde_json::Value;
+use socketioxide::socket::Sid;
+use crate::AppState;
+use crate::admin_handlers::{audit, AdminActor};
+use crate::errors::MyError;
+use crate::model::{DbLatency, LatencyQuery, LoopbackReq, LoopbackResp, NamespaceSockets};
+
+/// Namespaces registered in `main`, socketioxide does not list them
+const NAMESPACES: [&str; 1] = ["/"];
+
+/// Diagnostics settings, read from the environment <br/>
+/// - `ENABLE_DIAGNOSTICS`: `true` or `1` mounts the diagnostics under `/api/admin/diagnostics`, it also requires `ADMIN_TOKEN`
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsConfig {
+    pub enabled: bool,
+}
+
+impl DiagnosticsConfig {
+    pub fn from_env() -> Self {
+        let enabled = matches!(std::env::var("ENABLE_DIAGNOSTICS").unwrap_or_default().trim().to_lowercase().as_str(), "true" | "1");
+        Self { enabled }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// `ping` and indexed read round trips, `samples` times (3 by default, at most 20)
+pub async fn db_latency(
+    Query(query): Query<LatencyQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let samples = query.samples.unwrap_or(3).clamp(1, 20);
+    let mut ping_ms: Vec<f64> = Vec::with_capacity(samples);
+    let mut read_ms: Vec<f64> = Vec::with_capacity(samples);
+
+    for _ in 0..samples {
+        ping_ms.push(millis(state.db.ping().await?));
+
+        let started = Instant::now();
+        state.db.check_user_exists("__diagnostics__".to_string()).await?;
+        read_ms.push(millis(started.elapsed()));
+    }
+
+    let all = ping_ms.iter().chain(read_ms.iter()).copied().collect::<Vec<f64>>();
+    Ok((StatusCode::OK, Json(DbLatency {
+        min_ms: all.iter().copied().fold(f64::MAX, f64::min),
+        max_ms: all.iter().copied().fold(0.0, f64::max),
+        avg_ms: all.iter().sum::<f64>() / all.len() as f64,
+        ping_ms,
+        read_ms,
+    })))
+}
+
+/// Topology of the deployment and whether it accepts transactions
+pub async fn transaction_support(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let support = state.db.transaction_support().await?;
+    Ok((StatusCode::OK, Json(support)))
+}
+
+pub async fn socket_counts(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let socket_map = state.socket_state.socket_map.read().await;
+    let counts = NAMESPACES.iter().filter_map(|namespace| {
+        let sockets = state.io.of(*namespace)?.sockets().unwrap_or_default();
+        let rooms = state.io.of(*namespace)?.rooms().unwrap_or_default();
+        Some(NamespaceSockets {
+            namespace: namespace.to_string(),
+            linked: sockets.iter().filter(|socket| socket_map.contains_key(&socket.id.to_string())).count(),
+            sockets: sockets.len(),
+            rooms: rooms.len(),
+        })
+    }).collect::<Vec<NamespaceSockets>>();
+
+    Ok((StatusCode::OK, Json(counts)))
+}
+
+/// Emit `diagnostics_ping` to a single socket and measure the time until the client acknowledges it
+pub async fn loopback(
+    Extension(actor): Extension<AdminActor>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<LoopbackReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let sid = data.socket.parse::<Sid>().map_err(|_| MyError::InvalidIDError(data.socket.clone()))?;
+    let socket = state.io.get_socket(sid).ok_or(MyError::NotFoundError(data.socket.clone()))?;
+    let timeout = Duration::from_millis(data.timeout_ms.unwrap_or(5000).clamp(1, 30_000));
+
+    let started = Instant::now();
+    let sent_at = chrono::Utc::now();
+    let res = match socket.timeout(timeout).emit_with_ack::<_, Value>("diagnostics_ping", serde_json::json!({ "sent_at": sent_at })) {
+        Ok(ack) => ack.await.map(|_| started.elapsed()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+
+    audit(&state, &actor, "diagnostics_loopback", Some(data.socket.clone()), None).await;
+    Ok((StatusCode::OK, Json(LoopbackResp {
+        socket: data.socket,
+        acknowledged: res.is_ok(),
+        round_trip_ms: res.as_ref().ok().map(|elapsed| millis(*elapsed)),
+        error: res.err(),
+    })))
+}
@@ -6,7 +6,7 @@ pub enum MyError {
     #[error("MongoDB error")]
     MongoError(#[from] mongodb::error::Error),
     #[error("duplicate key error: {0}")]
-    MongoErrorKind(mongodb::error::ErrorKind),
+    MongoErrorKind(Box<mongodb::error::ErrorKind>),
     #[error("duplicate key error: {0}")]
     MongoDuplicateError(mongodb::error::Error),
     #[error("error during mongodb query: {0}")]
@@ -19,6 +19,18 @@ pub enum MyError {
     InvalidIDError(String),
     #[error("Record with ID: {0} not found")]
     NotFoundError(String),
+    #[error("invalid request: {0}")]
+    BadRequestError(String),
+    #[error("unauthorized: {0}")]
+    UnauthorizedError(String),
+    #[error("access denied: {0}")]
+    ForbiddenError(String),
+    #[error("conflict: {0}")]
+    ConflictError(String),
+    #[error("payload exceeds the limit of {0} bytes")]
+    PayloadTooLargeError(usize),
+    #[error("storage error: {0}")]
+    StorageError(#[from] std::io::Error),
     #[error("Internal error")]
     OwnError(String)
 }
@@ -29,9 +41,9 @@ struct ErrorResponse {
     message: String,
 }
 
-impl Into<(StatusCode, Json<serde_json::Value>)> for MyError {
-    fn into(self) -> (axum::http::StatusCode, Json<serde_json::Value>) {
-        let (status, error_response) = match self {
+impl From<MyError> for (StatusCode, Json<serde_json::Value>) {
+    fn from(err: MyError) -> (StatusCode, Json<serde_json::Value>) {
+        let (status, error_response) = match err {
             MyError::MongoErrorKind(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorResponse {
@@ -88,6 +100,48 @@ impl Into<(StatusCode, Json<serde_json::Value>)> for MyError {
                     message: format!("MongoDB error: {}", e),
                 },
             ),
+            MyError::BadRequestError(e) => (
+                StatusCode::BAD_REQUEST,
+                ErrorResponse {
+                    status: "fail",
+                    message: format!("Invalid request: {}", e),
+                },
+            ),
+            MyError::UnauthorizedError(e) => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    status: "fail",
+                    message: format!("Unauthorized: {}", e),
+                },
+            ),
+            MyError::ForbiddenError(e) => (
+                StatusCode::FORBIDDEN,
+                ErrorResponse {
+                    status: "fail",
+                    message: format!("Access denied: {}", e),
+                },
+            ),
+            MyError::ConflictError(e) => (
+                StatusCode::CONFLICT,
+                ErrorResponse {
+                    status: "fail",
+                    message: format!("Conflict: {}", e),
+                },
+            ),
+            MyError::PayloadTooLargeError(limit) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorResponse {
+                    status: "fail",
+                    message: format!("Payload exceeds the limit of {} bytes", limit),
+                },
+            ),
+            MyError::StorageError(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ErrorResponse {
+                    status: "error",
+                    message: format!("Storage error: {}", e),
+                },
+            ),
             MyError::OwnError(e) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 ErrorResponse {
@@ -99,9 +153,3 @@ impl Into<(StatusCode, Json<serde_json::Value>)> for MyError {
         (status, Json(serde_json::to_value(error_response).unwrap()))
     }
 }
-
-impl From<MyError> for (StatusCode, ErrorResponse) {
-    fn from(err: MyError) -> (StatusCode, ErrorResponse) {
-        err.into()
-    }
-}
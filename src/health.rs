@@ -0,0 +1,184 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::IntoResponse;
+use socketioxide::SocketIo;
+use tracing::info;
+use crate::AppState;
+use crate::model::{DependencyCheck, StatusReport};
+
+/// Process level state behind the health endpoints
+#[derive(Debug)]
+pub struct Health {
+    started: Instant,
+    indexes_ensured: AtomicBool,
+    shutting_down: AtomicBool,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            indexes_ensured: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Called once the indexes of `migrations::check_indexes` are all in place
+    pub fn set_indexes_ensured(&self) {
+        self.indexes_ensured.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM, after flipping the readiness and waiting `SHUTDOWN_GRACE_SECS` (5 by default)
+/// so the load balancer stops routing new connections before the server stops accepting them <br/>
+/// The remaining sockets are disconnected at the end, otherwise their long lived connections would keep the server running
+pub async fn shutdown_signal(health: Arc<Health>, io: SocketIo) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    let grace = std::env::var("SHUTDOWN_GRACE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(5);
+    info!("Shutdown requested, draining for {} seconds", grace);
+    health.set_shutting_down();
+    tokio::time::sleep(Duration::from_secs(grace)).await;
+
+    if let Some(operators) = io.of("/") {
+        operators.disconnect().ok();
+    }
+}
+
+/// Dependency checks shared by `/readyz` and `/api/status`
+async fn dependency_checks(state: &AppState) -> Vec<DependencyCheck> {
+    let mongo = match tokio::time::timeout(Duration::from_secs(2), state.db.ping()).await {
+        Ok(Ok(elapsed)) => DependencyCheck {
+            name: "mongodb".to_string(),
+            ok: true,
+            latency_ms: Some(elapsed.as_secs_f64() * 1000.0),
+            error: None,
+        },
+        Ok(Err(e)) => DependencyCheck {
+            name: "mongodb".to_string(),
+            ok: false,
+            latency_ms: None,
+            error: Some(e.to_string()),
+        },
+        Err(_) => DependencyCheck {
+            name: "mongodb".to_string(),
+            ok: false,
+            latency_ms: None,
+            error: Some("ping timed out".to_string()),
+        },
+    };
+
+    let indexes_ensured = state.health.indexes_ensured.load(Ordering::SeqCst);
+    let indexes = DependencyCheck {
+        name: "indexes".to_string(),
+        ok: indexes_ensured,
+        latency_ms: None,
+        error: (!indexes_ensured).then(|| "index drift, see the startup logs".to_string()),
+    };
+
+    // the namespace only exists once the socket.io layer is registered, its adapter then has to answer a fetch of the
+    // sockets, which is where a remote adapter reports a lost connection
+    let started = Instant::now();
+    let adapter = match state.io.of("/").map(|operators| operators.sockets()) {
+        Some(Ok(_)) => DependencyCheck {
+            name: "socketio_adapter".to_string(),
+            ok: true,
+            latency_ms: Some(started.elapsed().as_secs_f64() * 1000.0),
+            error: None,
+        },
+        Some(Err(e)) => DependencyCheck {
+            name: "socketio_adapter".to_string(),
+            ok: false,
+            latency_ms: None,
+            error: Some(e.to_string()),
+        },
+        None => DependencyCheck {
+            name: "socketio_adapter".to_string(),
+            ok: false,
+            latency_ms: None,
+            error: Some("namespace / is not registered".to_string()),
+        },
+    };
+
+    vec![mongo, indexes, adapter]
+}
+
+/// Liveness: the process answers
+pub async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+/// Readiness: every dependency check passes and the server is not shutting down
+pub async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if state.health.is_shutting_down() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_string());
+    }
+
+    let failing = dependency_checks(&state).await.into_iter()
+        .filter(|check| !check.ok)
+        .map(|check| check.name)
+        .collect::<Vec<String>>();
+    if failing.is_empty() {
+        (StatusCode::OK, "ready".to_string())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, format!("not ready: {}", failing.join(", ")))
+    }
+}
+
+/// JSON status report, answered with 503 whenever `/readyz` would fail
+pub async fn status_report(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let checks = dependency_checks(&state).await;
+    let status = if state.health.is_shutting_down() {
+        "shutting_down"
+    } else if checks.iter().all(|check| check.ok) {
+        "ok"
+    } else {
+        "degraded"
+    };
+
+    let code = if status.eq("ok") { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(StatusReport {
+        status: status.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: state.health.uptime().as_secs(),
+        sockets: state.io.sockets().map(|sockets| sockets.len()).unwrap_or(0),
+        checks,
+    }))
+}
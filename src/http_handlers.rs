@@ -1,54 +1,29 @@
 use std::sync::Arc;
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use axum::extract::{Multipart, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bson::oid::ObjectId;
 use serde_json::Value;
-use tracing::{error, info, warn};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
 use crate::AppState;
+use crate::auth::{same_secret, AuthUser};
+use crate::blob_store::sniff_content_type;
+use crate::db_model::{AttachmentCollection, ContactCollection, ContactStatus, Focus, NotificationKind, RemovedMessage, ReportAction, ReportCollection, ReviewStatus, RoomStatus, HeldMessageCollection};
 use crate::errors::MyError;
-use crate::model::{Filter, GeneralRequest, GeneralResponse, InPrivate, PaginationResponse, SocketResponse, User, UserExists};
-
-/// ### In this handler, we are going to emit a message to the client using the HTTP request handler
-/// *i.e, whenever the HTTP endpoint is hit, we are going to emit a message to the client and in this case we are broadcasting the message across all clients*
-/// *it is achieved using State(io), passed to the axum server using with_state() function*
-pub async fn http_socket_handler(State(app_state): State<Arc<AppState>>) {
-    let _ = app_state.io.emit("response", "Hello from server");
-}
-
-/// Handling the POST request from the client
-pub async fn http_socket_post_handler(
-    State(app_state): State<Arc<AppState>>,
-    Json(data): Json<GeneralRequest>,
-) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let general = GeneralRequest {
-        room: data.room.clone(),
-        sender: data.sender.clone(),
-        message: data.message.clone(),
-    };
-    info!("General: {:?}", &general);
-
-    warn!("Sockets: {:?}", app_state.io.sockets());
-
-    let response = GeneralResponse {
-        sender: general.sender.clone(),
-        room: general.room.clone(),
-        message: format!("Message By Client: {}", "HTTP Request").to_owned(),
-        date_time: chrono::Utc::now(),
-    };
-    info!("Response: {:?}", &response);
-
-    // not sending yet
-    app_state.io.emit("response", response.clone()).ok();
-    // io.within(general.room.clone()).emit("response", response.clone()).ok();
-
-    Ok((StatusCode::OK, Json::<GeneralResponse>(response)))
-}
+use crate::metrics::event_emitted;
+use crate::moderation::MessageKind;
+use crate::socket_handlers::{deliver_group_message, deliver_private_message, deliver_room_message, room_message};
+use crate::socket_state::SocketState;
+use crate::model::{EventError, HeldFilter, HeldMessageResp, UserRelation, GroupHistoryQuery, ContactReq, ContactUpdated, DmPolicyReq, MessageDeleted, ProfilePatch, ReportFilter, ReportReq, ReportResolve, ReportResp, Restriction, AttachmentResp, BlockListReq, MentionFilter, MentionResp, MentionsRead, NotificationFilter, NotificationsRead, Filter, InPrivate, PaginationResponse, SocketResponse, User, UserExists};
 
+/// Sockets stored in `sockets`, mounted under the admin router as it maps the sockets to the owned usernames <br/>
 /// *NOTE:*
 /// - The implementation is incomplete as is provided only for testing purposes, as List of Pair<String,String> is to be utilized rather than List of String.<br/>
 /// - Providing the list of sockets connected to the server creates a form of vulnerability, and is not to be used in realtime applications.<br/>
-/// <br/>
+///
 /// The function can be upgraded to fetch the socket details stored in any storage system like Redis, MongoDB, etc.
 pub async fn http_sockets_list(
     filter: Option<Query<Filter>>,
@@ -73,7 +48,7 @@ pub async fn http_sockets_list(
     let limit = filter.limit.unwrap_or(10) as i64;
     let page = filter.page.unwrap_or(1) as i64;
 
-    return match app_state.db.get_sockets(limit, page).await.map_err(MyError::from) {
+    return match app_state.db.get_sockets(limit, page).await {
         Ok(res) => {
             // info!("Sockets: {:?}", res);
             Ok((StatusCode::OK, Json(res)))
@@ -111,16 +86,22 @@ pub async fn check_user_exists(
     }
 }
 
+/// Whether a socket of the user is viewing a DM, on any instance, with every conversation its sockets are viewing
 pub async fn check_user_in_private(
     Query(data): Query<User>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<InPrivate>)> {
     info!("User in Private: {:?}", data);
-    match state.db.check_private_exists(data).await.map_err(MyError::from) {
-        Ok(res) => {
+    let focus = match state.db.find_user(&data.username).await {
+        Ok(_) => state.db.user_focus(&data.username).await,
+        Err(e) => Err(e)
+    };
+    match focus {
+        Ok(focus) => {
             let resp = InPrivate {
-                username: res.owned_username,
-                in_private: res.in_private,
+                username: data.username,
+                in_private: focus.iter().any(|focus| matches!(focus, Focus::Dm(_))),
+                focus,
             };
 
             Ok((StatusCode::FOUND, Json(resp)))
@@ -132,15 +113,657 @@ pub async fn check_user_in_private(
                 Json(InPrivate {
                     username: format!("ERROR: {:?}", e).to_owned(),
                     in_private: false,
+                    focus: vec![],
                 })
             ))
         }
     }
 }
 
-pub async fn test_transaction(
-    State(state): State<Arc<AppState>>
+/// Thread view: the root message and a page of its replies <br/>
+/// Uses the same `page`/`limit` query parameters as the sockets list
+pub async fn get_thread(
+    Path(id): Path<String>,
+    filter: Option<Query<Filter>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let Query(filter) = filter.unwrap_or_default();
+    let limit = filter.limit.unwrap_or(20).max(1) as i64;
+    let page = filter.page.unwrap_or(1).max(1) as i64;
+
+    match state.db.get_thread(id, limit, page).await {
+        Ok(res) => Ok((StatusCode::OK, Json(res))),
+        Err(e) => {
+            error!("Error: {:?}", e);
+            Err(e.into())
+        }
+    }
+}
+
+/// Upload a file as multipart form data with the field `file`, the uploader is the caller <br/>
+/// The content is written to the blob store and the metadata to the `attachments` collection,
+/// the returned ID can then be referenced in the `attachments` of `message` and `private` payloads.
+/// The stored content type is the one sniffed from the content, not the one declared by the client
+pub async fn upload_attachment(
+    AuthUser(uploader): AuthUser,
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let config = &state.attachment_config;
+    let mut file: Option<(String, String, Vec<u8>)> = None;
+
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| MyError::BadRequestError(e.to_string()))? {
+        // other fields are ignored, the uploader is the caller
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field.file_name().unwrap_or("file").to_string();
+
+        let mut data: Vec<u8> = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(|e| MyError::BadRequestError(e.to_string()))? {
+            if data.len() + chunk.len() > config.max_size {
+                return Err(MyError::PayloadTooLargeError(config.max_size).into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let content_type = sniff_content_type(&data).to_string();
+        if !config.is_allowed(&content_type) {
+            return Err(MyError::BadRequestError(format!("file type {} is not allowed", content_type)).into());
+        }
+        file = Some((name, content_type, data));
+    }
+
+    let (name, content_type, data) = file.ok_or(MyError::BadRequestError("missing file".to_string()))?;
+
+    let id = ObjectId::new();
+    let storage_key = id.to_hex();
+    state.blob_store.put(&storage_key, &data).await?;
+
+    let attachment = AttachmentCollection {
+        id,
+        name,
+        size: data.len() as i64,
+        content_type,
+        checksum: format!("{:x}", Sha256::digest(&data)),
+        uploader,
+        storage_key: storage_key.clone(),
+        room: None,
+        participants: vec![],
+        public: false,
+        updated_at: chrono::Utc::now(),
+        created_at: chrono::Utc::now(),
+    };
+
+    match state.db.insert_attachment(attachment).await {
+        Ok(res) => Ok((StatusCode::CREATED, Json(attachment_resp(res)))),
+        Err(e) => {
+            error!("Error: {:?}", e);
+            // do not leave orphan blobs behind
+            state.blob_store.delete(&storage_key).await.ok();
+            Err(e.into())
+        }
+    }
+}
+
+pub async fn get_attachment_meta(
+    Path(id): Path<String>,
+    user: Option<AuthUser>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let attachment = state.db.find_attachment(&id).await?;
+    check_attachment_access(&state, &attachment, user)?;
+
+    Ok((StatusCode::OK, Json(attachment_resp(attachment))))
+}
+
+pub async fn download_attachment(
+    Path(id): Path<String>,
+    user: Option<AuthUser>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let attachment = state.db.find_attachment(&id).await?;
+    check_attachment_access(&state, &attachment, user)?;
+
+    let data = state.blob_store.get(&attachment.storage_key).await?;
+    let disposition = format!("attachment; filename=\"{}\"", attachment.name.replace('"', ""));
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        data,
+    ))
+}
+
+/// The uploader and the DM participants always have access, and everyone to the avatars, without a session token <br/>
+/// For room attachments the user needs a live socket in the room, i.e. one of the sockets
+/// joined to the owned username room (see `handle_user_join`) is also joined to the attachment room
+fn check_attachment_access(state: &AppState, attachment: &AttachmentCollection, user: Option<AuthUser>) -> Result<(), MyError> {
+    if attachment.public {
+        return Ok(());
+    }
+    let AuthUser(username) = user.ok_or(MyError::UnauthorizedError("missing session token".to_string()))?;
+    if attachment.uploader.eq(&username) || attachment.participants.iter().any(|p| p.eq(&username)) {
+        return Ok(());
+    }
+
+    if let Some(room) = &attachment.room {
+        let user_sockets = state.io.within(username.clone()).sockets().unwrap_or_default();
+        let room_sockets = state.io.within(room.clone()).sockets().unwrap_or_default();
+        if user_sockets.iter().any(|user_socket| room_sockets.iter().any(|room_socket| room_socket.id == user_socket.id)) {
+            return Ok(());
+        }
+    }
+
+    Err(MyError::ForbiddenError(format!("{} can not access attachment {}", username, attachment.id)))
+}
+
+fn attachment_resp(attachment: AttachmentCollection) -> AttachmentResp {
+    AttachmentResp {
+        id: attachment.id.to_hex(),
+        name: attachment.name,
+        size: attachment.size,
+        content_type: attachment.content_type,
+        checksum: attachment.checksum,
+        uploader: attachment.uploader,
+        created_at: attachment.created_at,
+    }
+}
+
+/// Mentions of the caller, newest first, only the unread ones with `unread=true`
+pub async fn get_mentions(
+    AuthUser(username): AuthUser,
+    Query(filter): Query<MentionFilter>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let mentions = state.db.get_mentions(&username, filter.unread).await?;
+    let mentions = mentions.into_iter().map(SocketState::mention_resp).collect::<Vec<MentionResp>>();
+
+    Ok((StatusCode::OK, Json(mentions)))
+}
+
+pub async fn mark_mentions_read(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<MentionsRead>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let updated = state.db.mark_mentions_read(&username, &data.ids).await?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!({ "updated": updated }))))
+}
+
+/// Inbox of the caller, newest first, with the total unread count
+pub async fn get_notifications(
+    AuthUser(username): AuthUser,
+    Query(filter): Query<NotificationFilter>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let limit = filter.limit.unwrap_or(20).max(1) as i64;
+    let page = filter.page.unwrap_or(1).max(1) as i64;
+
+    let inbox = state.socket_state.notification_inbox(&username, filter.unread, limit, page).await?;
+    Ok((StatusCode::OK, Json(inbox)))
+}
+
+pub async fn mark_notifications_read(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<NotificationsRead>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if data.ids.is_empty() {
+        return Err(MyError::BadRequestError("no notification IDs given, use /api/notifications/read-all".to_string()).into());
+    }
+    let updated = state.db.mark_notifications_read(&username, &data.ids).await?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!({ "updated": updated }))))
+}
+
+/// Mark every unread notification of the caller as read
+pub async fn mark_all_notifications_read(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
-    let res = state.db.test_transaction().await.unwrap();
-    Ok((StatusCode::OK, Json(res)))
-}
\ No newline at end of file
+    let updated = state.db.mark_notifications_read(&username, &[]).await?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!({ "updated": updated }))))
+}
+
+/// Profile of the user, a renamed user is found under its old username during the grace period
+pub async fn get_profile(
+    Path(username): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let username = state.db.resolve_username(&username).await?.ok_or(MyError::NotFoundError(username))?;
+    let user = state.db.find_user(&username).await?;
+    Ok((StatusCode::OK, Json(SocketState::profile_resp(user))))
+}
+
+/// Update the profile of the user, its rooms and DM contacts get `profile_updated` <br/>
+/// Only the owner of the session token can update their own profile
+pub async fn update_profile(
+    AuthUser(caller): AuthUser,
+    Path(username): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ProfilePatch>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if caller.ne(&username) {
+        return Err(MyError::ForbiddenError(format!("{} can not update the profile of {}", caller, username)).into());
+    }
+    let profile = state.socket_state.update_profile(&username, data).await?;
+
+    let sockets = state.io.within(username.clone()).sockets().unwrap_or_default();
+    let rooms = state.socket_state.audience(sockets, &username).await;
+    event_emitted("profile_updated");
+    state.io.within(rooms).emit("profile_updated", profile.clone()).ok();
+
+    Ok((StatusCode::OK, Json(profile)))
+}
+
+/// Who can send private messages to the user, `everyone` or `contacts_only`, only set by the user
+pub async fn set_dm_policy(
+    AuthUser(caller): AuthUser,
+    Path(username): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<DmPolicyReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    if caller.ne(&username) {
+        return Err(MyError::ForbiddenError(format!("{} can not change the DM policy of {}", caller, username)).into());
+    }
+    let dm_policy = state.db.set_dm_policy(&username, data.dm_policy).await?;
+    Ok((StatusCode::OK, Json(DmPolicyReq { dm_policy })))
+}
+
+/// Contacts of the caller with their presence, and the pending requests both ways
+pub async fn get_contacts(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let list = state.socket_state.contact_list(&username).await?;
+    Ok((StatusCode::OK, Json(list)))
+}
+
+/// Send a friend request from the caller to `target`, a pending request from `target` is accepted instead
+pub async fn send_contact_request(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ContactReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contact = state.socket_state.send_contact_request(&username, &data.target).await?;
+    announce_contact(&state, &contact, &username, false).await;
+    Ok((StatusCode::CREATED, Json(contact_updated(&contact, &username, false))))
+}
+
+/// Accept the request `target` sent to the caller
+pub async fn accept_contact_request(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ContactReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contact = state.db.respond_contact_request(&username, &data.target, true).await?;
+    announce_contact(&state, &contact, &username, false).await;
+    Ok((StatusCode::OK, Json(contact_updated(&contact, &username, false))))
+}
+
+/// Decline the request `target` sent to the caller
+pub async fn decline_contact_request(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ContactReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contact = state.db.respond_contact_request(&username, &data.target, false).await?;
+    announce_contact(&state, &contact, &username, true).await;
+    Ok((StatusCode::OK, Json(contact_updated(&contact, &username, true))))
+}
+
+/// Withdraw the request the caller sent to `target`
+pub async fn cancel_contact_request(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ContactReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contact = state.db.delete_contact(&username, &data.target, ContactStatus::Pending).await?;
+    announce_contact(&state, &contact, &username, true).await;
+    Ok((StatusCode::OK, Json(contact_updated(&contact, &username, true))))
+}
+
+/// Remove `target` from the contacts of the caller
+pub async fn remove_contact(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ContactReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let contact = state.db.delete_contact(&username, &data.target, ContactStatus::Accepted).await?;
+    announce_contact(&state, &contact, &username, true).await;
+    Ok((StatusCode::OK, Json(contact_updated(&contact, &username, true))))
+}
+
+/// The pair seen from `username`
+fn contact_updated(contact: &ContactCollection, username: &str, removed: bool) -> ContactUpdated {
+    ContactUpdated {
+        username: contact.other(username),
+        state: (!removed).then(|| SocketState::contact_state(contact, username)),
+    }
+}
+
+/// Tell both users of the pair about the change made by `actor`, a new request or an acceptance also lands in the
+/// notification inbox of the other user
+async fn announce_contact(state: &AppState, contact: &ContactCollection, actor: &str, removed: bool) {
+    for username in [contact.requester.as_str(), contact.addressee.as_str()] {
+        event_emitted("contact_updated");
+        state.io.within(username.to_string()).emit("contact_updated", contact_updated(contact, username, removed)).ok();
+    }
+
+    let other = contact.other(actor);
+    let message = match (removed, contact.status) {
+        (false, ContactStatus::Pending) => format!("{} sent you a contact request", actor),
+        (false, ContactStatus::Accepted) => format!("{} accepted your contact request", actor),
+        (true, _) => return,
+    };
+    if let Some(notification) = state.socket_state.notify(&other, NotificationKind::Contact, Some(actor.to_string()), message, None).await {
+        event_emitted("notification");
+        state.io.within(other).emit("notification", notification).ok();
+    }
+}
+
+/// Group DMs of the caller, the most recently active first
+pub async fn get_group_dms(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let groups = state.db.group_dms(&username).await?;
+    Ok((StatusCode::OK, Json(groups.into_iter().map(SocketState::group_dm_resp).collect::<Vec<_>>())))
+}
+
+/// Page of the messages of a group DM, newest first, only for its participants <br/>
+/// The messages of the users blocked by the caller are left out
+pub async fn get_group_messages(
+    AuthUser(username): AuthUser,
+    Path(id): Path<String>,
+    Query(query): Query<GroupHistoryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let limit = query.limit.unwrap_or(20).max(1) as i64;
+    let page = query.page.unwrap_or(1).max(1) as i64;
+
+    let group = state.db.find_group_dm(&id).await?;
+    if !group.participants.contains(&username) {
+        return Err(MyError::ForbiddenError(format!("{} is not a participant of the group", username)).into());
+    }
+    let blocked = state.db.get_block_list(&username).await?.blocked;
+    let messages = state.db.get_group_messages(group.id, &blocked, limit, page).await?;
+
+    Ok((StatusCode::OK, Json(PaginationResponse {
+        data: messages.data.into_iter().map(SocketState::group_message_resp).collect::<Vec<_>>(),
+        curr_page: messages.curr_page,
+        total_pages: messages.total_pages,
+        total_records: messages.total_records,
+        next_page: messages.next_page,
+        prev_page: messages.prev_page,
+    })))
+}
+
+/// Every owned and generated username the user went by, newest first, only for the user itself
+pub async fn get_username_history(
+    AuthUser(caller): AuthUser,
+    Path(username): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    // an old name still redirecting to the caller is the caller as well
+    if state.db.resolve_username(&username).await?.as_deref() != Some(caller.as_str()) {
+        return Err(MyError::ForbiddenError("only the user can read its username history".to_string()).into());
+    }
+    let history = state.db.username_history(&caller).await?;
+    Ok((StatusCode::OK, Json(history.into_iter().map(SocketState::username_history_resp).collect::<Vec<_>>())))
+}
+
+pub async fn get_block_list(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let list = state.db.get_block_list(&username).await?;
+    Ok((StatusCode::OK, Json(list)))
+}
+
+/// Add `target` to the blocked or muted list of the caller
+pub async fn add_to_block_list(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<BlockListReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let list = state.db.update_block_list(&username, &data.target, data.list, true).await?;
+    Ok((StatusCode::OK, Json(list)))
+}
+
+pub async fn remove_from_block_list(
+    AuthUser(username): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<BlockListReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let list = state.db.update_block_list(&username, &data.target, data.list, false).await?;
+    Ok((StatusCode::OK, Json(list)))
+}
+
+/// Report a message or a user over HTTP, the reporter is the owner of the session token
+pub async fn create_report(
+    AuthUser(reporter): AuthUser,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ReportReq>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    let report = state.socket_state.create_report(&reporter, data).await?;
+
+    Ok((StatusCode::CREATED, Json(SocketState::report_resp(report))))
+}
+
+/// Review queue, oldest first, the open and claimed reports unless `status` is given
+pub async fn get_reports(
+    AuthUser(moderator): AuthUser,
+    Query(filter): Query<ReportFilter>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let limit = filter.limit.unwrap_or(20).max(1) as i64;
+    let page = filter.page.unwrap_or(1).max(1) as i64;
+
+    let reports = state.db.get_reports(filter.status, limit, page).await?;
+    let reports = reports.into_iter().map(SocketState::report_resp).collect::<Vec<ReportResp>>();
+
+    Ok((StatusCode::OK, Json(reports)))
+}
+
+pub async fn claim_report(
+    AuthUser(moderator): AuthUser,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let report = state.db.claim_report(&id, &moderator).await?;
+
+    Ok((StatusCode::OK, Json(SocketState::report_resp(report))))
+}
+
+/// Apply the action of the moderator and close the report, the reporter gets the outcome as a `moderation` notification
+pub async fn resolve_report(
+    AuthUser(moderator): AuthUser,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<ReportResolve>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let report = state.db.find_report(&id).await?;
+    if let Some(claimed_by) = report.moderator.as_ref().filter(|m| m.as_str() != moderator) {
+        return Err(MyError::ConflictError(format!("report {} is claimed by {}", id, claimed_by)).into());
+    }
+
+    let mute_until = chrono::Utc::now() + chrono::Duration::minutes(data.mute_minutes.unwrap_or(60).max(1));
+    let (report, removed) = state.db.resolve_report_with_action(&id, &moderator, data.action, data.note, mute_until).await?;
+    announce_report_action(&state, &report, removed).await;
+    info!("Report {} resolved by {} with {:?}", id, moderator, data.action);
+
+    let outcome = match data.action {
+        ReportAction::Dismiss => "no action was taken".to_string(),
+        ReportAction::DeleteMessage => "the message was removed".to_string(),
+        ReportAction::Mute => format!("{} was muted", report.reported_user),
+        ReportAction::Ban => format!("{} was banned", report.reported_user),
+    };
+    notify_moderation(&state, &report.reporter, format!("Your report was reviewed: {}", outcome), report.id.to_hex()).await;
+
+    Ok((StatusCode::OK, Json(SocketState::report_resp(report))))
+}
+
+/// Messages held by the moderation pipeline, oldest first, the pending ones unless `status` is given
+pub async fn get_held_messages(
+    AuthUser(moderator): AuthUser,
+    Query(filter): Query<HeldFilter>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let limit = filter.limit.unwrap_or(20).max(1) as i64;
+    let page = filter.page.unwrap_or(1).max(1) as i64;
+
+    let held = state.db.get_held_messages(filter.status, limit, page).await?;
+    let held = held.into_iter().map(SocketState::held_message_resp).collect::<Vec<HeldMessageResp>>();
+
+    Ok((StatusCode::OK, Json(held)))
+}
+
+/// Release a pending held message to its room, receiver or group DM as if it was just sent, the sender gets a `moderation` notification <br/>
+/// The message goes back to pending when it can not be delivered anymore, e.g. the room was closed or the receiver blocked the sender
+pub async fn approve_held_message(
+    AuthUser(moderator): AuthUser,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let held = state.db.review_held_message(&id, Some(&moderator), ReviewStatus::Pending, ReviewStatus::Approved).await?;
+    if let Err(e) = release_held_message(&state, &held).await {
+        state.db.review_held_message(&id, None, ReviewStatus::Approved, ReviewStatus::Pending).await?;
+        return Err(e.into());
+    }
+    info!("Held message {} approved by {}", id, moderator);
+
+    notify_moderation(&state, &held.sender, format!("Your message to {} was approved and delivered", held.target), held.id.to_hex()).await;
+    Ok((StatusCode::OK, Json(SocketState::held_message_resp(held))))
+}
+
+/// Drop a pending held message, the sender gets a `moderation` notification
+pub async fn reject_held_message(
+    AuthUser(moderator): AuthUser,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+    check_moderator(&state, &moderator)?;
+    let held = state.db.review_held_message(&id, Some(&moderator), ReviewStatus::Pending, ReviewStatus::Rejected).await?;
+    info!("Held message {} rejected by {}", id, moderator);
+
+    notify_moderation(&state, &held.sender, format!("Your message to {} was rejected by a moderator", held.target), held.id.to_hex()).await;
+    Ok((StatusCode::OK, Json(SocketState::held_message_resp(held))))
+}
+
+/// Deliver an approved held message through the same path as the socket handlers,
+/// the checks which may have changed since the message was held run again, a banned or muted sender included
+async fn release_held_message(state: &AppState, held: &HeldMessageCollection) -> Result<(), MyError> {
+    let socket_state = &state.socket_state;
+    match socket_state.db.user_restriction(&held.sender).await? {
+        Some(Restriction::Banned) => return Err(MyError::ConflictError(format!("{} is banned", held.sender))),
+        Some(Restriction::Muted(until)) => return Err(MyError::ConflictError(format!("{} is muted until {}", held.sender, until.to_rfc3339()))),
+        None => {}
+    }
+    match held.kind {
+        MessageKind::Room => {
+            if let RoomStatus::Closed | RoomStatus::Archived = socket_state.db.room_status(&held.target).await? {
+                return Err(MyError::ConflictError(format!("room {} is read-only", held.target)));
+            }
+            let message = room_message(held.sender.clone(), held.target.clone(), held.message.clone(), held.reply_to.clone(), held.attachments.clone());
+            for error in deliver_room_message(&state.io, socket_state, message).await? {
+                event_emitted("event_error");
+                state.io.within(held.sender.clone()).emit("event_error", EventError {
+                    event: "message".to_string(),
+                    message: error,
+                }).ok();
+            }
+        }
+        MessageKind::Private => {
+            let relation = socket_state.db.user_relation(&held.target, &held.sender).await?;
+            if relation == UserRelation::Blocked || !socket_state.db.dm_allowed(&held.target, &held.sender).await? {
+                return Err(MyError::ConflictError("message could not be delivered".to_string()));
+            }
+            let muted = relation == UserRelation::Muted;
+            deliver_private_message(&state.io, socket_state, &held.sender, &held.target, held.message.clone(), held.attachments.clone(), muted).await?;
+        }
+        MessageKind::Group => {
+            let group = socket_state.db.find_group_dm(&held.target).await?;
+            if !group.participants.contains(&held.sender) {
+                return Err(MyError::ConflictError(format!("{} is not a participant of the group", held.sender)));
+            }
+            deliver_group_message(&state.io, socket_state, group, &held.sender, held.message.clone(), held.attachments.clone()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Store a `moderation` notification for `username` and emit it to their owned username room
+async fn notify_moderation(state: &AppState, username: &str, message: String, reference: String) {
+    if let Some(notification) = state.socket_state.notify(username, NotificationKind::Moderation, None, message, Some(reference)).await {
+        event_emitted("notification");
+        state.io.within(username.to_string()).emit("notification", notification).ok();
+    }
+}
+
+/// Guard of the moderation router, the caller has to send the `MODERATOR_TOKEN` shared by the moderators in
+/// `X-Moderator-Token`, on top of the session of a user listed in `MODERATORS` checked by each handler
+pub async fn require_moderator(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let token = state.moderator_token.as_deref()
+        .ok_or(MyError::ForbiddenError("the moderation API is disabled".to_string()))?;
+    let given = request.headers()
+        .get("x-moderator-token")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !same_secret(token, given) {
+        return Err(MyError::UnauthorizedError("invalid moderator token".to_string()).into());
+    }
+
+    Ok(next.run(request).await)
+}
+
+fn check_moderator(state: &AppState, moderator: &str) -> Result<(), MyError> {
+    if state.moderators.iter().any(|m| m.eq(moderator)) {
+        Ok(())
+    } else {
+        Err(MyError::ForbiddenError(format!("{} is not a moderator", moderator)))
+    }
+}
+
+/// Tell the clients about the action once it is committed, a banned user is disconnected as well
+async fn announce_report_action(state: &AppState, report: &ReportCollection, removed: Option<RemovedMessage>) {
+    match removed {
+        Some(RemovedMessage::Room(message)) => {
+            let reply_to = message.reply_to.map(|root| root.to_hex());
+            state.socket_state.forget_message(&message.room, &report.target, reply_to.clone()).await;
+
+            // the watchers of the thread are told as well, either the thread of the reply or the thread of the deleted root
+            let thread_room = SocketState::thread_room(&reply_to.unwrap_or(report.target.clone()));
+            event_emitted("message_deleted");
+            state.io.within(vec![message.room.clone(), thread_room]).emit("message_deleted", MessageDeleted {
+                id: report.target.clone(),
+                room: Some(message.room),
+            }).ok();
+        }
+        Some(RemovedMessage::Private(message)) => {
+            event_emitted("message_deleted");
+            state.io.within(vec![message.sender, message.receiver]).emit("message_deleted", MessageDeleted {
+                id: report.target.clone(),
+                room: None,
+            }).ok();
+        }
+        None => {}
+    }
+    if report.action == Some(ReportAction::Ban) {
+        state.io.within(report.reported_user.clone()).disconnect().ok();
+    }
+}
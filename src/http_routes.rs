@@ -1,18 +1,106 @@
 use std::sync::Arc;
-use axum::Router;
-use axum::routing::{get, post};
+use axum::extract::DefaultBodyLimit;
+use axum::{middleware, Router};
+use axum::routing::{get, post, put};
+use tracing::warn;
 use crate::{AppState};
-use crate::http_handlers::{check_user_exists, check_user_in_private, http_socket_handler, http_socket_post_handler, http_sockets_list, test_transaction};
+use crate::metrics::metrics_handler;
+use crate::health::{healthz, readyz, status_report};
+use crate::diagnostics::{db_latency, loopback, socket_counts, transaction_support};
+use crate::admin_handlers::{announce, disconnect_socket, disconnect_user, get_audit, get_stats, list_sockets, require_admin, set_room_status};
+use crate::http_handlers::{check_user_exists, check_user_in_private, http_sockets_list, get_thread, upload_attachment, download_attachment, get_attachment_meta, get_mentions, mark_mentions_read, get_notifications, mark_notifications_read, mark_all_notifications_read, get_profile, update_profile, get_username_history, set_dm_policy, get_contacts, remove_contact, send_contact_request, accept_contact_request, decline_contact_request, cancel_contact_request, get_group_dms, get_group_messages, get_block_list, add_to_block_list, remove_from_block_list, create_report, get_reports, claim_report, resolve_report, get_held_messages, approve_held_message, reject_held_message, require_moderator};
 
 pub fn create_router(app_state: Arc<AppState>) -> Router {
-    Router::new()
+    // leave some room for the multipart boundaries and the text fields
+    let upload_limit = app_state.attachment_config.max_size + 64 * 1024;
+
+    let router = Router::new()
         .route("/api/", get(|| async { "Server Running" }))
-        .route("/api/socket-test", get(http_socket_handler)) // handle GET request on /socket-test namespace
-        // .route("/api/post", post(|| async { "POST Request"}))
-        .route("/api/post", post(http_socket_post_handler))
-        .route("/api/sockets-list", get(http_sockets_list))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/api/status", get(status_report))
+        .route("/metrics", get(metrics_handler))
         .route("/api/check-username", post(check_user_exists))
-        .route("/api/tt", get(test_transaction))
         .route("/api/in-private", get(check_user_in_private))
-        .with_state(app_state) // handle state and http events
+        .route("/api/threads/:id", get(get_thread))
+        .route("/api/attachments", post(upload_attachment).layer(DefaultBodyLimit::max(upload_limit)))
+        .route("/api/attachments/:id", get(download_attachment))
+        .route("/api/attachments/:id/meta", get(get_attachment_meta))
+        .route("/api/mentions", get(get_mentions))
+        .route("/api/mentions/read", post(mark_mentions_read))
+        .route("/api/notifications", get(get_notifications))
+        .route("/api/notifications/read", post(mark_notifications_read))
+        .route("/api/notifications/read-all", post(mark_all_notifications_read))
+        .route("/api/users/:username", get(get_profile).patch(update_profile))
+        .route("/api/users/:username/history", get(get_username_history))
+        .route("/api/users/:username/dm-policy", put(set_dm_policy))
+        .route("/api/group-dms", get(get_group_dms))
+        .route("/api/group-dms/:id/messages", get(get_group_messages))
+        .route("/api/contacts", get(get_contacts).delete(remove_contact))
+        .route("/api/contacts/requests", post(send_contact_request))
+        .route("/api/contacts/requests/accept", post(accept_contact_request))
+        .route("/api/contacts/requests/decline", post(decline_contact_request))
+        .route("/api/contacts/requests/cancel", post(cancel_contact_request))
+        .route("/api/blocks", get(get_block_list).post(add_to_block_list).delete(remove_from_block_list))
+        .route("/api/reports", post(create_report));
+
+    let router = if app_state.moderator_token.is_some() {
+        router.nest("/api/moderation", create_moderation_router(app_state.clone()))
+    } else {
+        warn!("MODERATOR_TOKEN is not set, the moderation API is disabled");
+        router
+    };
+
+    let router = if app_state.admin_config.token.is_some() {
+        router.nest("/api/admin", create_admin_router(app_state.clone()))
+    } else {
+        warn!("ADMIN_TOKEN is not set, the admin API is disabled");
+        router
+    };
+
+    router.with_state(app_state) // handle state and http events
+}
+
+/// Review queue and held messages, every route requires the `MODERATOR_TOKEN` (see `require_moderator`)
+/// and the session of a user listed in `MODERATORS`
+pub fn create_moderation_router(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/reports", get(get_reports))
+        .route("/reports/:id/claim", post(claim_report))
+        .route("/reports/:id/resolve", post(resolve_report))
+        .route("/held", get(get_held_messages))
+        .route("/held/:id/approve", post(approve_held_message))
+        .route("/held/:id/reject", post(reject_held_message))
+        .route_layer(middleware::from_fn_with_state(app_state, require_moderator))
+}
+
+/// Operator endpoints, every route requires the `ADMIN_TOKEN` bearer token (see `require_admin`)
+pub fn create_admin_router(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let router = Router::new()
+        .route("/sockets", get(list_sockets))
+        .route("/sockets-list", get(http_sockets_list))
+        .route("/sockets/:id/disconnect", post(disconnect_socket))
+        .route("/users/:username/disconnect", post(disconnect_user))
+        .route("/announcements", post(announce))
+        .route("/rooms/:room/status", post(set_room_status))
+        .route("/stats", get(get_stats))
+        .route("/audit", get(get_audit));
+
+    // the diagnostics replace the former `/api/tt`, `/api/socket-test` and `/api/post` debug endpoints
+    let router = if app_state.diagnostics_config.enabled {
+        router.nest("/diagnostics", create_diagnostics_router())
+    } else {
+        router
+    };
+
+    router.route_layer(middleware::from_fn_with_state(app_state, require_admin))
+}
+
+/// Mounted under the admin router only when `ENABLE_DIAGNOSTICS` is set
+pub fn create_diagnostics_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/db", get(db_latency))
+        .route("/transactions", get(transaction_support))
+        .route("/sockets", get(socket_counts))
+        .route("/loopback", post(loopback))
 }
\ No newline at end of file
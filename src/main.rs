@@ -7,23 +7,55 @@ mod errors;
 mod db_model;
 mod socket_state;
 mod socket_handlers;
+mod blob_store;
+mod content;
+mod moderation;
+mod admin_handlers;
+mod diagnostics;
+mod health;
+mod metrics;
+mod telemetry;
+mod migrations;
+mod auth;
+#[cfg(test)]
+mod tests;
 
 use std::sync::Arc;
 use axum::http::{HeaderValue, Method};
+use axum::Router;
 use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
 use dotenv::dotenv;
 use socketioxide::SocketIo;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
-
-use tracing_subscriber::fmt;
-use crate::db::DB;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
+use crate::admin_handlers::AdminConfig;
+use crate::blob_store::{AttachmentConfig, BlobStore, LocalBlobStore};
+use crate::diagnostics::DiagnosticsConfig;
+use crate::content::{HttpPreviewFetcher, LinkPreviewFetcher, NoopPreviewFetcher};
+use crate::db::{DB, SOCKET_HEARTBEAT};
+use crate::health::{shutdown_signal, Health};
+use crate::moderation::ModerationPipeline;
 use crate::http_routes::create_router;
+use crate::migrations::Migrator;
 use crate::socket::on_connect;
+use crate::socket_state::SocketState;
 
 pub struct AppState {
     io: SocketIo,
     db: DB,
+    blob_store: Arc<dyn BlobStore>,
+    attachment_config: AttachmentConfig,
+    socket_state: Arc<SocketState>,
+    /// owned usernames allowed to use the report review queue and review the held messages
+    moderators: Vec<String>,
+    /// secret shared by the moderators, the moderation API is not mounted without it
+    moderator_token: Option<String>,
+    admin_config: AdminConfig,
+    diagnostics_config: DiagnosticsConfig,
+    health: Arc<Health>,
 }
 
 #[tokio::main]
@@ -31,8 +63,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
     const PORT: i32 = 4040;
 
-    let db = DB::connect_mongo().await.unwrap();
+    // For Logging the different events in the application, JSON lines filtered by RUST_LOG
+    telemetry::init_tracing()?;
+
+    let health = Arc::new(Health::new());
+    let mut db = DB::connect_mongo().await.unwrap();
+
+    // `migrate [status | up | down <version>] [--dry-run]` runs the migrations and exits without serving
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.first().map(|arg| arg.eq("migrate")).unwrap_or(false) {
+        migrations::run_cli(&db, &args[1..]).await?;
+        return Ok(());
+    }
+
+    // the pending migrations are applied on start unless AUTO_MIGRATE=false, e.g. when they run as a deploy step
+    let migrator = Migrator::new(&db)?;
+    if std::env::var("AUTO_MIGRATE").map(|v| !v.eq_ignore_ascii_case("false")).unwrap_or(true) {
+        migrator.migrate(false).await?;
+    } else if let pending @ 1.. = migrator.pending().await? {
+        warn!("{} migrations pending, run `migrate` before serving", pending);
+    }
+
+    // the server is only ready once the planned indexes exist, the extra ones are only reported
+    let drift = migrations::check_indexes(&db).await?;
+    for index in drift.missing.iter().chain(drift.mismatched.iter()) {
+        warn!("Index drift, expected {}", index);
+    }
+    for index in &drift.unexpected {
+        warn!("Index drift, unexpected {}", index);
+    }
+    if drift.is_ok() {
+        health.set_indexes_ensured();
+    }
+
+    // the multi-document operations (see `db::UnitOfWork`) still run on a standalone server, one step after the other
+    if !db.detect_transactions().await?.supported {
+        warn!("MongoDB deployment does not support transactions, multi-document operations run without atomicity");
+    }
+
+    let blob_store = LocalBlobStore::new(std::env::var("BLOB_DIR").unwrap_or("uploads".to_owned())).await?;
+    let (app, state) = build_app(db, health.clone(), Arc::new(blob_store));
+
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", PORT)).await?;
+
+    info!("Server running on port: {}", PORT);
+
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal(health, state.io.clone()))
+        .await
+        .unwrap();
 
+    telemetry::shutdown_tracing();
+    Ok(())
+}
+
+/// Build the socket.io layer and the HTTP router around `db`, the rest of the configuration is read from the environment <br/>
+/// Shared by `main` and the integration tests, which serve the router on an ephemeral port
+fn build_app(db: DB, health: Arc<Health>, blob_store: Arc<dyn BlobStore>) -> (Router, Arc<AppState>) {
     let origins = std::env::var("ORIGINS")
         .unwrap_or("http://localhost:3000,http://localhost:3001,http://localhost:5173".to_owned())
         .replace("[","")
@@ -41,18 +128,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .map(|origin| origin.parse::<HeaderValue>().unwrap())
         .collect::<Vec<HeaderValue>>();
 
-    println!("Origins: {:?}", origins);
+    info!("Origins: {:?}", origins);
 
-    // For Logging the different events in the application in three categories (info, warn, error)
-    tracing::subscriber::set_global_default(fmt::Subscriber::default()).unwrap();
+    // same list format as ORIGINS
+    let moderators = std::env::var("MODERATORS")
+        .unwrap_or_default()
+        .replace("[","")
+        .replace("]","")
+        .split(",")
+        .map(|moderator| moderator.trim().to_string())
+        .filter(|moderator| !moderator.is_empty())
+        .collect::<Vec<String>>();
+
+    // link previews are only fetched when explicitly enabled, so the server works offline by default
+    let preview_fetcher: Arc<dyn LinkPreviewFetcher> = match std::env::var("LINK_PREVIEW_FETCHER").unwrap_or_default().as_str() {
+        "http" => Arc::new(HttpPreviewFetcher::new()),
+        _ => Arc::new(NoopPreviewFetcher),
+    };
 
-    let socket_state = Arc::new(socket_state::SocketState::new(db.clone()));
+    let socket_state = Arc::new(SocketState::new(db.clone(), preview_fetcher, ModerationPipeline::from_env()));
     let (layer, io) = SocketIo::builder()
-        .with_state(socket_state)
+        .with_state(socket_state.clone())
         .build_layer();
 
     io.ns("/", on_connect);
 
+    // the focus and sessions of the sockets are only trusted by the other instances while this one refreshes them
+    let heartbeat = socket_state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SOCKET_HEARTBEAT);
+        loop {
+            interval.tick().await;
+            heartbeat.heartbeat().await;
+        }
+    });
+
     let cors = CorsLayer::new()
         // .allow_origin(["http://localhost:3000".parse::<HeaderValue>().unwrap()])
         .allow_origin(origins)
@@ -60,19 +170,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .allow_credentials(true)
         .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
 
-
-    let app = create_router(Arc::new(AppState { io: io.clone(), db: db.clone() }))
+    let state = Arc::new(AppState {
+        io,
+        db,
+        blob_store,
+        attachment_config: AttachmentConfig::from_env(),
+        socket_state,
+        moderators,
+        moderator_token: std::env::var("MODERATOR_TOKEN").ok().filter(|token| !token.trim().is_empty()),
+        admin_config: AdminConfig::from_env(),
+        diagnostics_config: DiagnosticsConfig::from_env(),
+        health,
+    });
+
+    let app = create_router(state.clone())
         .layer(
             ServiceBuilder::new()
+                // every HTTP request gets an `x-request-id`, recorded on its span and returned in the response
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(telemetry::http_span))
+                .layer(PropagateRequestIdLayer::x_request_id())
                 .layer(cors)
                 .layer(layer)
         );
 
-
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", PORT)).await?;
-
-    println!("Server running on port: {}", PORT);
-
-    axum::serve(listener, app.into_make_service()).await.unwrap();
-    Ok(())
+    (app, state)
 }
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use crate::AppState;
+
+/// Process wide collectors, shared by the socket handlers and the `DB` methods
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    pub connected_sockets: IntGauge,
+    pub events_received: IntCounterVec,
+    pub events_emitted: IntCounterVec,
+    pub event_duration: HistogramVec,
+    pub db_duration: HistogramVec,
+    pub db_errors: IntCounterVec,
+    pub room_store_rooms: IntGauge,
+    pub room_store_messages: IntGauge,
+    pub rate_limit_rejections: IntCounter,
+    pub scrape_duration: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let connected_sockets = IntGauge::new("chat_connected_sockets", "Sockets connected to the default namespace").unwrap();
+        let events_received = IntCounterVec::new(
+            Opts::new("chat_events_received_total", "Socket events received, by event name"),
+            &["event"],
+        ).unwrap();
+        let events_emitted = IntCounterVec::new(
+            Opts::new("chat_events_emitted_total", "Socket events emitted by the server, by event name"),
+            &["event"],
+        ).unwrap();
+        let event_duration = HistogramVec::new(
+            HistogramOpts::new("chat_event_handler_duration_seconds", "Time spent in the socket event handlers"),
+            &["event"],
+        ).unwrap();
+        let db_duration = HistogramVec::new(
+            HistogramOpts::new("chat_db_operation_duration_seconds", "Latency of the DB methods")
+                .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["method"],
+        ).unwrap();
+        let db_errors = IntCounterVec::new(
+            Opts::new("chat_db_operation_errors_total", "Failed DB method calls"),
+            &["method"],
+        ).unwrap();
+        let room_store_rooms = IntGauge::new("chat_room_store_rooms", "Rooms kept in the in-memory RoomStore").unwrap();
+        let room_store_messages = IntGauge::new("chat_room_store_messages", "Messages kept in the in-memory RoomStore").unwrap();
+        let rate_limit_rejections = IntCounter::new(
+            "chat_rate_limit_rejections_total",
+            "Messages rejected by the spam filter rate limits",
+        ).unwrap();
+        let scrape_duration = Histogram::with_opts(
+            HistogramOpts::new("chat_metrics_scrape_duration_seconds", "Time spent rendering /metrics"),
+        ).unwrap();
+
+        registry.register(Box::new(connected_sockets.clone())).unwrap();
+        registry.register(Box::new(events_received.clone())).unwrap();
+        registry.register(Box::new(events_emitted.clone())).unwrap();
+        registry.register(Box::new(event_duration.clone())).unwrap();
+        registry.register(Box::new(db_duration.clone())).unwrap();
+        registry.register(Box::new(db_errors.clone())).unwrap();
+        registry.register(Box::new(room_store_rooms.clone())).unwrap();
+        registry.register(Box::new(room_store_messages.clone())).unwrap();
+        registry.register(Box::new(rate_limit_rejections.clone())).unwrap();
+        registry.register(Box::new(scrape_duration.clone())).unwrap();
+
+        Self {
+            registry,
+            connected_sockets,
+            events_received,
+            events_emitted,
+            event_duration,
+            db_duration,
+            db_errors,
+            room_store_rooms,
+            room_store_messages,
+            rate_limit_rejections,
+            scrape_duration,
+        }
+    }
+}
+
+/// Count a received event and time its handler, the latency is recorded when the returned timer is dropped
+pub fn event_received(event: &str) -> HistogramTimer {
+    METRICS.events_received.with_label_values(&[event]).inc();
+    METRICS.event_duration.with_label_values(&[event]).start_timer()
+}
+
+pub fn event_emitted(event: &str) {
+    METRICS.events_emitted.with_label_values(&[event]).inc();
+}
+
+/// Run the body of a `DB` method, recording its latency and counting its errors
+pub async fn observe_db<T, E, F>(method: &'static str, operation: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let res = operation.await;
+    METRICS.db_duration.with_label_values(&[method]).observe(started.elapsed().as_secs_f64());
+    if res.is_err() {
+        METRICS.db_errors.with_label_values(&[method]).inc();
+    }
+    res
+}
+
+/// Prometheus text exposition, the gauges are refreshed on every scrape
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let _timer = METRICS.scrape_duration.start_timer();
+
+    METRICS.connected_sockets.set(state.io.sockets().map(|sockets| sockets.len()).unwrap_or(0) as i64);
+    {
+        let _messages = state.socket_state.messages.read().await;
+        METRICS.room_store_rooms.set(_messages.len() as i64);
+        METRICS.room_store_messages.set(_messages.values().map(|room| room.len()).sum::<usize>() as i64);
+    }
+
+    let mut buffer: Vec<u8> = Vec::new();
+    match TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer) {
+        Ok(_) => (StatusCode::OK, [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], buffer),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, [(header::CONTENT_TYPE, "text/plain")], e.to_string().into_bytes()),
+    }
+}
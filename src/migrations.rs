@@ -0,0 +1,901 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, Database, IndexModel};
+use tracing::{info, warn};
+use crate::db::{is_duplicate_key, DB};
+use crate::db_model::MigrationCollection;
+use crate::errors::MyError;
+
+/// override the standard result type for the module
+type Result<T> = std::result::Result<T, MyError>;
+
+/// Collection recording the applied migrations, one document per version
+const MIGRATIONS_COLLECTION: &str = "_migrations";
+
+/// A versioned change of the collections <br/>
+/// Migrations run in `version` order and are recorded in `_migrations` once `up` succeeds. `up` and `down` must be
+/// idempotent, a migration interrupted half way (or run by two instances starting together) is simply run again
+#[async_trait]
+trait Migration: Send + Sync {
+    fn version(&self) -> i64;
+
+    fn name(&self) -> &'static str;
+
+    /// Changes `up` would make on the current database, printed by the dry-runs
+    async fn plan(&self, db: &Database) -> Result<Vec<String>>;
+
+    async fn up(&self, db: &Database) -> Result<()>;
+
+    async fn down(&self, db: &Database) -> Result<()>;
+
+    /// Indexes created by `up`, see `index_plan`
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        vec![]
+    }
+
+    /// Indexes of the earlier migrations dropped by `up`, see `index_plan`
+    fn dropped_indexes(&self) -> Vec<IndexSpec> {
+        vec![]
+    }
+}
+
+/// Every migration, in version order. New migrations are appended with the next version, applied ones are never edited
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(InitialIndexes),
+        Box::new(BackfillDefaults),
+        Box::new(IndexPlan),
+        Box::new(UsernameHistory),
+        Box::new(Contacts),
+        Box::new(GroupDms),
+        Box::new(SocketFocus),
+        Box::new(SocketSessions),
+        Box::new(RoomOwners),
+    ]
+}
+
+/// Outcome of a migration for `migrate` and `rollback`, `applied` is `false` for the dry-runs
+#[derive(Debug, Clone)]
+pub struct MigrationStep {
+    pub version: i64,
+    pub name: String,
+    pub direction: &'static str,
+    pub changes: Vec<String>,
+    pub applied: bool,
+}
+
+/// Known migration with the date it was applied, `None` while pending
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub struct Migrator {
+    database: Database,
+    records: Collection<MigrationCollection>,
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl Migrator {
+    pub fn new(db: &DB) -> Result<Self> {
+        let database = db.database.clone().ok_or(MyError::OwnError(String::from("Database not found")))?;
+        Ok(Self {
+            records: database.collection(MIGRATIONS_COLLECTION),
+            database,
+            migrations: migrations(),
+        })
+    }
+
+    async fn applied(&self) -> Result<Vec<MigrationCollection>> {
+        let mut cursor = self.records.find(None, None).await?;
+        let mut applied: Vec<MigrationCollection> = Vec::new();
+        while cursor.advance().await? {
+            applied.push(cursor.deserialize_current()?);
+        }
+        applied.sort_by_key(|record| record.version);
+
+        // a database migrated by a newer build can not be handled by this one
+        if let Some(unknown) = applied.iter().find(|record| !self.migrations.iter().any(|m| m.version() == record.version)) {
+            return Err(MyError::ConflictError(format!(
+                "migration {} ({}) is applied but unknown to this build",
+                unknown.version, unknown.name
+            )));
+        }
+        Ok(applied)
+    }
+
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>> {
+        let applied = self.applied().await?;
+        Ok(self.migrations.iter().map(|migration| MigrationStatus {
+            version: migration.version(),
+            name: migration.name().to_string(),
+            applied_at: applied.iter().find(|record| record.version == migration.version()).map(|record| record.applied_at),
+        }).collect())
+    }
+
+    pub async fn pending(&self) -> Result<usize> {
+        Ok(self.status().await?.iter().filter(|status| status.applied_at.is_none()).count())
+    }
+
+    /// Apply the pending migrations in order, stopping at the first failure <br/>
+    /// With `dry_run` nothing is written, the planned changes are returned instead
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<MigrationStep>> {
+        let applied = self.applied().await?;
+        let mut steps: Vec<MigrationStep> = Vec::new();
+
+        for migration in &self.migrations {
+            if applied.iter().any(|record| record.version == migration.version()) {
+                continue;
+            }
+            let changes = migration.plan(&self.database).await?;
+            if !dry_run {
+                info!("Applying migration {} ({})", migration.version(), migration.name());
+                migration.up(&self.database).await?;
+                let record = MigrationCollection {
+                    version: migration.version(),
+                    name: migration.name().to_string(),
+                    applied_at: chrono::Utc::now(),
+                };
+                match self.records.insert_one(&record, None).await {
+                    Ok(_) => {}
+                    // another instance applied it concurrently, `up` being idempotent this is fine
+                    Err(e) if is_duplicate_key(&e) => warn!("Migration {} was recorded concurrently", migration.version()),
+                    Err(e) => return Err(MyError::MongoError(e)),
+                }
+            }
+            steps.push(MigrationStep {
+                version: migration.version(),
+                name: migration.name().to_string(),
+                direction: "up",
+                changes,
+                applied: !dry_run,
+            });
+        }
+        Ok(steps)
+    }
+
+    /// Revert the applied migrations newer than `target`, newest first (`0` reverts everything)
+    pub async fn rollback(&self, target: i64, dry_run: bool) -> Result<Vec<MigrationStep>> {
+        let applied = self.applied().await?;
+        let mut steps: Vec<MigrationStep> = Vec::new();
+
+        for migration in self.migrations.iter().rev() {
+            if migration.version() <= target || !applied.iter().any(|record| record.version == migration.version()) {
+                continue;
+            }
+            if !dry_run {
+                info!("Reverting migration {} ({})", migration.version(), migration.name());
+                migration.down(&self.database).await?;
+                self.records.delete_one(doc! {"_id": migration.version()}, None).await?;
+            }
+            steps.push(MigrationStep {
+                version: migration.version(),
+                name: migration.name().to_string(),
+                direction: "down",
+                changes: vec![],
+                applied: !dry_run,
+            });
+        }
+        Ok(steps)
+    }
+}
+
+/// `migrate [status | up | down <version>] [--dry-run]`, `up` being the default <br/>
+/// `status` also lists the index drift, see `check_indexes`
+pub async fn run_cli(db: &DB, args: &[String]) -> Result<()> {
+    let migrator = Migrator::new(db)?;
+    let dry_run = args.iter().any(|arg| arg.eq("--dry-run"));
+    let args = args.iter().filter(|arg| !arg.starts_with("--")).collect::<Vec<&String>>();
+
+    let steps = match args.first().map(|arg| arg.as_str()) {
+        Some("status") => {
+            for status in migrator.status().await? {
+                match status.applied_at {
+                    Some(applied_at) => println!("{:>4}  {:<24} applied {}", status.version, status.name, applied_at.to_rfc3339()),
+                    None => println!("{:>4}  {:<24} pending", status.version, status.name),
+                }
+            }
+            let drift = check_indexes(db).await?;
+            for index in &drift.missing {
+                println!("missing     {}", index);
+            }
+            for index in &drift.mismatched {
+                println!("mismatched  {}", index);
+            }
+            for index in &drift.unexpected {
+                println!("unexpected  {}", index);
+            }
+            return Ok(());
+        }
+        None | Some("up") => migrator.migrate(dry_run).await?,
+        Some("down") => {
+            let target = args.get(1)
+                .and_then(|version| version.parse::<i64>().ok())
+                .ok_or(MyError::BadRequestError("usage: migrate down <version> [--dry-run]".to_string()))?;
+            migrator.rollback(target, dry_run).await?
+        }
+        Some(other) => return Err(MyError::BadRequestError(format!("unknown migrate command: {}", other))),
+    };
+
+    if steps.is_empty() {
+        println!("Nothing to do");
+    }
+    for step in steps {
+        let verb = if step.applied { "done" } else { "dry-run" };
+        println!("{} {} ({}) [{}]", step.direction, step.version, step.name, verb);
+        for change in step.changes {
+            println!("    {}", change);
+        }
+    }
+    Ok(())
+}
+
+/// `IndexNotFound` (27) and `NamespaceNotFound` (26), the targets of a drop being already gone
+fn is_already_dropped(e: &mongodb::error::Error) -> bool {
+    matches!(e.kind.as_ref(), mongodb::error::ErrorKind::Command(command) if command.code == 26 || command.code == 27)
+}
+
+/// Name the server gives an index created without one, e.g. `receiver_1_status_1_created_at_1`
+fn index_name(keys: &Document) -> String {
+    keys.iter().map(|(field, direction)| match direction {
+        Bson::String(kind) => format!("{}_{}", field, kind),
+        Bson::Int32(v) => format!("{}_{}", field, v),
+        Bson::Int64(v) => format!("{}_{}", field, v),
+        other => format!("{}_{}", field, other),
+    }).collect::<Vec<String>>().join("_")
+}
+
+/// Index of a collection, named by the server from its keys
+#[derive(Debug, Clone)]
+struct IndexSpec {
+    collection: &'static str,
+    keys: Document,
+    unique: bool,
+    expire_after: Option<Duration>,
+    partial: Option<Document>,
+}
+
+impl IndexSpec {
+    fn new(collection: &'static str, keys: Document) -> Self {
+        Self { collection, keys, unique: false, expire_after: None, partial: None }
+    }
+
+    fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    /// Partial index, only the documents matching `filter` are indexed (and checked by a unique index)
+    fn partial(mut self, filter: Document) -> Self {
+        self.partial = Some(filter);
+        self
+    }
+
+    /// TTL index, the documents are removed once the date in the indexed field is older than `expire_after`
+    fn ttl(mut self, expire_after: Duration) -> Self {
+        self.expire_after = Some(expire_after);
+        self
+    }
+
+    fn name(&self) -> String {
+        index_name(&self.keys)
+    }
+
+    fn describe(&self) -> String {
+        let mut kind = String::new();
+        if self.unique {
+            kind.push_str("unique ");
+        }
+        if let Some(expire_after) = self.expire_after {
+            kind.push_str(&format!("TTL {}s ", expire_after.as_secs()));
+        }
+        if let Some(filter) = &self.partial {
+            kind.push_str(&format!("partial {} ", filter));
+        }
+        format!("{}: {}index {}", self.collection, kind, self.name())
+    }
+
+    fn model(&self) -> IndexModel {
+        let options = IndexOptions::builder()
+            .unique(self.unique.then_some(true))
+            .expire_after(self.expire_after)
+            .partial_filter_expression(self.partial.clone())
+            .build();
+        IndexModel::builder().keys(self.keys.clone()).options(options).build()
+    }
+}
+
+/// Create the indexes, creating an index which already exists with the same keys and options is a no-op
+async fn create_indexes(db: &Database, indexes: &[IndexSpec]) -> Result<()> {
+    for index in indexes {
+        db.collection::<Document>(index.collection).create_index(index.model(), None).await?;
+    }
+    Ok(())
+}
+
+async fn drop_indexes(db: &Database, indexes: &[IndexSpec]) -> Result<()> {
+    for index in indexes {
+        match db.collection::<Document>(index.collection).drop_index(index.name(), None).await {
+            Ok(_) => {}
+            Err(e) if is_already_dropped(&e) => {}
+            Err(e) => return Err(MyError::MongoError(e)),
+        }
+    }
+    Ok(())
+}
+
+/// 1: the indexes previously created by `DB::connect_mongo` on every start
+struct InitialIndexes;
+
+impl InitialIndexes {
+    /// the `_id` indexes always exist and are left out
+    fn indexes() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("sockets", doc! {"created_at": -1}),
+            IndexSpec::new("sockets", doc! {"username": "text"}),
+            IndexSpec::new("messages", doc! {"created_at": -1}),
+            IndexSpec::new("messages", doc! {"room": "text"}),
+            IndexSpec::new("private_messages", doc! {"created_at": -1}),
+            IndexSpec::new("private_messages", doc! {"sender": "text"}),
+            IndexSpec::new("private_messages", doc! {"receiver": 1, "status": 1, "created_at": 1}),
+            IndexSpec::new("users", doc! {"created_at": -1}),
+            IndexSpec::new("users", doc! {"owned_uname": "text"}),
+            IndexSpec::new("rooms", doc! {"created_at": -1}),
+            IndexSpec::new("rooms", doc! {"owned_username": "text"}),
+            IndexSpec::new("attachments", doc! {"created_at": -1}),
+            IndexSpec::new("attachments", doc! {"uploader": 1}),
+            IndexSpec::new("room_members", doc! {"room": 1, "username": 1}).unique(),
+            IndexSpec::new("mentions", doc! {"username": 1, "read": 1, "created_at": -1}),
+            IndexSpec::new("notifications", doc! {"username": 1, "read": 1, "created_at": -1}),
+            IndexSpec::new("moderation_audit", doc! {"created_at": -1}),
+            IndexSpec::new("moderation_audit", doc! {"sender": 1, "created_at": -1}),
+            IndexSpec::new("held_messages", doc! {"status": 1, "created_at": 1}),
+            IndexSpec::new("reports", doc! {"status": 1, "created_at": 1}),
+            IndexSpec::new("room_status", doc! {"room": 1}).unique(),
+            IndexSpec::new("admin_audit", doc! {"created_at": -1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for InitialIndexes {
+    fn version(&self) -> i64 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "initial_indexes"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::indexes()
+    }
+
+    async fn plan(&self, _db: &Database) -> Result<Vec<String>> {
+        Ok(Self::indexes().iter().map(|index| format!("create {}", index.describe())).collect())
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::indexes()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::indexes()).await
+    }
+}
+
+/// 2: write the fields added over time to the documents stored before them <br/>
+/// The structs read the missing fields with `#[serde(default)]`, but the queries filtering on them (e.g. `banned: false`)
+/// do not match the older documents
+struct BackfillDefaults;
+
+impl BackfillDefaults {
+    /// `(collection, field, value)`, the values are the serde defaults of `db_model`
+    fn defaults() -> Vec<(&'static str, &'static str, Bson)> {
+        vec![
+            ("messages", "reply_to", Bson::Null),
+            ("messages", "reply_count", Bson::Int64(0)),
+            ("messages", "last_reply_at", Bson::Null),
+            ("messages", "attachments", Bson::Array(vec![])),
+            ("messages", "entities", Bson::Array(vec![])),
+            ("messages", "previews", Bson::Array(vec![])),
+            ("private_messages", "attachments", Bson::Array(vec![])),
+            // stored before the delivery tracking, see `DeliveryStatus`
+            ("private_messages", "status", Bson::String("delivered".to_string())),
+            ("users", "blocked", Bson::Array(vec![])),
+            ("users", "muted", Bson::Array(vec![])),
+            ("users", "muted_until", Bson::Null),
+            ("users", "banned", Bson::Boolean(false)),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for BackfillDefaults {
+    fn version(&self) -> i64 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "backfill_defaults"
+    }
+
+    async fn plan(&self, db: &Database) -> Result<Vec<String>> {
+        let mut changes: Vec<String> = Vec::new();
+        for (collection, field, value) in Self::defaults() {
+            let missing = db.collection::<Document>(collection)
+                .count_documents(doc! {field: {"$exists": false}}, None)
+                .await?;
+            changes.push(format!("{}: set {} = {} on {} documents", collection, field, value, missing));
+        }
+        Ok(changes)
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        for (collection, field, value) in Self::defaults() {
+            db.collection::<Document>(collection)
+                .update_many(doc! {field: {"$exists": false}}, doc! {"$set": {field: value}}, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Nothing to revert: the written values are the ones read for the missing fields
+    async fn down(&self, _db: &Database) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How long the generated username of a connection is kept in `sockets` when the disconnect is never recorded
+const SOCKET_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// 3: indexes matching the equality lookups of `db.rs` <br/>
+/// The text indexes of the first plan never served a query, every lookup on those fields is an equality match.
+/// The owned usernames become unique, the migration stops while duplicates exist so they can be merged first
+struct IndexPlan;
+
+impl IndexPlan {
+    fn dropped() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("sockets", doc! {"created_at": -1}),
+            IndexSpec::new("sockets", doc! {"username": "text"}),
+            IndexSpec::new("messages", doc! {"room": "text"}),
+            IndexSpec::new("private_messages", doc! {"sender": "text"}),
+            IndexSpec::new("users", doc! {"owned_uname": "text"}),
+            IndexSpec::new("rooms", doc! {"owned_username": "text"}),
+        ]
+    }
+
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("sockets", doc! {"username": 1}),
+            // replaces `created_at_-1`, a TTL index can not share the keys of another index
+            IndexSpec::new("sockets", doc! {"created_at": 1}).ttl(SOCKET_TTL),
+            IndexSpec::new("messages", doc! {"room": 1, "created_at": -1}),
+            IndexSpec::new("messages", doc! {"reply_to": 1, "created_at": 1}),
+            IndexSpec::new("private_messages", doc! {"sender": 1, "receiver": 1, "created_at": -1}),
+            IndexSpec::new("users", doc! {"owned_uname": 1}).unique(),
+            IndexSpec::new("rooms", doc! {"owned_username": 1}).unique(),
+        ]
+    }
+
+    /// Values of `field` held by more than one document, which would make the unique index fail
+    async fn duplicates(db: &Database, collection: &str, field: &str) -> Result<Vec<String>> {
+        let pipeline = vec![
+            doc! {"$group": {"_id": format!("${}", field), "count": {"$sum": 1}}},
+            doc! {"$match": {"count": {"$gt": 1}}},
+            doc! {"$limit": 20},
+        ];
+        let mut cursor = db.collection::<Document>(collection).aggregate(pipeline, None).await?;
+        let mut values: Vec<String> = Vec::new();
+        while cursor.advance().await? {
+            let group = cursor.deserialize_current()?;
+            values.push(group.get("_id").map(|value| value.to_string()).unwrap_or_default());
+        }
+        Ok(values)
+    }
+
+    async fn unique_conflicts(db: &Database) -> Result<Vec<String>> {
+        let mut conflicts: Vec<String> = Vec::new();
+        for index in Self::created().iter().filter(|index| index.unique) {
+            let field = index.keys.keys().next().map(|field| field.as_str()).unwrap_or_default();
+            let duplicates = Self::duplicates(db, index.collection, field).await?;
+            if !duplicates.is_empty() {
+                conflicts.push(format!("{}.{} has duplicates: {}", index.collection, field, duplicates.join(", ")));
+            }
+        }
+        Ok(conflicts)
+    }
+}
+
+#[async_trait]
+impl Migration for IndexPlan {
+    fn version(&self) -> i64 {
+        3
+    }
+
+    fn name(&self) -> &'static str {
+        "index_plan"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    fn dropped_indexes(&self) -> Vec<IndexSpec> {
+        Self::dropped()
+    }
+
+    async fn plan(&self, db: &Database) -> Result<Vec<String>> {
+        let mut changes = Self::dropped().iter().map(|index| format!("drop {}", index.describe())).collect::<Vec<String>>();
+        changes.extend(Self::created().iter().map(|index| format!("create {}", index.describe())));
+        changes.extend(Self::unique_conflicts(db).await?.into_iter().map(|conflict| format!("blocked: {}", conflict)));
+        Ok(changes)
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let conflicts = Self::unique_conflicts(db).await?;
+        if !conflicts.is_empty() {
+            return Err(MyError::ConflictError(format!(
+                "{}, merge or rename them and run `migrate` again",
+                conflicts.join("; ")
+            )));
+        }
+        drop_indexes(db, &Self::dropped()).await?;
+        create_indexes(db, &Self::created()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await?;
+        create_indexes(db, &Self::dropped()).await
+    }
+}
+
+/// 4: indexes of `username_history`, the redirects of renamed users are looked up by the old name
+struct UsernameHistory;
+
+impl UsernameHistory {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("username_history", doc! {"username": 1, "created_at": -1}),
+            IndexSpec::new("username_history", doc! {"user_id": 1, "created_at": -1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for UsernameHistory {
+    fn version(&self) -> i64 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "username_history"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, _db: &Database) -> Result<Vec<String>> {
+        Ok(Self::created().iter().map(|index| format!("create {}", index.describe())).collect())
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::created()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// 5: indexes of `contacts`, one document per pair of users
+struct Contacts;
+
+impl Contacts {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("contacts", doc! {"key": 1}).unique(),
+            IndexSpec::new("contacts", doc! {"users": 1, "updated_at": -1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for Contacts {
+    fn version(&self) -> i64 {
+        5
+    }
+
+    fn name(&self) -> &'static str {
+        "contacts"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, _db: &Database) -> Result<Vec<String>> {
+        Ok(Self::created().iter().map(|index| format!("create {}", index.describe())).collect())
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::created()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// 6: indexes of `group_dms` and `group_messages`, the queue of a participant is looked up through `pending`
+struct GroupDms;
+
+impl GroupDms {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("group_dms", doc! {"key": 1}).unique(),
+            IndexSpec::new("group_dms", doc! {"participants": 1, "updated_at": -1}),
+            IndexSpec::new("group_messages", doc! {"group_id": 1, "created_at": -1}),
+            IndexSpec::new("group_messages", doc! {"pending": 1, "created_at": 1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for GroupDms {
+    fn version(&self) -> i64 {
+        6
+    }
+
+    fn name(&self) -> &'static str {
+        "group_dms"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, _db: &Database) -> Result<Vec<String>> {
+        Ok(Self::created().iter().map(|index| format!("create {}", index.describe())).collect())
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::created()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// 7: focus of the sockets, replacing the `in_private` flag of `rooms` which is removed from the stored rooms
+struct SocketFocus;
+
+impl SocketFocus {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("sockets", doc! {"owned_username": 1, "focus": 1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for SocketFocus {
+    fn version(&self) -> i64 {
+        7
+    }
+
+    fn name(&self) -> &'static str {
+        "socket_focus"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, db: &Database) -> Result<Vec<String>> {
+        let mut changes = Self::created().iter().map(|index| format!("create {}", index.describe())).collect::<Vec<String>>();
+        let flagged = db.collection::<Document>("rooms")
+            .count_documents(doc! {"in_private": {"$exists": true}}, None)
+            .await?;
+        changes.push(format!("rooms: unset in_private on {} documents", flagged));
+        Ok(changes)
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::created()).await?;
+        db.collection::<Document>("rooms")
+            .update_many(doc! {"in_private": {"$exists": true}}, doc! {"$unset": {"in_private": ""}}, None)
+            .await?;
+        Ok(())
+    }
+
+    /// The `in_private` flags are not restored, nothing reads them anymore
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// 8: lookup of the sockets by the digest of their session token, see `auth::AuthUser`
+struct SocketSessions;
+
+impl SocketSessions {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("sockets", doc! {"session": 1}),
+        ]
+    }
+}
+
+#[async_trait]
+impl Migration for SocketSessions {
+    fn version(&self) -> i64 {
+        8
+    }
+
+    fn name(&self) -> &'static str {
+        "socket_sessions"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, _db: &Database) -> Result<Vec<String>> {
+        Ok(Self::created().iter().map(|index| format!("create {}", index.describe())).collect())
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        create_indexes(db, &Self::created()).await
+    }
+
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// 9: a single owner per room, enforced by a unique index on the owners so two first joiners can not both become owner <br/>
+/// The extra owners left by that race are demoted to members first, the earliest one keeps the room
+struct RoomOwners;
+
+impl RoomOwners {
+    fn created() -> Vec<IndexSpec> {
+        vec![
+            IndexSpec::new("room_members", doc! {"room": 1}).unique().partial(doc! {"role": "owner"}),
+        ]
+    }
+
+    /// IDs of the owners to demote, every owner of a room but the earliest
+    async fn extra_owners(db: &Database) -> Result<Vec<Bson>> {
+        let pipeline = vec![
+            doc! {"$match": {"role": "owner"}},
+            doc! {"$sort": {"created_at": 1, "_id": 1}},
+            doc! {"$group": {"_id": "$room", "owners": {"$push": "$_id"}}},
+            doc! {"$match": {"owners.1": {"$exists": true}}},
+        ];
+        let mut cursor = db.collection::<Document>("room_members").aggregate(pipeline, None).await?;
+        let mut extra: Vec<Bson> = Vec::new();
+        while cursor.advance().await? {
+            let group = cursor.deserialize_current()?;
+            if let Ok(owners) = group.get_array("owners") {
+                extra.extend(owners.iter().skip(1).cloned());
+            }
+        }
+        Ok(extra)
+    }
+}
+
+#[async_trait]
+impl Migration for RoomOwners {
+    fn version(&self) -> i64 {
+        9
+    }
+
+    fn name(&self) -> &'static str {
+        "room_owners"
+    }
+
+    fn created_indexes(&self) -> Vec<IndexSpec> {
+        Self::created()
+    }
+
+    async fn plan(&self, db: &Database) -> Result<Vec<String>> {
+        let mut changes = vec![format!("room_members: demote {} extra owners", Self::extra_owners(db).await?.len())];
+        changes.extend(Self::created().iter().map(|index| format!("create {}", index.describe())));
+        Ok(changes)
+    }
+
+    async fn up(&self, db: &Database) -> Result<()> {
+        let extra = Self::extra_owners(db).await?;
+        if !extra.is_empty() {
+            db.collection::<Document>("room_members")
+                .update_many(doc! {"_id": {"$in": extra}}, doc! {"$set": {"role": "member"}}, None)
+                .await?;
+        }
+        create_indexes(db, &Self::created()).await
+    }
+
+    /// The demoted owners stay members
+    async fn down(&self, db: &Database) -> Result<()> {
+        drop_indexes(db, &Self::created()).await
+    }
+}
+
+/// Indexes expected once every migration is applied, the indexes created by the migrations in order,
+/// less the ones dropped by a later migration
+fn index_plan() -> Vec<IndexSpec> {
+    migrations().iter().fold(Vec::new(), |mut plan, migration| {
+        let dropped = migration.dropped_indexes();
+        plan.retain(|index| !dropped.iter().any(|drop| drop.collection == index.collection && drop.keys == index.keys));
+        plan.extend(migration.created_indexes());
+        plan
+    })
+}
+
+/// Difference between `index_plan` and the indexes of the database, as `collection: index` entries
+#[derive(Debug, Clone, Default)]
+pub struct IndexDrift {
+    pub missing: Vec<String>,
+    /// same keys as a planned index but other options, e.g. not unique
+    pub mismatched: Vec<String>,
+    /// not in the plan, only reported: they cost writes but do not break anything
+    pub unexpected: Vec<String>,
+}
+
+impl IndexDrift {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Compare the indexes of every planned collection with `index_plan`
+pub async fn check_indexes(db: &DB) -> Result<IndexDrift> {
+    let database = db.database.clone().ok_or(MyError::OwnError(String::from("Database not found")))?;
+    let plan = index_plan();
+    let mut collections = plan.iter().map(|index| index.collection).collect::<Vec<&str>>();
+    collections.sort();
+    collections.dedup();
+
+    let mut drift = IndexDrift::default();
+    for collection in collections {
+        let mut existing: Vec<IndexModel> = Vec::new();
+        match database.collection::<Document>(collection).list_indexes(None).await {
+            Ok(mut cursor) => {
+                while cursor.advance().await? {
+                    existing.push(cursor.deserialize_current()?);
+                }
+            }
+            // the collection does not exist yet, every index is missing
+            Err(e) if is_already_dropped(&e) => {}
+            Err(e) => return Err(MyError::MongoError(e)),
+        }
+
+        for index in plan.iter().filter(|index| index.collection.eq(collection)) {
+            match existing.iter().find(|model| model.keys == index.keys) {
+                None => drift.missing.push(index.describe()),
+                Some(model) => {
+                    let options = model.options.as_ref();
+                    let unique = options.and_then(|options| options.unique).unwrap_or(false);
+                    let expire_after = options.and_then(|options| options.expire_after);
+                    let partial = options.and_then(|options| options.partial_filter_expression.as_ref());
+                    if unique != index.unique || expire_after != index.expire_after || partial != index.partial.as_ref() {
+                        drift.mismatched.push(index.describe());
+                    }
+                }
+            }
+        }
+        for model in existing.iter().filter(|model| !model.keys.contains_key("_id")) {
+            if !plan.iter().any(|index| index.collection.eq(collection) && index.keys == model.keys) {
+                let name = model.options.as_ref().and_then(|options| options.name.clone()).unwrap_or(index_name(&model.keys));
+                drift.unexpected.push(format!("{}: index {}", collection, name));
+            }
+        }
+    }
+    Ok(drift)
+}
@@ -1,27 +1,100 @@
+use std::collections::HashMap;
+use std::fmt;
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
+use crate::content::{Entity, LinkPreview};
+use crate::moderation::MessageKind;
+use crate::telemetry::Redacted;
+use crate::db_model::{DmPolicy, DeliveryStatus, Focus, MentionKind, NotificationKind, ReportAction, ReportKind, ReportStatus, ReviewStatus, RoomRole, RoomStatus, UsernameKind};
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 pub struct GeneralRequest {
+    /// Not trusted, the sender is the identity of the connection (see `SocketState::socket_identity`)
+    #[serde(default)]
     pub sender: String,
     pub room: String,
     pub message: String,
+    /// ID of the message being replied to, the reply is attached to the thread of that message
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// IDs returned by the upload endpoint, the attachments must be uploaded by the sender
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// `join_room` only: leave out the messages of the users blocked by `sender`, from the history and the live messages
+    #[serde(default)]
+    pub hide_blocked: bool,
 }
 
-#[derive(Debug, Serialize, Clone)]
+// the message bodies are only printed with `LOG_MESSAGE_BODIES=true`, see `telemetry::Redacted`
+impl fmt::Debug for GeneralRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneralRequest")
+            .field("sender", &self.sender)
+            .field("room", &self.room)
+            .field("message", &Redacted(&self.message))
+            .field("reply_to", &self.reply_to)
+            .field("attachments", &self.attachments)
+            .field("hide_blocked", &self.hide_blocked)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub struct GeneralResponse {
+    pub id: Option<String>,
     pub sender: String,
     pub room: String,
     pub message: String,
+    pub reply_to: Option<String>,
+    pub attachments: Vec<String>,
+    pub entities: Vec<Entity>,
+    pub sender_profile: Option<ProfileSummary>,
     pub date_time: DateTime<chrono::Utc>,
 }
 
+impl fmt::Debug for GeneralResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneralResponse")
+            .field("id", &self.id)
+            .field("sender", &self.sender)
+            .field("room", &self.room)
+            .field("message", &Redacted(&self.message))
+            .field("reply_to", &self.reply_to)
+            .field("attachments", &self.attachments)
+            .field("entities", &self.entities)
+            .field("sender_profile", &self.sender_profile)
+            .field("date_time", &self.date_time)
+            .finish()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub username: String,
     pub generated_username: String,
 }
 
+/// `user_handle`, links the owned `username` to the connection <br/>
+/// `generated_username` has to be the one sent with `username` on connect, and `token` an identity token
+/// signed for `username` (see `auth::IdentityVerifier`)
+#[derive(Clone, Deserialize)]
+pub struct UserHandleReq {
+    pub username: String,
+    pub generated_username: String,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for UserHandleReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserHandleReq")
+            .field("username", &self.username)
+            .field("generated_username", &self.generated_username)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct UserExists {
     pub exists: bool,
@@ -29,50 +102,721 @@ pub struct UserExists {
     pub generated_username: String,
 }
 
+/// New owned username requested with `rename_user`, the current one is the one linked to the connection
+#[derive(Debug, Deserialize, Clone)]
+pub struct RenameUserReq {
+    pub username: String,
+}
+
+/// Sent to the user, its DM contacts and its rooms, `old_username` keeps reaching the user until `redirect_until`
+#[derive(Debug, Serialize, Clone)]
+pub struct UserRenamed {
+    pub old_username: String,
+    pub new_username: String,
+    pub redirect_until: DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct UsernameHistoryResp {
+    pub kind: UsernameKind,
+    pub username: String,
+    pub replaced_by: String,
+    pub redirect_until: Option<DateTime<chrono::Utc>>,
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// `PATCH /api/users/:username`, the fields left out are kept and an empty string clears the field <br/>
+/// A new `status_text` without `status_until` does not expire
+#[derive(Debug, Deserialize, Default)]
+pub struct ProfilePatch {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    /// ID of an image attachment uploaded by the user
+    pub avatar: Option<String>,
+    pub status_text: Option<String>,
+    pub status_until: Option<DateTime<chrono::Utc>>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ProfileResp {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub status_text: Option<String>,
+    pub status_until: Option<DateTime<chrono::Utc>>,
+    pub timezone: Option<String>,
+    pub online: bool,
+    pub updated_at: DateTime<chrono::Utc>,
+}
+
+/// Part of the profile carried along the messages of the user
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProfileSummary {
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub status_text: Option<String>,
+}
+
+/// Answer of `/api/in-private`, `in_private` is set while a socket of the user is viewing a DM <br/>
+/// Also the `joined_private`/`left_private` reply of the deprecated socket events, with the focus of that socket
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InPrivate {
     pub in_private: bool,
-    pub username: String
+    pub username: String,
+    /// conversations viewed by the sockets of the user, on every instance
+    #[serde(default)]
+    pub focus: Vec<Focus>,
+}
+
+/// Payload of the deprecated `private_joined`/`private_left`, the `username` and `in_private` sent by the older clients
+/// are ignored. `private_joined` needs the `peer` of the DM, `private_left` leaves whichever DM is focused
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrivateFocusReq {
+    #[serde(default)]
+    pub peer: Option<String>,
+}
+
+/// Sent with `focus` when the socket opens a conversation, `None` when it leaves it. Echoed back as `focused`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusReq {
+    pub focus: Option<Focus>,
+}
+
+/// `conversation_read`, sent to the sockets of the user when opening a conversation marked its unread backlog as read
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationRead {
+    pub focus: Focus,
+    pub notifications: u64,
+    pub mentions: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserResp {
     pub owned_uname: String,
     pub cur_gen_uname: String,
+    /// bearer token of the HTTP API, valid while the socket that sent `user_handle` is connected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
     pub updated_at: DateTime<chrono::Utc>,
     pub created_at: DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Serialize, Clone)]
 pub struct Message {
+    pub id: Option<String>,
     pub sender: String,
     pub room: String,
     pub message: String,
+    pub reply_to: Option<String>,
+    pub reply_count: i64,
+    pub last_reply_at: Option<DateTime<chrono::Utc>>,
+    pub attachments: Vec<String>,
+    /// spans parsed from `message` (mentions, links, code, markdown), see `content::parse_entities`
+    pub entities: Vec<Entity>,
+    pub previews: Vec<LinkPreview>,
     pub date_time: DateTime<chrono::Utc>,
 }
 
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("id", &self.id)
+            .field("sender", &self.sender)
+            .field("room", &self.room)
+            .field("message", &Redacted(&self.message))
+            .field("reply_to", &self.reply_to)
+            .field("reply_count", &self.reply_count)
+            .field("last_reply_at", &self.last_reply_at)
+            .field("attachments", &self.attachments)
+            .field("entities", &self.entities)
+            .field("previews", &self.previews)
+            .field("date_time", &self.date_time)
+            .finish()
+    }
+}
+
+/// `profiles` holds the profile of every sender of `messages`, by owned username
 #[derive(Serialize)]
 pub struct Messages {
     pub messages: Vec<Message>,
+    pub profiles: HashMap<String, ProfileSummary>,
+}
+
+/// Root message of a thread along with a page of its replies (oldest first)
+#[derive(Serialize, Debug)]
+pub struct ThreadResponse {
+    pub root: Message,
+    pub replies: PaginationResponse<Message>,
+}
+
+/// Emitted as `thread_updated` to the room and to the clients watching the thread, whenever a reply is added
+#[derive(Serialize, Debug, Clone)]
+pub struct ThreadUpdate {
+    pub root_id: String,
+    pub room: String,
+    pub reply_count: i64,
+    pub last_reply_at: Option<DateTime<chrono::Utc>>,
+    pub reply: Message,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ThreadWatch {
+    pub id: String,
+}
+
+/// Emitted as `link_previews` to the room once the previews of the links in a message are fetched
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkPreviews {
+    pub message_id: String,
+    pub room: String,
+    pub previews: Vec<LinkPreview>,
+}
+
+/// Emitted as `mention` to the owned username room of the mentioned user, also listed by `/api/mentions`
+#[derive(Serialize, Debug, Clone)]
+pub struct MentionResp {
+    pub id: String,
+    pub sender: String,
+    pub room: String,
+    pub message_id: String,
+    pub message: String,
+    pub kind: MentionKind,
+    pub read: bool,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MentionFilter {
+    #[serde(default)]
+    pub unread: bool,
+}
+
+/// Mark the given mentions as read, all of them when `ids` is empty
+#[derive(Deserialize, Debug)]
+pub struct MentionsRead {
+    #[serde(default)]
+    pub ids: Vec<String>,
+}
+
+/// Change the role of `username` in `room`, only allowed for the owner of the room
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoomRoleReq {
+    pub room: String,
+    pub username: String,
+    pub role: RoomRole,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RoomRoleResp {
+    pub room: String,
+    pub username: String,
+    pub role: RoomRole,
+}
+
+/// Emitted as `notification` to the owned username room of the receiver
+#[derive(Serialize, Debug, Clone)]
+pub struct NotificationResp {
+    pub id: String,
+    pub kind: NotificationKind,
+    pub sender: Option<String>,
+    pub message: String,
+    pub reference: Option<String>,
+    pub read: bool,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+/// Page of the inbox along with the total unread count, also emitted as `notifications` on `user_handle`
+#[derive(Serialize, Debug, Clone)]
+pub struct NotificationInbox {
+    pub notifications: Vec<NotificationResp>,
+    pub unread: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotificationFilter {
+    #[serde(default)]
+    pub unread: bool,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotificationsRead {
+    #[serde(default)]
+    pub ids: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockList {
+    pub username: String,
+    pub blocked: Vec<String>,
+    pub muted: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockListKind {
+    Blocked,
+    Muted,
+}
+
+/// Add or remove `target` from the `list` of the caller
+#[derive(Deserialize, Debug)]
+pub struct BlockListReq {
+    pub target: String,
+    pub list: BlockListKind,
+}
+
+/// How a user treats the messages of another user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserRelation {
+    Normal,
+    /// delivered without notifications
+    Muted,
+    /// not delivered at all, the sender only gets a generic failure
+    Blocked,
+}
+
+/// Moderator restriction on a user, checked before accepting their messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    Muted(DateTime<chrono::Utc>),
+    Banned,
+}
+
+/// Flag a message or a user through the `report` event or `POST /api/reports` <br/>
+/// `target` is the ID of the message for `message`/`private` reports and the owned username for `user` reports <br/>
+/// The reporter is the owned username linked to the connection, or to the session token over HTTP
+#[derive(Deserialize, Clone)]
+pub struct ReportReq {
+    pub kind: ReportKind,
+    pub target: String,
+    pub reason: String,
+}
+
+impl fmt::Debug for ReportReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReportReq")
+            .field("kind", &self.kind)
+            .field("target", &self.target)
+            .field("reason", &Redacted(&self.reason))
+            .finish()
+    }
+}
+
+/// Emitted as `report_received` to the reporter, and returned by the review queue endpoints
+#[derive(Serialize, Debug, Clone)]
+pub struct ReportResp {
+    pub id: String,
+    pub reporter: String,
+    pub kind: ReportKind,
+    pub target: String,
+    pub reported_user: String,
+    pub room: Option<String>,
+    pub reason: String,
+    pub status: ReportStatus,
+    pub moderator: Option<String>,
+    pub action: Option<ReportAction>,
+    pub note: Option<String>,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+/// Review queue query, only the moderators configured with `MODERATORS` and holding `MODERATOR_TOKEN` get an answer
+#[derive(Deserialize, Debug)]
+pub struct ReportFilter {
+    pub status: Option<ReportStatus>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// `mute_minutes` is only used by the `mute` action, 60 minutes by default
+#[derive(Deserialize, Debug)]
+pub struct ReportResolve {
+    pub action: ReportAction,
+    pub note: Option<String>,
+    pub mute_minutes: Option<i64>,
+}
+
+/// Emitted as `message_deleted` when a moderator removes a message, `room` is `None` for private messages
+#[derive(Serialize, Debug, Clone)]
+pub struct MessageDeleted {
+    pub id: String,
+    pub room: Option<String>,
+}
+
+/// Emitted as `message_held` to the sender when the moderation pipeline holds the message for review,
+/// the message is delivered once a moderator approves it (see `approve_held_message`)
+#[derive(Serialize, Debug, Clone)]
+pub struct MessageHeld {
+    pub id: String,
+    pub target: String,
+    pub reason: String,
+}
+
+/// Held messages query, only the moderators configured with `MODERATORS` and holding `MODERATOR_TOKEN` get an answer, the pending messages unless `status` is given
+#[derive(Deserialize, Debug)]
+pub struct HeldFilter {
+    pub status: Option<ReviewStatus>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Returned by the held message endpoints, `target` is the room, the receiver or the ID of the group DM
+#[derive(Serialize, Debug, Clone)]
+pub struct HeldMessageResp {
+    pub id: String,
+    pub kind: MessageKind,
+    pub sender: String,
+    pub target: String,
+    pub message: String,
+    pub reply_to: Option<String>,
+    pub attachments: Vec<String>,
+    pub reason: String,
+    pub status: ReviewStatus,
+    pub reviewed_by: Option<String>,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+/// Emitted as `event_error` when an event could not be processed
+#[derive(Serialize, Debug, Clone)]
+pub struct EventError {
+    pub event: String,
+    pub message: String,
 }
 
 /// Struct for handling the private messages <br/>
-/// It utilizes the `sender` and `receiver` (socket IDs) fields to send the message to the respective user <br/>
-/// For fetching the socket IDs the API endpoint can be used
+/// `sender` and `receiver` are owned usernames, the message is delivered to every socket joined to the owned username room
+/// of the receiver (see `handle_user_join`), so the socket IDs are never exposed and reconnects do not break the conversation
 ///
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PrivateMessage {
+    pub id: Option<String>,
     pub sender: String,
     pub message: String,
     pub receiver: String,
+    pub attachments: Vec<String>,
+    pub status: DeliveryStatus,
+    #[serde(default)]
+    pub sender_profile: Option<ProfileSummary>,
     pub date_time: DateTime<chrono::Utc>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl fmt::Debug for PrivateMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateMessage")
+            .field("id", &self.id)
+            .field("sender", &self.sender)
+            .field("message", &Redacted(&self.message))
+            .field("receiver", &self.receiver)
+            .field("attachments", &self.attachments)
+            .field("status", &self.status)
+            .field("sender_profile", &self.sender_profile)
+            .field("date_time", &self.date_time)
+            .finish()
+    }
+}
+
+/// `create_group_dm`, the owned username linked to the connection is added to `participants`
+#[derive(Debug, Deserialize, Clone)]
+pub struct GroupDmReq {
+    pub participants: Vec<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct GroupDmResp {
+    pub id: String,
+    pub name: Option<String>,
+    pub participants: Vec<String>,
+    pub created_by: String,
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// `group_message`, the sender is the owned username linked to the connection
+#[derive(Clone, Deserialize)]
+pub struct GroupMessageReq {
+    pub group: String,
+    pub message: String,
+    #[serde(default)]
+    pub attachments: Vec<String>,
+}
+
+impl fmt::Debug for GroupMessageReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupMessageReq")
+            .field("group", &self.group)
+            .field("message", &Redacted(&self.message))
+            .field("attachments", &self.attachments)
+            .finish()
+    }
+}
+
+/// Emitted as `group_resp` to every participant, `pending` lists the participants which have not confirmed it yet
+#[derive(Clone, Serialize)]
+pub struct GroupMessage {
+    pub id: String,
+    pub group: String,
+    pub sender: String,
+    pub message: String,
+    pub attachments: Vec<String>,
+    pub pending: Vec<String>,
+    pub sender_profile: Option<ProfileSummary>,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+impl fmt::Debug for GroupMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupMessage")
+            .field("id", &self.id)
+            .field("group", &self.group)
+            .field("sender", &self.sender)
+            .field("message", &Redacted(&self.message))
+            .field("attachments", &self.attachments)
+            .field("pending", &self.pending)
+            .field("sender_profile", &self.sender_profile)
+            .field("date_time", &self.date_time)
+            .finish()
+    }
+}
+
+/// Emitted as `group_status` to the sender when a participant confirms a group message
+#[derive(Clone, Debug, Serialize)]
+pub struct GroupStatus {
+    pub id: String,
+    pub group: String,
+    pub username: String,
+    pub status: DeliveryStatus,
+}
+
+/// Page of the history of a group DM, only readable by its participants
+#[derive(Deserialize, Debug)]
+pub struct GroupHistoryQuery {
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+/// Sent by the receiver with the IDs of the private messages it received, clearing them from its queue
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrivateDelivered {
+    pub ids: Vec<String>,
+}
+
+/// Emitted as `private_status` to the sender when the status of a private message changes
+#[derive(Clone, Debug, Serialize)]
+pub struct PrivateStatus {
+    pub id: String,
+    pub receiver: String,
+    pub status: DeliveryStatus,
+}
+
+/// `receiver` is the owned username of the recipient <br/>
+/// `sender` is ignored by `handle_private`, the sender is the owned username linked to the connection through `user_handle`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PrivateMessageReq {
     pub sender: Option<String>,
     pub message: String,
     pub receiver: String,
+    #[serde(default)]
+    pub attachments: Vec<String>,
+}
+
+impl fmt::Debug for PrivateMessageReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateMessageReq")
+            .field("sender", &self.sender)
+            .field("message", &Redacted(&self.message))
+            .field("receiver", &self.receiver)
+            .field("attachments", &self.attachments)
+            .finish()
+    }
+}
+
+/// Attachment metadata as returned by the upload and metadata endpoints
+#[derive(Serialize, Debug, Clone)]
+pub struct AttachmentResp {
+    pub id: String,
+    pub name: String,
+    pub size: i64,
+    pub content_type: String,
+    pub checksum: String,
+    pub uploader: String,
+    pub created_at: DateTime<chrono::Utc>,
+}
+
+/// Friend request actions and contact removal, the caller is the acting user and `target` the other one
+#[derive(Deserialize, Debug)]
+pub struct ContactReq {
+    pub target: String,
+}
+
+/// Where a user stands in the contact list of the owner of the list
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContactState {
+    Accepted,
+    /// request received, waiting for the owner of the list
+    Incoming,
+    /// request sent by the owner of the list
+    Outgoing,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ContactResp {
+    pub username: String,
+    pub state: ContactState,
+    pub online: bool,
+    pub profile: Option<ProfileSummary>,
+    pub since: DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ContactList {
+    pub contacts: Vec<ContactResp>,
+    pub incoming: Vec<ContactResp>,
+    pub outgoing: Vec<ContactResp>,
+}
+
+/// `contact_updated`, sent to both users of a pair, `state` is `None` once the request or the contact is gone
+#[derive(Serialize, Debug, Clone)]
+pub struct ContactUpdated {
+    pub username: String,
+    pub state: Option<ContactState>,
+}
+
+/// `contact_presence`, sent to the contacts of a user when its first socket links or its last one disconnects
+#[derive(Serialize, Debug, Clone)]
+pub struct ContactPresence {
+    pub username: String,
+    pub online: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DmPolicyReq {
+    pub dm_policy: DmPolicy,
+}
+
+/// Live socket as listed by the admin API, `user` is the owned username linked through `user_handle`
+#[derive(Serialize, Debug, Clone)]
+pub struct AdminSocket {
+    pub id: String,
+    pub user: Option<String>,
+    pub rooms: Vec<String>,
+}
+
+/// System announcement, broadcast to every socket or only to `room`
+#[derive(Deserialize, Debug)]
+pub struct AnnouncementReq {
+    pub message: String,
+    pub room: Option<String>,
+}
+
+/// Emitted as `announcement`
+#[derive(Serialize, Debug, Clone)]
+pub struct Announcement {
+    pub message: String,
+    pub room: Option<String>,
+    pub date_time: DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RoomStatusReq {
+    pub status: RoomStatus,
+}
+
+/// Emitted as `room_status` to the room when an admin changes its status
+#[derive(Serialize, Debug, Clone)]
+pub struct RoomStatusResp {
+    pub room: String,
+    pub status: RoomStatus,
+}
+
+/// Size of a collection, `size`, `storage_size` and `indexes` are left out when `collStats` is not available
+#[derive(Serialize, Debug, Clone)]
+pub struct CollectionStats {
+    pub name: String,
+    pub documents: u64,
+    pub size: Option<i64>,
+    pub storage_size: Option<i64>,
+    pub indexes: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct LatencyQuery {
+    pub samples: Option<usize>,
+}
+
+/// Round trips of `ping` and of a single document read, in milliseconds
+#[derive(Serialize, Debug, Clone)]
+pub struct DbLatency {
+    pub ping_ms: Vec<f64>,
+    pub read_ms: Vec<f64>,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NamespaceSockets {
+    pub namespace: String,
+    pub sockets: usize,
+    /// sockets which linked an owned username through `user_handle`
+    pub linked: usize,
+    pub rooms: usize,
+}
+
+/// Emit `diagnostics_ping` to `socket` and wait up to `timeout_ms` (5000 by default) for the acknowledgement
+#[derive(Deserialize, Debug)]
+pub struct LoopbackReq {
+    pub socket: String,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LoopbackResp {
+    pub socket: String,
+    pub acknowledged: bool,
+    pub round_trip_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransactionSupport {
+    pub supported: bool,
+    /// `standalone`, `replica_set` or `sharded`
+    pub topology: String,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DependencyCheck {
+    pub name: String,
+    pub ok: bool,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Served by `/api/status`, `status` is `ok`, `degraded` or `shutting_down`
+#[derive(Serialize, Debug, Clone)]
+pub struct StatusReport {
+    pub status: String,
+    pub version: String,
+    pub uptime_secs: u64,
+    pub sockets: usize,
+    pub checks: Vec<DependencyCheck>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AdminAuditResp {
+    pub id: String,
+    pub actor: String,
+    /// `X-Admin-User` header of the request, unverified
+    pub claimed_actor: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub date_time: DateTime<chrono::Utc>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+/// Where the moderated message is going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    Room,
+    Private,
+    Group,
+}
+
+/// Inbound message as seen by the filters, `target` is the room, the receiver or the ID of the group DM
+#[derive(Debug, Clone)]
+pub struct ModerationInput {
+    pub kind: MessageKind,
+    pub sender: String,
+    pub target: String,
+    pub text: String,
+}
+
+/// Decision of a single filter <br/>
+/// `Redact` carries the rewritten text which is handed to the next filters,
+/// `Hold` and `Reject` stop the pipeline and carry the reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationAction {
+    Allow,
+    Redact(String),
+    Hold(String),
+    Reject(String),
+}
+
+/// Stored form of a decision, the redacted text is not kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Allow,
+    Redact,
+    Hold,
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub filter: String,
+    pub verdict: Verdict,
+    pub reason: Option<String>,
+}
+
+/// Result of the whole pipeline: the final verdict, the text to store (redacted if needed) and every filter decision
+#[derive(Debug, Clone)]
+pub struct ModerationOutcome {
+    pub verdict: Verdict,
+    pub text: String,
+    pub reason: Option<String>,
+    pub decisions: Vec<Decision>,
+}
+
+/// Hook for custom filters, registered with `ModerationPipeline::with_filter`
+#[async_trait]
+pub trait ModerationFilter: Send + Sync + Debug {
+    fn name(&self) -> &'static str;
+    async fn check(&self, input: &ModerationInput) -> ModerationAction;
+}
+
+/// Runs the filters in order on every inbound message
+#[derive(Debug, Clone, Default)]
+pub struct ModerationPipeline {
+    filters: Vec<Arc<dyn ModerationFilter>>,
+}
+
+impl ModerationPipeline {
+    pub fn new() -> Self {
+        Self { filters: vec![] }
+    }
+
+    pub fn with_filter(mut self, filter: Arc<dyn ModerationFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Blocklist (when configured) followed by the spam heuristic <br/>
+    /// - `MODERATION_BLOCKLIST_FILE`: one entry per line, plain words are matched case-insensitively on word boundaries,
+    ///   lines prefixed with `re:` are regular expressions, empty lines and lines starting with `#` are skipped <br/>
+    /// - `MODERATION_BLOCKLIST_ACTION`: `redact` (default), `hold` or `reject`
+    pub fn from_env() -> Self {
+        let mut pipeline = Self::new();
+
+        if let Ok(path) = std::env::var("MODERATION_BLOCKLIST_FILE") {
+            match std::fs::read_to_string(&path) {
+                Ok(content) => {
+                    let action = std::env::var("MODERATION_BLOCKLIST_ACTION").unwrap_or_default();
+                    match BlocklistFilter::parse(&content, BlocklistAction::from_name(&action)) {
+                        Ok(filter) => {
+                            info!("Moderation blocklist loaded from {}", path);
+                            pipeline = pipeline.with_filter(Arc::new(filter));
+                        }
+                        Err(e) => error!("Invalid moderation blocklist {}: {}", path, e)
+                    }
+                }
+                Err(e) => error!("Error reading moderation blocklist {}: {:?}", path, e)
+            }
+        }
+
+        pipeline.with_filter(Arc::new(SpamFilter::default()))
+    }
+
+    pub async fn run(&self, input: &ModerationInput) -> ModerationOutcome {
+        let mut current = input.clone();
+        let mut decisions: Vec<Decision> = Vec::new();
+        let mut verdict = Verdict::Allow;
+
+        for filter in &self.filters {
+            let action = filter.check(&current).await;
+            let (step, reason) = match action {
+                ModerationAction::Allow => (Verdict::Allow, None),
+                ModerationAction::Redact(text) => {
+                    current.text = text;
+                    (Verdict::Redact, None)
+                }
+                ModerationAction::Hold(reason) => (Verdict::Hold, Some(reason)),
+                ModerationAction::Reject(reason) => (Verdict::Reject, Some(reason)),
+            };
+            decisions.push(Decision {
+                filter: filter.name().to_string(),
+                verdict: step,
+                reason: reason.clone(),
+            });
+
+            match step {
+                Verdict::Hold | Verdict::Reject => {
+                    return ModerationOutcome { verdict: step, text: current.text, reason, decisions };
+                }
+                Verdict::Redact => verdict = Verdict::Redact,
+                Verdict::Allow => {}
+            }
+        }
+
+        ModerationOutcome { verdict, text: current.text, reason: None, decisions }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlocklistAction {
+    Redact,
+    Hold,
+    Reject,
+}
+
+impl BlocklistAction {
+    pub fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "hold" => BlocklistAction::Hold,
+            "reject" => BlocklistAction::Reject,
+            _ => BlocklistAction::Redact,
+        }
+    }
+}
+
+/// Word and regex blocklist, matches are masked with `*` when redacting
+#[derive(Debug, Clone)]
+pub struct BlocklistFilter {
+    patterns: Vec<Regex>,
+    action: BlocklistAction,
+}
+
+impl BlocklistFilter {
+    pub fn new(words: &[String], patterns: Vec<Regex>, action: BlocklistAction) -> Result<Self, regex::Error> {
+        let mut all = Vec::with_capacity(words.len() + patterns.len());
+        for word in words {
+            all.push(Regex::new(&format!(r"(?i)\b{}\b", regex::escape(word)))?);
+        }
+        all.extend(patterns);
+
+        Ok(Self { patterns: all, action })
+    }
+
+    /// Parse the content of the blocklist file, see `ModerationPipeline::from_env`
+    pub fn parse(content: &str, action: BlocklistAction) -> Result<Self, regex::Error> {
+        let mut words: Vec<String> = Vec::new();
+        let mut patterns: Vec<Regex> = Vec::new();
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match line.strip_prefix("re:") {
+                Some(pattern) => patterns.push(Regex::new(pattern)?),
+                None => words.push(line.to_string())
+            }
+        }
+
+        Self::new(&words, patterns, action)
+    }
+}
+
+#[async_trait]
+impl ModerationFilter for BlocklistFilter {
+    fn name(&self) -> &'static str {
+        "blocklist"
+    }
+
+    async fn check(&self, input: &ModerationInput) -> ModerationAction {
+        if !self.patterns.iter().any(|pattern| pattern.is_match(&input.text)) {
+            return ModerationAction::Allow;
+        }
+
+        match self.action {
+            BlocklistAction::Hold => ModerationAction::Hold("blocked content".to_string()),
+            BlocklistAction::Reject => ModerationAction::Reject("blocked content".to_string()),
+            BlocklistAction::Redact => {
+                let mut text = input.text.clone();
+                for pattern in &self.patterns {
+                    text = pattern.replace_all(&text, |caps: &regex::Captures| "*".repeat(caps[0].chars().count())).to_string();
+                }
+                ModerationAction::Redact(text)
+            }
+        }
+    }
+}
+
+/// sender -> recent (text, link count, time)
+pub type SpamHistory = HashMap<String, VecDeque<(String, usize, Instant)>>;
+
+/// Spam heuristic <br/>
+/// - rejects the same text sent by the same sender more than `max_repeats` times within `window` <br/>
+/// - holds messages with more than `max_links` links, and rejects senders posting more than `max_links_per_window` links within `window`
+#[derive(Debug)]
+pub struct SpamFilter {
+    pub max_repeats: usize,
+    pub max_links: usize,
+    pub max_links_per_window: usize,
+    pub window: Duration,
+    history: Mutex<SpamHistory>,
+}
+
+impl Default for SpamFilter {
+    fn default() -> Self {
+        Self {
+            max_repeats: 3,
+            max_links: 5,
+            max_links_per_window: 10,
+            window: Duration::from_secs(30),
+            history: Mutex::new(SpamHistory::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ModerationFilter for SpamFilter {
+    fn name(&self) -> &'static str {
+        "spam"
+    }
+
+    async fn check(&self, input: &ModerationInput) -> ModerationAction {
+        let text = input.text.trim().to_lowercase();
+        let links = text.matches("http://").count() + text.matches("https://").count();
+        if links > self.max_links {
+            return ModerationAction::Hold(format!("{} links in a single message", links));
+        }
+
+        let now = Instant::now();
+        let mut _history = self.history.lock().await;
+        // forget the senders without recent messages so the map does not grow forever
+        _history.retain(|_, recent| recent.back().map(|(_, _, at)| now.duration_since(*at) < self.window).unwrap_or(false));
+
+        let recent = _history.entry(input.sender.clone()).or_default();
+        while recent.front().map(|(_, _, at)| now.duration_since(*at) >= self.window).unwrap_or(false) {
+            recent.pop_front();
+        }
+
+        let repeats = recent.iter().filter(|(previous, _, _)| previous.eq(&text)).count();
+        let recent_links: usize = recent.iter().map(|(_, links, _)| links).sum();
+        recent.push_back((text, links, now));
+
+        if repeats >= self.max_repeats {
+            ModerationAction::Reject("repeated message".to_string())
+        } else if recent_links + links > self.max_links_per_window {
+            ModerationAction::Reject("too many links".to_string())
+        } else {
+            ModerationAction::Allow
+        }
+    }
+}
@@ -1,13 +1,17 @@
 use std::sync::Arc;
 use socketioxide::extract::{SocketRef, State};
-use tracing::info;
-use crate::socket_handlers::{handle_removal, handle_join_room, handle_message, handle_private, handle_disconnect_socket, handle_user_join, handle_private_joined, handle_private_left, handle_notify};
-use crate::socket_state::SocketState;
+use tracing::{field, info, info_span};
+use crate::socket_handlers::{handle_removal, handle_join_room, handle_message, handle_private, handle_disconnect_socket, handle_user_join, handle_focus, handle_private_joined, handle_private_left, handle_notify, handle_watch_thread, handle_unwatch_thread, handle_set_room_role, handle_private_delivered, handle_report, handle_rename_user, handle_create_group_dm, handle_group_message, handle_group_delivered};
+use crate::metrics::event_emitted;
+use crate::socket_state::{GeneratedName, SocketState};
 
 /// todo: INITIALIZE THE SOCKET IDS INTO A VARIABLE PAIRED TO A USERNAME <br/>
 /// and store the list of key value pair in the memory store or in the DB
 pub async fn on_connect(socket: SocketRef, socket_state: State<Arc<SocketState>>) {
-    info!("Socket Connected: {:?}", socket.id);
+    // parent of the event spans, `user` is recorded once the socket sends its owned username
+    let span = info_span!("connection", socket = %socket.id, user = field::Empty);
+    info!(parent: &span, "Socket Connected: {:?}", socket.id);
+    socket.extensions.insert(span.clone());
 
     // generator code kept in one line else prone to MessageHandler Errors
     let name = names::Generator::default().next().unwrap();
@@ -15,6 +19,7 @@ pub async fn on_connect(socket: SocketRef, socket_state: State<Arc<SocketState>>
     // JOIN THE USERNAME AND CREATE A PRIVATE CHAT ROOM
     // AND NEVER EXPOSE THE ACTUAL SOCKET ID OF THE USER TO THE FRONTEND
     socket.join(name.clone()).ok();
+    socket.extensions.insert(GeneratedName(name.clone()));
     // socket.join(socket.id.clone()).ok();
 
     // todo: NOT Storing the socket id and the name in the memory store
@@ -23,14 +28,20 @@ pub async fn on_connect(socket: SocketRef, socket_state: State<Arc<SocketState>>
     // self.socket_map.write().await.insert(name.clone(), socket_id.clone());
 
     socket_state.db.insert_socket_name(name.clone(), socket.id.clone().to_string()).await.unwrap();
-    info!("Generated Username (private group): {:?}", name.clone());
+    info!(parent: &span, "Generated Username (private group): {:?}", name.clone());
 
+    event_emitted("username");
     socket.emit("username", name.clone()).ok();
     // The first and foremost event to be called when the socket is connected
     // socket.on("default", handle_default);
 
     socket.on("user_handle",handle_user_join);
 
+    socket.on("rename_user", handle_rename_user);
+
+    socket.on("focus", handle_focus);
+
+    // deprecated aliases of `focus` kept for the older clients
     socket.on("private_joined", handle_private_joined);
 
     socket.on("private_left", handle_private_left);
@@ -41,8 +52,24 @@ pub async fn on_connect(socket: SocketRef, socket_state: State<Arc<SocketState>>
 
     socket.on("private", handle_private);
 
+    socket.on("private_delivered", handle_private_delivered);
+
+    socket.on("create_group_dm", handle_create_group_dm);
+
+    socket.on("group_message", handle_group_message);
+
+    socket.on("group_delivered", handle_group_delivered);
+
     socket.on("message", handle_message);
 
+    socket.on("watch_thread", handle_watch_thread);
+
+    socket.on("unwatch_thread", handle_unwatch_thread);
+
+    socket.on("set_room_role", handle_set_room_role);
+
+    socket.on("report", handle_report);
+
     socket.on("remove", handle_removal);
 
     socket.on_disconnect(handle_disconnect_socket);
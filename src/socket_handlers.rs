@@ -1,8 +1,16 @@
 use std::sync::Arc;
+use serde::Serialize;
 use socketioxide::extract::{Data, SocketRef, State};
-use tracing::info;
-use crate::model::{GeneralRequest, GeneralResponse, InPrivate, Message, PrivateMessageReq, User};
-use crate::socket_state::SocketState;
+use socketioxide::socket::Sid;
+use tracing::{info, instrument, warn};
+use crate::content::{links, parse_entities};
+use crate::db_model::{DeliveryStatus, GroupDmCollection, Focus, NotificationKind, RoomRole, RoomStatus};
+use crate::errors::MyError;
+use crate::model::{BlockListKind, ContactPresence, GroupDmReq, GroupMessageReq, MessageHeld, ReportReq, Restriction, UserRelation, EventError, FocusReq, InPrivate, PrivateDelivered, PrivateFocusReq, PrivateStatus, RenameUserReq, RoomRoleReq, RoomRoleResp, GeneralRequest, GeneralResponse, LinkPreviews, Message, PrivateMessageReq, ThreadWatch, User, UserHandleReq};
+use crate::moderation::{MessageKind, ModerationInput, Verdict};
+use crate::metrics::{event_emitted, event_received, METRICS};
+use crate::socket_state::{Broadcaster, GeneratedName, SocketState};
+use crate::telemetry::connection_span;
 use crate::model::Messages;
 
 // Created a separate module for the socket handlers
@@ -14,21 +22,50 @@ use crate::model::Messages;
 /// `get_messages` function <br/>
 /// *NOTE* The message reading is not being performed from the DB and is being read from the memory store
 /// provided by the `SocketState` struct implementation through `socketioxide` >v8.0 library
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "join_room"))]
 pub async fn handle_join_room(_socket: SocketRef, Data(data): Data<GeneralRequest>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("join_room");
     let general = GeneralRequest {
         sender: data.sender.clone(),
         room: data.room.clone(),
         message: data.message.clone(),
+        reply_to: None,
+        attachments: vec![],
+        hide_blocked: data.hide_blocked,
     };
     info!("General: {:?}", &general);
 
+    // the DMs of a user are delivered to the room named after it and the thread updates to `thread:<id>`,
+    // neither can be joined as a chat room
+    let routed = general.room.starts_with("thread:")
+        || !matches!(socket_state.db.resolve_username(&general.room).await, Ok(None));
+    if routed {
+        emit_error(&_socket, "join_room", format!("{} can not be joined", general.room));
+        return;
+    }
+
+    if let Ok(RoomStatus::Closed) = socket_state.db.room_status(&general.room).await {
+        emit_error(&_socket, "join_room", format!("room {} is closed", general.room));
+        return;
+    }
+
     // _socket.leave_all().ok();
 
-    // Leave all the rooms except the username group room for private chats
-    socket_state.leave_all_expect_one(_socket.clone(), data.sender.clone()).await;
+    // Leave all the rooms except the username group rooms for private chats
+    socket_state.leave_all_expect_own(_socket.clone()).await;
 
     _socket.join(general.room.clone()).ok();
-    let messages = socket_state.get_messages(&general.room).await;
+    // the membership goes to the identity of the connection, the ownership of a new room only to an owned username
+    let may_own = socket_state.socket_user(&_socket.id.to_string()).await.is_some();
+    let member = socket_state.socket_identity(&_socket).await;
+    if let Err(e) = socket_state.db.join_room_member(&general.room, &member, may_own).await {
+        warn!("Error recording room member: {:?}", e);
+    }
+    socket_state.set_hide_blocked(_socket.id.to_string(), general.hide_blocked).await;
+    let mut messages = socket_state.get_messages(&general.room).await;
+    if general.hide_blocked {
+        messages = socket_state.filter_blocked(&member, messages).await;
+    }
 
 
     // let response = GeneralResponse {
@@ -40,69 +77,667 @@ pub async fn handle_join_room(_socket: SocketRef, Data(data): Data<GeneralReques
 
     // _socket.within(general.room.clone()).emit("response", response).ok();
 
-    _socket.emit("messages", Messages { messages }).ok();
+    let profiles = socket_state.profile_summaries(messages.iter().map(|message| message.sender.clone()).collect()).await;
+
+    event_emitted("messages");
+    _socket.emit("messages", Messages { messages, profiles }).ok();
 }
 
-pub async fn handle_private(_socket: SocketRef, Data(data): Data<PrivateMessageReq>, socket_state: State<Arc<SocketState>>) {
+/// Send a private message to the owned username `receiver` <br/>
+/// The sender is the owned username linked to this connection by `user_handle`, the `sender` of the payload is not trusted.
+/// Unknown receivers are rejected with `event_error`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "private"))]
+pub async fn handle_private(_socket: SocketRef, Data(mut data): Data<PrivateMessageReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("private");
     // info!("Private: {:?}", data);
-    let sender = match data.sender.clone() {
+    let sender = match socket_state.socket_user(&_socket.id.to_string()).await {
         Some(sender) => sender,
-        None => _socket.id.clone().to_string()
+        None => {
+            emit_error(&_socket, "private", "user_handle must be sent before sending private messages".to_string());
+            return;
+        }
     };
+
+    if !check_restriction(&_socket, &socket_state, "private", &sender).await {
+        return;
+    }
+
+    // a DM addressed to a renamed user reaches it under its current name during the grace period
+    match socket_state.db.resolve_username(&data.receiver).await {
+        Ok(Some(receiver)) => data.receiver = receiver,
+        Ok(None) => {
+            emit_error(&_socket, "private", format!("unknown recipient: {}", data.receiver));
+            return;
+        }
+        Err(e) => {
+            emit_error(&_socket, "private", e.to_string());
+            return;
+        }
+    }
+
+    // blocked senders only get a generic failure, so they can not tell they are blocked
+    let relation = socket_state.db.user_relation(&data.receiver, &sender).await;
+    if let Ok(UserRelation::Blocked) = relation {
+        emit_error(&_socket, "private", "message could not be delivered".to_string());
+        return;
+    }
+    match socket_state.db.dm_allowed(&data.receiver, &sender).await {
+        Ok(true) => {}
+        Ok(false) => {
+            emit_error(&_socket, "private", format!("{} only accepts messages from contacts", data.receiver));
+            return;
+        }
+        Err(e) => {
+            emit_error(&_socket, "private", e.to_string());
+            return;
+        }
+    }
+
+    let input = ModerationInput {
+        kind: MessageKind::Private,
+        sender: sender.clone(),
+        target: data.receiver.clone(),
+        text: data.message.clone(),
+    };
+    let text = match moderate_inbound(&_socket, &socket_state, "private", input, None, data.attachments.clone()).await {
+        Some(text) => text,
+        None => return
+    };
+
+    let muted = matches!(relation, Ok(UserRelation::Muted));
+    if let Err(e) = deliver_private_message(&_socket, &socket_state, &sender, &data.receiver, text, data.attachments, muted).await {
+        emit_error(&_socket, "private", e.to_string());
+    }
+}
+
+/// Link the attachments, store the DM and deliver it as `resp` to the receiver and `resp_back` to the sender,
+/// shared by `handle_private` and the release of held messages <br/>
+/// `muted` tells whether the receiver muted the sender, which skips the notification of an offline receiver
+pub async fn deliver_private_message(
+    from: &impl Broadcaster,
+    socket_state: &SocketState,
+    sender: &str,
+    receiver: &str,
+    text: String,
+    attachments: Vec<String>,
+    muted: bool,
+) -> Result<(), MyError> {
+    if !attachments.is_empty() {
+        let participants = vec![sender.to_string(), receiver.to_string()];
+        socket_state.db.link_attachments(&attachments, sender, None, participants).await?;
+    }
+
     let message = PrivateMessageReq {
-        message: data.message.to_owned(),
-        sender: Some(sender.clone()),
-        receiver: data.receiver.clone(),
+        message: text,
+        sender: Some(sender.to_string()),
+        receiver: receiver.to_string(),
+        attachments,
     };
 
+    // the message stays queued for the receiver until it is confirmed with `private_delivered`
+    let online = !from.within_rooms(receiver.to_string()).sockets().unwrap_or_default().is_empty();
+    let status = if online { DeliveryStatus::Sent } else { DeliveryStatus::Queued };
+    // an offline receiver finds the DM in its inbox on the next `user_handle`, unless it muted the sender
+    let notify = !online && !muted;
+
     // INSERT THE MESSAGE INTO DB
-    let response = socket_state.insert_private_messages(message).await;
+    let mut response = socket_state.insert_private_messages(message, status, notify).await;
+    response.sender_profile = socket_state.sender_profile(sender).await;
     info!("Private Message: {:?}", response.clone());
 
-    _socket.to(data.receiver).emit("resp", response.clone()).ok(); // message to receiver
-    _socket.emit("resp_back", response).ok(); // message back to sender
+    if online {
+        event_emitted("resp");
+        from.within_rooms(receiver.to_string()).emit("resp", response.clone()).ok(); // message to every socket of the receiver
+    }
+    // message back to every socket of the sender, carrying the `queued` status when the receiver is offline
+    event_emitted("resp_back");
+    from.within_rooms(sender.to_string()).emit("resp_back", response).ok();
+    Ok(())
+}
+
+/// Create a group DM with the owned username linked to the connection and `participants`, every participant gets `group_dm` <br/>
+/// Creating a group of the same users again returns the existing one
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "create_group_dm"))]
+pub async fn handle_create_group_dm(_socket: SocketRef, Data(data): Data<GroupDmReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("create_group_dm");
+    info!("Create Group DM: {:?}", data);
+    let creator = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(creator) => creator,
+        None => {
+            emit_error(&_socket, "create_group_dm", "user_handle must be sent before creating a group DM".to_string());
+            return;
+        }
+    };
 
+    match socket_state.create_group_dm(&creator, data).await {
+        Ok(group) => {
+            let participants = group.participants.clone();
+            event_emitted("group_dm");
+            _socket.within(participants).emit("group_dm", SocketState::group_dm_resp(group)).ok();
+        }
+        Err(e) => {
+            emit_error(&_socket, "create_group_dm", e.to_string());
+        }
+    }
+}
+
+/// Send a message to a group DM, delivered as `group_resp` to the owned username room of every participant <br/>
+/// The message stays queued for each other participant until it is confirmed with `group_delivered`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "group_message"))]
+pub async fn handle_group_message(_socket: SocketRef, Data(data): Data<GroupMessageReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("group_message");
+    let sender = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(sender) => sender,
+        None => {
+            emit_error(&_socket, "group_message", "user_handle must be sent before sending group messages".to_string());
+            return;
+        }
+    };
+
+    if !check_restriction(&_socket, &socket_state, "group_message", &sender).await {
+        return;
+    }
+
+    let group = match socket_state.db.find_group_dm(&data.group).await {
+        Ok(group) if group.participants.contains(&sender) => group,
+        Ok(_) => {
+            emit_error(&_socket, "group_message", format!("{} is not a participant of the group", sender));
+            return;
+        }
+        Err(e) => {
+            emit_error(&_socket, "group_message", e.to_string());
+            return;
+        }
+    };
+
+    let input = ModerationInput {
+        kind: MessageKind::Group,
+        sender: sender.clone(),
+        target: data.group.clone(),
+        text: data.message.clone(),
+    };
+    let text = match moderate_inbound(&_socket, &socket_state, "group_message", input, None, data.attachments.clone()).await {
+        Some(text) => text,
+        None => return
+    };
+
+    if let Err(e) = deliver_group_message(&_socket, &socket_state, group, &sender, text, data.attachments).await {
+        emit_error(&_socket, "group_message", e.to_string());
+    }
+}
+
+/// Link the attachments, store the group message and deliver it as `group_resp` to the participants who did not block
+/// the sender, shared by `handle_group_message` and the release of held messages
+pub async fn deliver_group_message(
+    from: &impl Broadcaster,
+    socket_state: &SocketState,
+    group: GroupDmCollection,
+    sender: &str,
+    text: String,
+    attachments: Vec<String>,
+) -> Result<(), MyError> {
+    if !attachments.is_empty() {
+        socket_state.db.link_attachments(&attachments, sender, None, group.participants.clone()).await?;
+    }
+
+    // the participants who blocked the sender neither receive nor queue the message
+    let blocking = socket_state.db.users_listing(sender, &group.participants, BlockListKind::Blocked).await?;
+    let recipients = group.participants.iter()
+        .filter(|participant| participant.as_str() != sender && !blocking.contains(participant))
+        .cloned()
+        .collect::<Vec<String>>();
+    let offline = recipients.iter()
+        .filter(|participant| from.within_rooms((*participant).clone()).sockets().unwrap_or_default().is_empty())
+        .cloned()
+        .collect::<Vec<String>>();
+    let mut response = socket_state.insert_group_message(&group, sender, text, &attachments, &recipients, &offline).await?;
+    response.sender_profile = socket_state.sender_profile(sender).await;
+    info!("Group Message: {:?}", response);
+
+    let mut audience = recipients;
+    audience.push(sender.to_string());
+    event_emitted("group_resp");
+    from.within_rooms(audience).emit("group_resp", response).ok();
+    Ok(())
+}
+
+/// Delivery confirmation of group messages, clears them from the queue of the participant and lets the senders know
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "group_delivered"))]
+pub async fn handle_group_delivered(_socket: SocketRef, Data(data): Data<PrivateDelivered>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("group_delivered");
+    info!("Group Delivered: {:?}", data);
+    let username = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(username) => username,
+        None => {
+            emit_error(&_socket, "group_delivered", "user_handle must be sent before confirming messages".to_string());
+            return;
+        }
+    };
+
+    match socket_state.confirm_group_messages(&username, &data.ids).await {
+        Ok(updates) => {
+            for (sender, update) in updates {
+                event_emitted("group_status");
+                _socket.within(sender).emit("group_status", update).ok();
+            }
+        }
+        Err(e) => {
+            emit_error(&_socket, "group_delivered", e.to_string());
+        }
+    }
+}
+
+/// Delivery confirmation from the receiver, clears the messages from its queue and lets the senders know
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "private_delivered"))]
+pub async fn handle_private_delivered(_socket: SocketRef, Data(data): Data<PrivateDelivered>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("private_delivered");
+    info!("Private Delivered: {:?}", data);
+    let receiver = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(receiver) => receiver,
+        None => {
+            emit_error(&_socket, "private_delivered", "user_handle must be sent before confirming messages".to_string());
+            return;
+        }
+    };
+
+    match socket_state.confirm_private_messages(&receiver, &data.ids).await {
+        Ok(updates) => {
+            for (sender, update) in updates {
+                event_emitted("private_status");
+                _socket.within(sender).emit("private_status", update).ok();
+            }
+        }
+        Err(e) => {
+            emit_error(&_socket, "private_delivered", e.to_string());
+        }
+    }
 }
 
 
 /// Handling the message from the client <br/>
 /// *NOTE:* The mechanism is not built for Ultra high throughput as OPS limit is not set and may exceed
 /// if too many write operations are performed simultaneously <br/>
-/// To resolve it and upgrade the server a Pub/Sub mechanism can be used to handle the ultra-high throughput requirements
+/// To resolve it and upgrade the server a Pub/Sub mechanism can be used to handle the ultra-high throughput requirements <br/>
+/// Messages carrying `reply_to` are attached to a thread and fanned out as `thread_updated` instead of `response` <br/>
+/// The sender is the identity of the connection (see `SocketState::socket_identity`), the `sender` of the payload is not trusted
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "message"))]
 pub async fn handle_message(_socket: SocketRef, Data(data): Data<GeneralRequest>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("message");
     info!("Message: {:?}", data);
+    let sender = socket_state.socket_identity(&_socket).await;
+    if !check_restriction(&_socket, &socket_state, "message", &sender).await {
+        return;
+    }
+    match socket_state.db.room_status(&data.room).await {
+        Ok(RoomStatus::Closed) | Ok(RoomStatus::Archived) => {
+            emit_error(&_socket, "message", format!("room {} is read-only", data.room));
+            return;
+        }
+        _ => {}
+    }
+    // attachments are uploaded by owned usernames, so only the user linked to the connection can reference them
+    if !data.attachments.is_empty() && socket_state.socket_user(&_socket.id.to_string()).await.is_none() {
+        emit_error(&_socket, "message", "user_handle must be sent before sending attachments".to_string());
+        return;
+    }
+
+    let input = ModerationInput {
+        kind: MessageKind::Room,
+        sender: sender.clone(),
+        target: data.room.clone(),
+        text: data.message.clone(),
+    };
+    let text = match moderate_inbound(&_socket, &socket_state, "message", input, data.reply_to.clone(), data.attachments.clone()).await {
+        Some(text) => text,
+        None => return
+    };
+
+    let message = room_message(sender, data.room, text, data.reply_to, data.attachments);
+    match deliver_room_message(&_socket, &socket_state, message).await {
+        Ok(errors) => {
+            for error in errors {
+                emit_error(&_socket, "message", error);
+            }
+        }
+        Err(e) => emit_error(&_socket, "message", e.to_string())
+    }
+}
+
+/// New room message of `sender`, not stored yet
+pub fn room_message(sender: String, room: String, text: String, reply_to: Option<String>, attachments: Vec<String>) -> Message {
+    Message {
+        id: None,
+        sender,
+        room,
+        entities: parse_entities(&text),
+        message: text,
+        reply_to,
+        reply_count: 0,
+        last_reply_at: None,
+        attachments,
+        previews: vec![],
+        date_time: chrono::Utc::now(),
+    }
+}
+
+/// Link the attachments, store the room message (or the reply) and fan it out to the room, the thread and the mentioned users,
+/// shared by `handle_message` and the release of held messages <br/>
+/// Returns the rejected `@here`/`@room` mentions, for the sender
+pub async fn deliver_room_message(from: &impl Broadcaster, socket_state: &Arc<SocketState>, message: Message) -> Result<Vec<String>, MyError> {
+    let room = message.room.clone();
+    let sender = message.sender.clone();
+    if !message.attachments.is_empty() {
+        socket_state.db.link_attachments(&message.attachments, &sender, Some(room.clone()), vec![]).await?;
+    }
+
+    if message.reply_to.is_some() {
+        let update = socket_state.insert_reply(message).await?;
+        let thread_room = SocketState::thread_room(&update.root_id);
+        let hidden = socket_state.hidden_sockets(from, &room, &sender).await;
+        emit_except(from, vec![room, thread_room], &hidden, "thread_updated", update.clone());
+        return Ok(deliver_mentions(from, socket_state, &update.reply).await);
+    }
+
+    // INSERT THE MESSAGE INTO DB
+    let stored = socket_state.insert(&room, message).await?;
+
     let response = GeneralResponse {
-        sender: data.sender.clone(),
-        room: data.room.clone(),
+        id: stored.id.clone(),
+        sender: stored.sender.clone(),
+        room: stored.room.clone(),
         // message: format!("Message By Client: {}", data.message).to_owned(),
-        message: data.message.clone(),
-        date_time: chrono::Utc::now(),
+        message: stored.message.clone(),
+        reply_to: None,
+        attachments: stored.attachments.clone(),
+        entities: stored.entities.clone(),
+        sender_profile: socket_state.sender_profile(&stored.sender).await,
+        date_time: stored.date_time,
     };
 
-    // INSERT THE MESSAGE INTO DB
-    socket_state.insert(&data.room, Message {
-        sender: data.sender.clone(),
-        room: data.room.clone(),
-        message: data.message.clone(),
-        date_time: response.date_time.clone(),
-    }).await;
+    let hidden = socket_state.hidden_sockets(from, &room, &sender).await;
+    emit_except(from, vec![room.clone()], &hidden, "response", response);
+    let errors = deliver_mentions(from, socket_state, &stored).await;
+
+    let urls = links(&stored.entities);
+    if let (Some(id), false) = (stored.id, urls.is_empty()) {
+        let state = socket_state.clone();
+        let from = from.clone();
+        tokio::spawn(async move {
+            let previews = state.fetch_previews(&id, &room, urls).await;
+            if !previews.is_empty() {
+                event_emitted("link_previews");
+                from.within_rooms(room.clone()).emit("link_previews", LinkPreviews {
+                    message_id: id,
+                    room,
+                    previews,
+                }).ok();
+            }
+        });
+    }
+    Ok(errors)
+}
+
+/// Reject the messages of the users muted or banned by a moderator, returns `false` after letting the sender know
+async fn check_restriction(_socket: &SocketRef, socket_state: &SocketState, event: &str, sender: &str) -> bool {
+    let message = match socket_state.db.user_restriction(sender).await {
+        Ok(Some(Restriction::Banned)) => "you are banned".to_string(),
+        Ok(Some(Restriction::Muted(until))) => format!("you are muted until {}", until.to_rfc3339()),
+        _ => return true
+    };
+
+    emit_error(_socket, event, message);
+    false
+}
+
+/// Pass the inbound message through the moderation pipeline <br/>
+/// Returns the text to store (possibly redacted), or `None` after letting the sender know the message was rejected or held
+async fn moderate_inbound(
+    _socket: &SocketRef,
+    socket_state: &SocketState,
+    event: &str,
+    input: ModerationInput,
+    reply_to: Option<String>,
+    attachments: Vec<String>,
+) -> Option<String> {
+    let target = input.target.clone();
+    let (outcome, held_id) = socket_state.moderate(input, reply_to, attachments).await;
+
+    match outcome.verdict {
+        Verdict::Allow | Verdict::Redact => Some(outcome.text),
+        Verdict::Hold => {
+            event_emitted("message_held");
+            _socket.emit("message_held", MessageHeld {
+                id: held_id.unwrap_or_default(),
+                target,
+                reason: outcome.reason.unwrap_or_default(),
+            }).ok();
+            None
+        }
+        Verdict::Reject => {
+            // the spam filter carries the repeat and link rate limits
+            if outcome.decisions.last().map(|decision| decision.filter.eq("spam")).unwrap_or(false) {
+                METRICS.rate_limit_rejections.inc();
+            }
+            emit_error(_socket, event, format!("message rejected: {}", outcome.reason.unwrap_or_default()));
+            None
+        }
+    }
+}
 
-    _socket.within(data.room.clone()).emit("response", response).ok();
+/// Broadcast to the rooms, skipping the `hidden` sockets (see `SocketState::hidden_sockets`) <br/>
+/// Falls back to emitting socket by socket only when there is something to skip
+fn emit_except<T: Serialize + Clone>(from: &impl Broadcaster, rooms: Vec<String>, hidden: &[Sid], event: &'static str, data: T) {
+    event_emitted(event);
+    if hidden.is_empty() {
+        from.within_rooms(rooms).emit(event, data).ok();
+        return;
+    }
+
+    for socket in from.within_rooms(rooms).sockets().unwrap_or_default() {
+        if !hidden.contains(&socket.id) {
+            socket.emit(event, data.clone()).ok();
+        }
+    }
+}
+
+/// Emit `event_error` to the socket which sent `event`
+fn emit_error(_socket: &SocketRef, event: &str, message: String) {
+    event_emitted("event_error");
+    _socket.emit("event_error", EventError {
+        event: event.to_string(),
+        message,
+    }).ok();
+}
+
+/// Emit `mention` to the owned username room of every mentioned user (joined in `handle_user_join`),
+/// so it reaches them even when they are not in the room of the message <br/>
+/// Rejected `@here`/`@room` mentions are returned, to be reported back to the sender
+async fn deliver_mentions(from: &impl Broadcaster, socket_state: &SocketState, message: &Message) -> Vec<String> {
+    let (mentions, errors) = socket_state.record_mentions(from, message).await;
+    let room = Focus::Room(message.room.clone());
+    for (username, mut mention) in mentions {
+        // a user viewing the room reads the mention as it arrives
+        if socket_state.is_viewing(&username, &room).await {
+            match socket_state.db.mark_mentions_read(&username, &[mention.id.clone()]).await {
+                Ok(_) => mention.read = true,
+                Err(e) => warn!("Error marking the mention as read: {:?}", e)
+            }
+        }
+        event_emitted("mention");
+        from.within_rooms(username.clone()).emit("mention", mention).ok();
+        push_notification(
+            from,
+            socket_state,
+            &username,
+            NotificationKind::Mention,
+            Some(message.sender.clone()),
+            format!("{} mentioned you in {}", message.sender, message.room),
+            Some(message.room.clone()),
+            Some(&room),
+        ).await;
+    }
+    errors
+}
+
+/// Store the notification in the inbox of `username` and emit it as `notification` to their owned username room <br/>
+/// Offline users receive it with the rest of their unread backlog on the next `user_handle`.
+/// Nothing is stored while a socket of the user is viewing `conversation`, the conversation the notification is about
+#[allow(clippy::too_many_arguments)]
+async fn push_notification(
+    from: &impl Broadcaster,
+    socket_state: &SocketState,
+    username: &str,
+    kind: NotificationKind,
+    sender: Option<String>,
+    message: String,
+    reference: Option<String>,
+    conversation: Option<&Focus>,
+) {
+    if let Some(conversation) = conversation {
+        if socket_state.is_viewing(username, conversation).await {
+            return;
+        }
+    }
+    if let Some(notification) = socket_state.notify(username, kind, sender, message, reference).await {
+        event_emitted("notification");
+        from.within_rooms(username.to_string()).emit("notification", notification).ok();
+    }
+}
+
+/// Change the role of a member of the room, only the owner of the room, the identity of the connection, can do it
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "set_room_role"))]
+pub async fn handle_set_room_role(_socket: SocketRef, Data(data): Data<RoomRoleReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("set_room_role");
+    info!("Set Room Role: {:?}", data);
+    let sender = socket_state.socket_identity(&_socket).await;
+    let is_owner = matches!(socket_state.db.room_member_role(&data.room, &sender).await, Ok(Some(RoomRole::Owner)));
+
+    let resp = if !is_owner {
+        Err(MyError::ForbiddenError(format!("{} is not the owner of {}", sender, data.room)))
+    } else if data.role == RoomRole::Owner {
+        Err(MyError::BadRequestError("the ownership of a room can not be transferred".to_string()))
+    } else {
+        socket_state.db.set_room_role(&data.room, &data.username, data.role).await
+    };
+
+    match resp {
+        Ok(member) => {
+            event_emitted("room_role_updated");
+            _socket.within(data.room.clone()).emit("room_role_updated", RoomRoleResp {
+                room: member.room,
+                username: member.username,
+                role: member.role,
+            }).ok();
+        }
+        Err(e) => {
+            emit_error(&_socket, "set_room_role", e.to_string());
+        }
+    }
+}
+
+/// Start receiving `thread_updated` events for a thread, without being a member of its room
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "watch_thread"))]
+pub async fn handle_watch_thread(_socket: SocketRef, Data(data): Data<ThreadWatch>) {
+    let _timer = event_received("watch_thread");
+    info!("Watch Thread: {:?}", data);
+    _socket.join(SocketState::thread_room(&data.id)).ok();
+}
+
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "unwatch_thread"))]
+pub async fn handle_unwatch_thread(_socket: SocketRef, Data(data): Data<ThreadWatch>) {
+    let _timer = event_received("unwatch_thread");
+    info!("Unwatch Thread: {:?}", data);
+    _socket.leave(SocketState::thread_room(&data.id)).ok();
 }
 
 /// Handle the user linking to the generated unique username <br/>
-/// Enable the user to map its owned username to the generated username, which is mapped with socket id and changes with each connection
-pub async fn handle_user_join(_socket: SocketRef, Data(data): Data<User>, socket_state: State<Arc<SocketState>>) {
+/// Enable the user to map its owned username to the generated username, which is mapped with socket id and changes with each connection <br/>
+/// The owned username is only linked with an identity token signed for it, and the generated username has to be the one
+/// of this connection. A socket linking another owned username first drops the room, focus and session of the previous one
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "user_handle"))]
+pub async fn handle_user_join(_socket: SocketRef, Data(data): Data<UserHandleReq>, socket_state: State<Arc<SocketState>>) {
 
+    let _timer = event_received("user_handle");
     info!("User Join: {:?}", data);
-    let user_resp = socket_state.handle_user(data.clone()).await;
+    let generated = _socket.extensions.get::<GeneratedName>().map(|name| name.0.clone()).unwrap_or_default();
+    if !data.generated_username.eq(&generated) {
+        emit_error(&_socket, "user_handle", "generated_username is not the one of this connection".to_string());
+        return;
+    }
+    // a client still using a renamed username is linked under the current one, `user_handled` carries it
+    let verified = match socket_state.verify_user(&data.username, data.token.as_deref()).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            emit_error(&_socket, "user_handle", e.to_string());
+            return;
+        }
+    };
+    let data = User {
+        username: verified.username().to_string(),
+        generated_username: data.generated_username,
+    };
+    if let Ok(Some(Restriction::Banned)) = socket_state.db.user_restriction(&data.username).await {
+        emit_error(&_socket, "user_handle", "you are banned".to_string());
+        _socket.disconnect().ok();
+        return;
+    }
+
+    let mut user_resp = match socket_state.handle_user(data.clone()).await {
+        Ok(user_resp) => user_resp,
+        Err(e) => {
+            emit_error(&_socket, "user_handle", e.to_string());
+            return;
+        }
+    };
+    let previous = socket_state.socket_user(&_socket.id.to_string()).await;
+    if let Some(previous) = previous.filter(|previous| !previous.eq(&data.username)) {
+        unlink_previous_user(&_socket, &socket_state, &previous).await;
+    }
+    let was_online = socket_state.is_online(&data.username).await;
+    socket_state.set_socket_user(_socket.id.to_string(), data.username.clone()).await;
+    match socket_state.open_session(&_socket.id.to_string(), &verified).await {
+        Ok(token) => user_resp.session_token = Some(token),
+        Err(e) => warn!("Error linking the socket to {}: {:?}", data.username, e)
+    }
+    connection_span(&_socket).record("user", data.username.as_str());
+    if !was_online {
+        announce_presence(&_socket, &socket_state, &data.username, true).await;
+    }
 
     info!("User Join Own Private: {:?}", data.username.clone());
     // info: Only provided as a patch functionality
-    _socket.join(data.username).ok();
+    _socket.join(data.username.clone()).ok();
 
+    event_emitted("user_handled");
     _socket.emit("user_handled", user_resp).ok();
 
+    // flush the private messages queued while offline, in order, their senders see them move from `queued` to `sent`
+    for message in socket_state.undelivered_private_messages(&data.username).await {
+        let update = PrivateStatus {
+            id: message.id.clone().unwrap_or_default(),
+            receiver: message.receiver.clone(),
+            status: message.status,
+        };
+        let sender = message.sender.clone();
+        event_emitted("resp");
+        _socket.emit("resp", message).ok();
+        event_emitted("private_status");
+        _socket.within(sender).emit("private_status", update).ok();
+    }
+    for message in socket_state.undelivered_group_messages(&data.username).await {
+        event_emitted("group_resp");
+        _socket.emit("group_resp", message).ok();
+    }
+
+    // deliver the notifications received while offline
+    match socket_state.notification_inbox(&data.username, true, 50, 1).await {
+        Ok(inbox) => {
+            event_emitted("notifications");
+            _socket.emit("notifications", inbox).ok();
+        }
+        Err(e) => warn!("Error reading notifications: {:?}", e)
+    }
+
 
 //     let response = User {
 //         username: data.username.clone(),
@@ -114,33 +749,191 @@ pub async fn handle_user_join(_socket: SocketRef, Data(data): Data<User>, socket
 //     //     sender: data.sender.clone(),
 //     //     room: data.room.clone(),
 //     //     message: data.message.clone(),
-//     //     date_time: response.date_time.clone(),
+//     //     date_time: response.date_time,
 //     // }).await;
 //
 //     _socket.emit("user_joined", response).ok();
 }
 
-pub async fn handle_private_joined(_socket: SocketRef, Data(data): Data<InPrivate>, socket_state: State<Arc<SocketState>>) {
-    info!("Private Joined: {:?}", data.clone());
+/// Rename the owned username linked to the connection <br/>
+/// Every socket of the user moves to the room of the new name, and the user, its DM contacts and its rooms get `user_renamed`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "rename_user"))]
+pub async fn handle_rename_user(_socket: SocketRef, Data(data): Data<RenameUserReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("rename_user");
+    info!("Rename User: {:?}", data);
+    let old = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(old) => old,
+        None => {
+            emit_error(&_socket, "rename_user", "user_handle must be sent before renaming".to_string());
+            return;
+        }
+    };
 
-    let resp = socket_state.handle_private_joined(data).await;
+    let renamed = match socket_state.rename_user(&old, &data.username).await {
+        Ok(renamed) => renamed,
+        Err(e) => {
+            emit_error(&_socket, "rename_user", e.to_string());
+            return;
+        }
+    };
+    connection_span(&_socket).record("user", renamed.new_username.as_str());
 
-    _socket.emit("joined_private", resp).ok();
+    let sockets = _socket.within(old.clone()).sockets().unwrap_or_default();
+    for socket in &sockets {
+        socket.leave(old.clone()).ok();
+        socket.join(renamed.new_username.clone()).ok();
+    }
+    let rooms = socket_state.audience(sockets, &renamed.new_username).await;
+
+    event_emitted("user_renamed");
+    _socket.within(rooms).emit("user_renamed", renamed).ok();
+}
+
+/// The socket opened a conversation, or left it with a `null` focus, echoed back as `focused` <br/>
+/// While a conversation is viewed its notifications are not stored, and opening it marks its unread backlog as read,
+/// which is told to every socket of the user with `conversation_read`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "focus"))]
+pub async fn handle_focus(_socket: SocketRef, Data(data): Data<FocusReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("focus");
+    info!("Focus: {:?}", data);
+    if let Some(username) = focus_user(&_socket, &socket_state, "focus").await {
+        focus_socket(&_socket, &socket_state, "focus", &username, data.focus).await;
+    }
+}
+
+/// Deprecated alias of `focus` on the DM with `peer`, also answered with the former `joined_private`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "private_joined"))]
+pub async fn handle_private_joined(_socket: SocketRef, Data(data): Data<PrivateFocusReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("private_joined");
+    info!("Private Joined: {:?}", data);
+    let Some(username) = focus_user(&_socket, &socket_state, "private_joined").await else {
+        return;
+    };
+    let Some(peer) = data.peer else {
+        emit_error(&_socket, "private_joined", "the peer of the DM must be set, prefer the focus event".to_string());
+        return;
+    };
+
+    if let Some(focus) = focus_socket(&_socket, &socket_state, "private_joined", &username, Some(Focus::Dm(peer))).await {
+        event_emitted("joined_private");
+        _socket.emit("joined_private", InPrivate { in_private: true, username, focus: focus.into_iter().collect() }).ok();
+    }
+}
+
+/// Deprecated alias of a `null` `focus` when the socket is viewing a DM, also answered with the former `left_private`
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "private_left"))]
+pub async fn handle_private_left(_socket: SocketRef, Data(data): Data<PrivateFocusReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("private_left");
+    info!("Private Left: {:?}", data);
+    let Some(username) = focus_user(&_socket, &socket_state, "private_left").await else {
+        return;
+    };
+
+    let focus = socket_state.focus.read().await.get(&_socket.id.to_string()).cloned();
+    let focus = match focus {
+        Some(Focus::Dm(_)) => match focus_socket(&_socket, &socket_state, "private_left", &username, None).await {
+            Some(focus) => focus,
+            None => return,
+        },
+        focus => focus
+    };
+    event_emitted("left_private");
+    _socket.emit("left_private", InPrivate { in_private: false, username, focus: focus.into_iter().collect() }).ok();
 }
-pub async fn handle_private_left(_socket: SocketRef, Data(data): Data<InPrivate>, socket_state: State<Arc<SocketState>>) {
-    info!("Private Left: {:?}", data.clone());
 
-    let resp = socket_state.handle_private_left(data).await;
-    _socket.emit("left_private", resp).ok();
+/// Owned username of the socket changing its focus, an `event_error` is sent when `user_handle` was not sent yet
+async fn focus_user(_socket: &SocketRef, socket_state: &SocketState, event: &str) -> Option<String> {
+    let username = socket_state.socket_user(&_socket.id.to_string()).await;
+    if username.is_none() {
+        emit_error(_socket, event, "user_handle must be sent before focusing a conversation".to_string());
+    }
+    username
 }
 
-pub async fn handle_notify(_socket: SocketRef, Data(data): Data<PrivateMessageReq>, _socket_state: State<Arc<SocketState>>) {
+/// Set the focus of the socket and answer with `focused`, and `conversation_read` when it marked a backlog as read <br/>
+/// Returns the focus which was set, `None` after an `event_error`
+async fn focus_socket(_socket: &SocketRef, socket_state: &SocketState, event: &str, username: &str, focus: Option<Focus>) -> Option<Option<Focus>> {
+    match socket_state.set_focus(&_socket.id.to_string(), username, focus).await {
+        Ok((focus, read)) => {
+            event_emitted("focused");
+            _socket.emit("focused", FocusReq { focus: focus.clone() }).ok();
+            if let Some(read) = read {
+                event_emitted("conversation_read");
+                _socket.within(username.to_string()).emit("conversation_read", read).ok();
+            }
+            Some(focus)
+        }
+        Err(e) => {
+            emit_error(_socket, event, e.to_string());
+            None
+        }
+    }
+}
+
+/// Notify the receiver about a DM, the notification is stored in the inbox of the receiver
+/// in addition to the plain `notified` event kept for the existing clients <br/>
+/// Nothing is sent when the receiver muted the sender, a blocked sender gets a generic failure
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "notify"))]
+pub async fn handle_notify(_socket: SocketRef, Data(data): Data<PrivateMessageReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("notify");
     // _socket.emit("notified", data).ok();
     info!("Notification: {:?}", data.clone());
-    _socket.to(data.receiver).emit("notified", data.message).ok();
+
+    // the sender is the owned username linked to the connection, the `sender` of the payload is not trusted
+    let sender = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(sender) => sender,
+        None => {
+            emit_error(&_socket, "notify", "user_handle must be sent before sending notifications".to_string());
+            return;
+        }
+    };
+    match socket_state.db.user_relation(&data.receiver, &sender).await {
+        Ok(UserRelation::Blocked) => {
+            emit_error(&_socket, "notify", "notification could not be delivered".to_string());
+            return;
+        }
+        Ok(UserRelation::Muted) => return,
+        _ => {}
+    }
+
+    // the receiver already has the DM open
+    if socket_state.is_viewing(&data.receiver, &Focus::Dm(sender.clone())).await {
+        return;
+    }
+
+    event_emitted("notified");
+    _socket.to(data.receiver.clone()).emit("notified", data.message.clone()).ok();
+    push_notification(&_socket, &socket_state, &data.receiver, NotificationKind::Dm, Some(sender), data.message, None, None).await;
 }
 
+/// Flag a message or a user for the moderators, the reporter is the owned username linked to the connection <br/>
+/// The report is acknowledged with `report_received`, the outcome comes later as a `moderation` notification
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "report"))]
+pub async fn handle_report(_socket: SocketRef, Data(data): Data<ReportReq>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("report");
+    info!("Report: {:?}", data);
+    let reporter = match socket_state.socket_user(&_socket.id.to_string()).await {
+        Some(reporter) => reporter,
+        None => {
+            emit_error(&_socket, "report", "user_handle must be sent before reporting".to_string());
+            return;
+        }
+    };
+
+    match socket_state.create_report(&reporter, data).await {
+        Ok(report) => {
+            event_emitted("report_received");
+            _socket.emit("report_received", SocketState::report_resp(report)).ok();
+        }
+        Err(e) => {
+            emit_error(&_socket, "report", e.to_string());
+        }
+    }
+}
+
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "remove"))]
 pub async fn handle_removal(_socket: SocketRef, Data(data): Data<User>, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("remove");
     info!("Disconnect: {:?}", data.clone());
     // let _ = socket_state.remove_socket(_socket.id.clone().to_string()).await;
     let _ = socket_state.remove_socket(data).await;
@@ -155,11 +948,45 @@ pub async fn handle_removal(_socket: SocketRef, Data(data): Data<User>, socket_s
     _socket.disconnect().ok();
 }
 
-pub fn handle_disconnect_socket(_socket: SocketRef, _socket_state: State<Arc<SocketState>>) {
+#[instrument(name = "event", parent = connection_span(&_socket), skip_all, fields(event = "disconnect"))]
+pub async fn handle_disconnect_socket(_socket: SocketRef, socket_state: State<Arc<SocketState>>) {
+    let _timer = event_received("disconnect");
     _socket.leave_all().ok();
+    socket_state.clear_focus(&_socket.id.to_string()).await;
+    if let Err(e) = socket_state.db.end_socket_session(&_socket.id.to_string()).await {
+        warn!("Error ending the session of {}: {:?}", _socket.id, e);
+    }
+    if let Some(username) = socket_state.remove_socket_user(&_socket.id.to_string()).await {
+        announce_presence(&_socket, &socket_state, &username, false).await;
+    }
     info!("Socket Disconnected: {:?}", _socket.id);
 }
 
+/// The socket is linked to another owned username, it stops receiving the DMs and mentions of `previous`,
+/// whose session token and focus end as on a disconnect
+async fn unlink_previous_user(_socket: &SocketRef, socket_state: &SocketState, previous: &str) {
+    _socket.leave(previous.to_string()).ok();
+    socket_state.clear_focus(&_socket.id.to_string()).await;
+    if let Err(e) = socket_state.db.end_socket_session(&_socket.id.to_string()).await {
+        warn!("Error ending the session of {}: {:?}", _socket.id, e);
+    }
+    if let Some(username) = socket_state.remove_socket_user(&_socket.id.to_string()).await {
+        announce_presence(_socket, socket_state, &username, false).await;
+    }
+}
+
+/// Tell the contacts of `username` it came online or went offline
+async fn announce_presence(_socket: &SocketRef, socket_state: &SocketState, username: &str, online: bool) {
+    let contacts = socket_state.accepted_contacts(username).await;
+    if !contacts.is_empty() {
+        event_emitted("contact_presence");
+        _socket.within(contacts).emit("contact_presence", ContactPresence {
+            username: username.to_string(),
+            online,
+        }).ok();
+    }
+}
+
 // /// The first and foremost event to be called when the socket is connected
 // ///in order to create the map for usernames and socket id
 // pub async fn handle_default(_socket: SocketRef, socket_state: State<Arc<SocketState>>) {
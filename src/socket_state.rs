@@ -1,12 +1,97 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, LazyLock};
+use regex::Regex;
 use socketioxide::extract::SocketRef;
+use socketioxide::operators::{BroadcastOperators, RoomParam};
+use socketioxide::socket::Sid;
+use socketioxide::SocketIo;
 use tokio::sync::RwLock;
+use tracing::warn;
+use crate::auth::{new_session_token, session_digest, IdentityVerifier};
+use crate::content::{mentions, LinkPreview, LinkPreviewFetcher, MAX_PREVIEWS};
 use crate::db::DB;
-use crate::db_model::{PrivateMessageCollection};
-use crate::model::{InPrivate, Message, PrivateMessage, PrivateMessageReq, User, UserResp};
+use crate::db_model::{ContactCollection, ContactStatus, DeliveryStatus, Focus, GroupDmCollection, GroupMessageCollection, HeldMessageCollection, MentionCollection, ModerationAuditCollection, ReviewStatus, MentionKind, NotificationCollection, NotificationKind, PrivateMessageCollection, ReportCollection, ReportKind, ReportStatus, UserCollection, UserProfile, UsernameHistoryCollection};
+use crate::errors::MyError;
+use crate::moderation::{ModerationInput, ModerationOutcome, ModerationPipeline, Verdict};
+use crate::model::{BlockListKind, ContactList, ContactResp, ContactState, GroupDmReq, GroupDmResp, GroupMessage, GroupStatus, HeldMessageResp, UserRelation, ConversationRead, MentionResp, Message, NotificationInbox, NotificationResp, PrivateMessage, PrivateMessageReq, PrivateStatus, ProfilePatch, ProfileResp, ProfileSummary, ReportReq, ReportResp, ThreadUpdate, User, UserRenamed, UserResp, UsernameHistoryResp};
 
 pub type RoomStore = HashMap<String, VecDeque<Message>>;
-// pub type SocketMap = HashMap<String, String>;
+/// socket ID -> owned username, filled once the socket links its owned username through `user_handle`
+pub type SocketMap = HashMap<String, String>;
+/// socket ID -> conversation the socket is viewing, mirrored in `sockets` for the other instances
+pub type FocusMap = HashMap<String, Focus>;
+
+/// Username generated for the connection in `on_connect`, kept in the socket extensions
+#[derive(Debug, Clone)]
+pub struct GeneratedName(pub String);
+
+/// Where the rooms are reached from, the socket of an event handler or the `SocketIo` of the HTTP handlers,
+/// so the fan-out helpers work the same for messages sent over a socket and messages released by a moderator
+pub trait Broadcaster: Clone + Send + Sync + 'static {
+    fn within_rooms(&self, rooms: impl RoomParam) -> BroadcastOperators;
+}
+
+impl Broadcaster for SocketRef {
+    fn within_rooms(&self, rooms: impl RoomParam) -> BroadcastOperators {
+        self.within(rooms)
+    }
+}
+
+impl Broadcaster for SocketIo {
+    fn within_rooms(&self, rooms: impl RoomParam) -> BroadcastOperators {
+        self.within(rooms)
+    }
+}
+
+const MAX_DISPLAY_NAME: usize = 64;
+const MAX_BIO: usize = 280;
+const MAX_STATUS_TEXT: usize = 100;
+/// participants of a group DM, the creator included
+const MAX_GROUP_DM: usize = 32;
+
+/// `UTC`, an IANA name such as `America/Argentina/Buenos_Aires`, or an offset such as `+05:30`
+static TIMEZONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(UTC|[A-Za-z]+(/[A-Za-z0-9_+\-]+){1,2}|[+-]\d{2}:\d{2})$").unwrap()
+});
+
+/// Owned usernames, letters, digits and underscores only, so they never look like a generated username (`adjective-noun`)
+/// nor like the `thread:<id>` rooms
+static USERNAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[A-Za-z0-9_]{3,32}$").unwrap());
+/// Kept for the names the server routes or acts on, a username can neither be one nor start with one followed by `_`
+const RESERVED_USERNAMES: [&str; 5] = ["admin", "group", "room", "system", "thread"];
+
+/// Limits of `rename_user`, from `RENAME_COOLDOWN_HOURS` (30 days by default) and `RENAME_REDIRECT_HOURS` (14 days)
+#[derive(Debug, Clone, Copy)]
+pub struct RenamePolicy {
+    /// minimum time between two renames of the same user
+    pub cooldown: chrono::Duration,
+    /// how long the old username keeps redirecting to the user
+    pub grace: chrono::Duration,
+}
+
+impl RenamePolicy {
+    pub fn from_env() -> Self {
+        let hours = |var: &str, default: i64| std::env::var(var)
+            .ok()
+            .and_then(|hours| hours.parse::<i64>().ok())
+            .unwrap_or(default);
+
+        Self {
+            cooldown: chrono::Duration::hours(hours("RENAME_COOLDOWN_HOURS", 30 * 24)),
+            grace: chrono::Duration::hours(hours("RENAME_REDIRECT_HOURS", 14 * 24)),
+        }
+    }
+}
+
+/// Owned username whose identity token was verified by `SocketState::verify_user`, the only way to build one
+#[derive(Debug, Clone)]
+pub struct VerifiedUser(String);
+
+impl VerifiedUser {
+    pub fn username(&self) -> &str {
+        &self.0
+    }
+}
 
 /// Utilizing the RwLock to store the messages in the room and using the DB instance as well to store messages for longer durations
 /// *This is a shared state between the WebSocket handlers*
@@ -16,33 +101,349 @@ pub type RoomStore = HashMap<String, VecDeque<Message>>;
 pub struct SocketState {
     pub db: DB,
     pub messages: RwLock<RoomStore>,
-    // pub socket_map: RwLock<SocketMap>,
+    pub preview_fetcher: Arc<dyn LinkPreviewFetcher>,
+    pub socket_map: RwLock<SocketMap>,
+    pub focus: RwLock<FocusMap>,
+    /// sockets which joined their room with `hide_blocked`
+    pub hide_blocked: RwLock<HashSet<String>>,
+    pub moderation: ModerationPipeline,
+    pub rename_policy: RenamePolicy,
+    pub identity: IdentityVerifier,
 }
 
 impl SocketState {
     /// Create a new instance of the SocketState
-    pub fn new(db: DB) -> Self {
+    pub fn new(db: DB, preview_fetcher: Arc<dyn LinkPreviewFetcher>, moderation: ModerationPipeline) -> Self {
         Self {
             db,
             messages: RwLock::new(RoomStore::new()),
-            // socket_map: RwLock::new(SocketMap::new()),
+            preview_fetcher,
+            socket_map: RwLock::new(SocketMap::new()),
+            focus: RwLock::new(FocusMap::new()),
+            hide_blocked: RwLock::new(HashSet::new()),
+            moderation,
+            rename_policy: RenamePolicy::from_env(),
+            identity: IdentityVerifier::from_env(),
         }
     }
 
-    /// Remove the connected user from all the other rooms expect the room created with the username<br/>
-    /// That room is used for receiving messages specifically from the other users,
+    /// Remove the connected user from all the other rooms expect the rooms created with the usernames<br/>
+    /// Those rooms are used for receiving messages specifically from the other users,
     /// that have the knowledge of the user with the specific username being active and connected to server
-    pub async fn leave_all_expect_one(&self, _socket: SocketRef, room_to_stay: String) {
+    pub async fn leave_all_expect_own(&self, _socket: SocketRef) {
+        let generated = _socket.extensions.get::<GeneratedName>().map(|name| name.0.clone());
+        let owned = self.socket_user(&_socket.id.to_string()).await;
         let rooms = _socket.rooms().ok();
         if let Some(rooms) = rooms {
             for room in rooms {
-                if room.ne(&room_to_stay) {
+                let own = [&generated, &owned].iter().any(|name| name.as_deref() == Some(room.as_ref()));
+                if !own {
                     _socket.leave(room).ok();
                 }
             }
         }
     }
 
+    /// Name the connection acts under in the rooms, the owned username once `user_handle` was sent and the generated
+    /// username before. The `sender` of the payloads is not trusted
+    pub async fn socket_identity(&self, _socket: &SocketRef) -> String {
+        match self.socket_user(&_socket.id.to_string()).await {
+            Some(username) => username,
+            None => _socket.extensions.get::<GeneratedName>().map(|name| name.0.clone()).unwrap_or_default()
+        }
+    }
+
+    /// Map the socket to the owned username it linked to
+    pub async fn set_socket_user(&self, socket_id: String, username: String) {
+        self.socket_map.write().await.insert(socket_id, username);
+    }
+
+    /// Owned username linked to the socket, `None` until the socket sent `user_handle`
+    pub async fn socket_user(&self, socket_id: &str) -> Option<String> {
+        self.socket_map.read().await.get(socket_id).cloned()
+    }
+
+    /// Returns the owned username of the socket when it was its last socket
+    pub async fn remove_socket_user(&self, socket_id: &str) -> Option<String> {
+        let mut _socket_map = self.socket_map.write().await;
+        let username = _socket_map.remove(socket_id);
+        self.hide_blocked.write().await.remove(socket_id);
+        username.filter(|username| !_socket_map.values().any(|user| user.eq(username)))
+    }
+
+    /// Set the conversation viewed by the socket of `username`, `None` when it left it <br/>
+    /// Opening a conversation marks its unread notifications and mentions as read, returned when there were any
+    pub async fn set_focus(&self, socket_id: &str, username: &str, focus: Option<Focus>) -> Result<(Option<Focus>, Option<ConversationRead>), MyError> {
+        let focus = match focus {
+            Some(Focus::Dm(peer)) => {
+                let peer = self.db.resolve_username(&peer).await?.ok_or(MyError::NotFoundError(peer.clone()))?;
+                Some(Focus::Dm(peer))
+            }
+            Some(Focus::Group(group)) => {
+                if !self.db.find_group_dm(&group).await?.participants.iter().any(|participant| participant.eq(username)) {
+                    return Err(MyError::ForbiddenError(format!("{} is not a participant of the group", username)));
+                }
+                Some(Focus::Group(group))
+            }
+            Some(Focus::Room(room)) if room.trim().is_empty() => {
+                return Err(MyError::BadRequestError("the room must be set".to_string()));
+            }
+            focus => focus
+        };
+
+        self.db.set_socket_focus(socket_id, focus.as_ref()).await?;
+        match &focus {
+            Some(viewed) => self.focus.write().await.insert(socket_id.to_string(), viewed.clone()),
+            None => self.focus.write().await.remove(socket_id)
+        };
+
+        let read = match &focus {
+            Some(viewed) => match self.db.mark_conversation_read(username, viewed).await? {
+                (0, 0) => None,
+                (notifications, mentions) => Some(ConversationRead {
+                    focus: viewed.clone(),
+                    notifications,
+                    mentions,
+                })
+            },
+            None => None
+        };
+        Ok((focus, read))
+    }
+
+    /// Forget the focus of a disconnected socket, here and in `sockets`
+    pub async fn clear_focus(&self, socket_id: &str) {
+        if self.focus.write().await.remove(socket_id).is_some() {
+            if let Err(e) = self.db.set_socket_focus(socket_id, None).await {
+                warn!("Error clearing the focus of {}: {:?}", socket_id, e);
+            }
+        }
+    }
+
+    /// Refresh the linked sockets of this server in `sockets`, so the other instances keep trusting their focus and
+    /// session, run every `SOCKET_HEARTBEAT` by `build_app`
+    pub async fn heartbeat(&self) {
+        let sockets = self.socket_map.read().await.keys().cloned().collect::<Vec<String>>();
+        if sockets.is_empty() {
+            return;
+        }
+        if let Err(e) = self.db.touch_sockets(&sockets).await {
+            warn!("Error refreshing {} sockets: {:?}", sockets.len(), e);
+        }
+    }
+
+    /// Whether a socket of `username` is viewing `focus`, the sockets of this server are checked first
+    /// and the other instances through `sockets`
+    pub async fn is_viewing(&self, username: &str, focus: &Focus) -> bool {
+        {
+            let _socket_map = self.socket_map.read().await;
+            let _focus = self.focus.read().await;
+            let local = _focus.iter().any(|(socket, viewed)| {
+                viewed.eq(focus) && _socket_map.get(socket).is_some_and(|user| user.eq(username))
+            });
+            if local {
+                return true;
+            }
+        }
+
+        self.db.is_focused(username, focus).await.unwrap_or_else(|e| {
+            warn!("Error reading the focus of {}: {:?}", username, e);
+            false
+        })
+    }
+
+    /// Whether a socket linked `username` on this server
+    pub async fn is_online(&self, username: &str) -> bool {
+        self.socket_map.read().await.values().any(|user| user.eq(username))
+    }
+
+    pub async fn set_hide_blocked(&self, socket_id: String, hide: bool) {
+        let mut _hide_blocked = self.hide_blocked.write().await;
+        if hide {
+            _hide_blocked.insert(socket_id);
+        } else {
+            _hide_blocked.remove(&socket_id);
+        }
+    }
+
+    /// Sockets of `room` which must not receive the messages of `sender`:
+    /// the ones which asked to hide the blocked users and whose user blocked `sender`
+    pub async fn hidden_sockets(&self, from: &impl Broadcaster, room: &str, sender: &str) -> Vec<Sid> {
+        let sockets = from.within_rooms(room.to_string()).sockets().unwrap_or_default();
+        let candidates: Vec<(Sid, String)> = {
+            let _hide_blocked = self.hide_blocked.read().await;
+            let _socket_map = self.socket_map.read().await;
+            sockets.iter()
+                .filter(|socket| _hide_blocked.contains(&socket.id.to_string()))
+                .filter_map(|socket| _socket_map.get(&socket.id.to_string()).map(|user| (socket.id, user.clone())))
+                .collect()
+        };
+        if candidates.is_empty() {
+            return vec![];
+        }
+
+        let users = candidates.iter().map(|(_, user)| user.clone()).collect::<Vec<String>>();
+        let blocking = self.db.users_listing(sender, &users, BlockListKind::Blocked).await.unwrap_or_default();
+        candidates.into_iter()
+            .filter(|(_, user)| blocking.contains(user))
+            .map(|(sid, _)| sid)
+            .collect()
+    }
+
+    /// Drop the messages of the users blocked by `username`
+    pub async fn filter_blocked(&self, username: &str, messages: Vec<Message>) -> Vec<Message> {
+        match self.db.get_block_list(username).await {
+            Ok(list) if !list.blocked.is_empty() => messages.into_iter()
+                .filter(|message| !list.blocked.contains(&message.sender))
+                .collect(),
+            _ => messages
+        }
+    }
+
+    /// Owned usernames of the sockets currently joined to the room
+    pub async fn users_in_room(&self, from: &impl Broadcaster, room: &str) -> Vec<String> {
+        let sockets = from.within_rooms(room.to_string()).sockets().unwrap_or_default();
+        let _socket_map = self.socket_map.read().await;
+
+        let mut users: Vec<String> = Vec::new();
+        for socket in sockets {
+            if let Some(user) = _socket_map.get(&socket.id.to_string()) {
+                if !users.contains(user) {
+                    users.push(user.clone());
+                }
+            }
+        }
+        users
+    }
+
+    /// Resolve the mentions of a stored room message into the users to notify and record them <br/>
+    /// `@owned_username` targets existing users, `@here` the users connected to the room and `@room` every member of the room,
+    /// the last two require the sender to be an owner or a moderator of the room <br/>
+    /// The sender is never notified about their own message, the rejected mentions are returned as errors <br/>
+    /// Users who blocked the sender are skipped, users who muted the sender get the mention recorded but not delivered
+    pub async fn record_mentions(&self, from: &impl Broadcaster, message: &Message) -> (Vec<(String, MentionResp)>, Vec<String>) {
+        let mut errors: Vec<String> = Vec::new();
+        let message_id = match message.id.as_deref().map(bson::oid::ObjectId::parse_str) {
+            Some(Ok(id)) => id,
+            _ => return (vec![], errors)
+        };
+
+        let mut recipients: Vec<(String, MentionKind)> = Vec::new();
+        for name in mentions(&message.entities) {
+            let (kind, names) = match name.as_str() {
+                "here" | "room" => {
+                    let role = self.db.room_member_role(&message.room, &message.sender).await.ok().flatten();
+                    if !role.map(|role| role.can_mention_all()).unwrap_or(false) {
+                        errors.push(format!("@{} requires the owner or moderator role in {}", name, message.room));
+                        continue;
+                    }
+                    if name.eq("here") {
+                        (MentionKind::Here, self.users_in_room(from, &message.room).await)
+                    } else {
+                        (MentionKind::Room, self.db.room_member_names(&message.room).await.unwrap_or_default())
+                    }
+                }
+                _ => match self.db.check_user_exists(name.clone()).await {
+                    Ok(Some(user)) => (MentionKind::User, vec![user.username]),
+                    _ => continue
+                }
+            };
+
+            for username in names {
+                if username.ne(&message.sender) && !recipients.iter().any(|(u, _)| u.eq(&username)) {
+                    recipients.push((username, kind));
+                }
+            }
+        }
+
+        // blocked senders are dropped, muted ones are recorded without being delivered
+        let names = recipients.iter().map(|(username, _)| username.clone()).collect::<Vec<String>>();
+        let blocking = self.db.users_listing(&message.sender, &names, BlockListKind::Blocked).await.unwrap_or_default();
+        let muting = self.db.users_listing(&message.sender, &names, BlockListKind::Muted).await.unwrap_or_default();
+        recipients.retain(|(username, _)| !blocking.contains(username));
+
+        let mentions = recipients.into_iter().map(|(username, kind)| MentionCollection {
+            id: bson::oid::ObjectId::new(),
+            username,
+            sender: message.sender.clone(),
+            room: message.room.clone(),
+            message_id,
+            message: message.message.clone(),
+            kind,
+            read: false,
+            updated_at: chrono::Utc::now(),
+            created_at: chrono::Utc::now(),
+        }).collect::<Vec<MentionCollection>>();
+
+        if let Err(e) = self.db.insert_mentions(&mentions).await {
+            warn!("Error storing mentions: {:?}", e);
+        }
+
+        let resp = mentions.into_iter()
+            .filter(|mention| !muting.contains(&mention.username))
+            .map(|mention| (mention.username.clone(), Self::mention_resp(mention)))
+            .collect();
+        (resp, errors)
+    }
+
+    pub fn mention_resp(mention: MentionCollection) -> MentionResp {
+        MentionResp {
+            id: mention.id.to_hex(),
+            sender: mention.sender,
+            room: mention.room,
+            message_id: mention.message_id.to_hex(),
+            message: mention.message,
+            kind: mention.kind,
+            read: mention.read,
+            date_time: mention.created_at,
+        }
+    }
+
+    /// Store a notification in the inbox of `username`, returns `None` when it could not be stored
+    pub async fn notify(&self, username: &str, kind: NotificationKind, sender: Option<String>, message: String, reference: Option<String>) -> Option<NotificationResp> {
+        let notification = NotificationCollection {
+            id: bson::oid::ObjectId::new(),
+            username: username.to_string(),
+            kind,
+            sender,
+            message,
+            reference,
+            read: false,
+            updated_at: chrono::Utc::now(),
+            created_at: chrono::Utc::now(),
+        };
+
+        match self.db.insert_notification(notification, None).await {
+            Ok(notification) => Some(Self::notification_resp(notification)),
+            Err(e) => {
+                warn!("Error storing notification: {:?}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn notification_inbox(&self, username: &str, unread_only: bool, limit: i64, page: i64) -> Result<NotificationInbox, MyError> {
+        let notifications = self.db.get_notifications(username, unread_only, limit, page).await?;
+        let unread = self.db.count_unread_notifications(username).await?;
+
+        Ok(NotificationInbox {
+            notifications: notifications.into_iter().map(Self::notification_resp).collect(),
+            unread,
+        })
+    }
+
+    pub fn notification_resp(notification: NotificationCollection) -> NotificationResp {
+        NotificationResp {
+            id: notification.id.to_hex(),
+            kind: notification.kind,
+            sender: notification.sender,
+            message: notification.message,
+            reference: notification.reference,
+            read: notification.read,
+            date_time: notification.created_at,
+        }
+    }
+
     /// Remove the socket from memory and DB once the socket disconnects from the server
     pub async fn remove_socket(&self, user: User) {
         // let mut _socket_map = self.socket_map.write().await;
@@ -67,12 +468,206 @@ impl SocketState {
     //     (name, socket_id)
     // }
 
-    /// push the messages to top of the queue and insert the message to the database
-    pub async fn insert(&self, room: &String, message: Message) {
+    /// Run an inbound message through the moderation pipeline, before it reaches `insert` or `insert_private_messages` <br/>
+    /// Every outcome is written to the audit collection, held messages are stored for review (see `MessageHeld`)
+    /// and the ID of the held message is returned along with the outcome
+    pub async fn moderate(&self, input: ModerationInput, reply_to: Option<String>, attachments: Vec<String>) -> (ModerationOutcome, Option<String>) {
+        let outcome = self.moderation.run(&input).await;
+
+        let audit = ModerationAuditCollection {
+            id: bson::oid::ObjectId::new(),
+            kind: input.kind,
+            sender: input.sender.clone(),
+            target: input.target.clone(),
+            verdict: outcome.verdict,
+            decisions: outcome.decisions.clone(),
+            created_at: chrono::Utc::now(),
+        };
+        if let Err(e) = self.db.insert_moderation_audit(&audit).await {
+            warn!("Error writing moderation audit: {:?}", e);
+        }
+
+        let mut held_id = None;
+        if outcome.verdict == Verdict::Hold {
+            let held = HeldMessageCollection {
+                id: bson::oid::ObjectId::new(),
+                kind: input.kind,
+                sender: input.sender,
+                target: input.target,
+                message: outcome.text.clone(),
+                reply_to,
+                attachments,
+                reason: outcome.reason.clone().unwrap_or_default(),
+                status: ReviewStatus::Pending,
+                reviewed_by: None,
+                updated_at: chrono::Utc::now(),
+                created_at: chrono::Utc::now(),
+            };
+            match self.db.insert_held_message(held).await {
+                Ok(held) => held_id = Some(held.id.to_hex()),
+                Err(e) => warn!("Error storing held message: {:?}", e)
+            }
+        }
+
+        (outcome, held_id)
+    }
+
+    /// Resolve the target of the report and store it in the review queue <br/>
+    /// Private messages can only be reported by their receiver, and users can not report themselves
+    pub async fn create_report(&self, reporter: &str, req: ReportReq) -> Result<ReportCollection, MyError> {
+        if req.reason.trim().is_empty() {
+            return Err(MyError::BadRequestError("a reason is required".to_string()));
+        }
+
+        let (reported_user, room) = match req.kind {
+            ReportKind::Message => {
+                let oid = bson::oid::ObjectId::parse_str(&req.target).map_err(|_| MyError::InvalidIDError(req.target.clone()))?;
+                let message = self.db.find_message_oid(oid).await.map_err(|_| MyError::NotFoundError(req.target.clone()))?;
+                (message.sender, Some(message.room))
+            }
+            ReportKind::Private => {
+                let oid = bson::oid::ObjectId::parse_str(&req.target).map_err(|_| MyError::InvalidIDError(req.target.clone()))?;
+                let message = self.db.find_private_message_oid(oid).await.map_err(|_| MyError::NotFoundError(req.target.clone()))?;
+                if message.receiver.ne(reporter) {
+                    return Err(MyError::ForbiddenError(format!("{} did not receive message {}", reporter, req.target)));
+                }
+                (message.sender, None)
+            }
+            ReportKind::User => match self.db.check_user_exists(req.target.clone()).await? {
+                Some(user) => (user.username, None),
+                None => return Err(MyError::NotFoundError(req.target.clone()))
+            }
+        };
+        if reported_user.eq(reporter) {
+            return Err(MyError::BadRequestError("users can not report themselves".to_string()));
+        }
+
+        self.db.insert_report(ReportCollection {
+            id: bson::oid::ObjectId::new(),
+            reporter: reporter.to_string(),
+            kind: req.kind,
+            target: req.target,
+            reported_user,
+            room,
+            reason: req.reason,
+            status: ReportStatus::Open,
+            moderator: None,
+            action: None,
+            note: None,
+            updated_at: chrono::Utc::now(),
+            created_at: chrono::Utc::now(),
+        }).await
+    }
+
+    pub fn report_resp(report: ReportCollection) -> ReportResp {
+        ReportResp {
+            id: report.id.to_hex(),
+            reporter: report.reporter,
+            kind: report.kind,
+            target: report.target,
+            reported_user: report.reported_user,
+            room: report.room,
+            reason: report.reason,
+            status: report.status,
+            moderator: report.moderator,
+            action: report.action,
+            note: report.note,
+            date_time: report.created_at,
+        }
+    }
+
+    pub fn held_message_resp(held: HeldMessageCollection) -> HeldMessageResp {
+        HeldMessageResp {
+            id: held.id.to_hex(),
+            kind: held.kind,
+            sender: held.sender,
+            target: held.target,
+            message: held.message,
+            reply_to: held.reply_to,
+            attachments: held.attachments,
+            reason: held.reason,
+            status: held.status,
+            reviewed_by: held.reviewed_by,
+            date_time: held.created_at,
+        }
+    }
+
+    /// Drop a deleted message from the memory store, refreshing the reply count of its thread root
+    pub async fn forget_message(&self, room: &str, id: &str, reply_to: Option<String>) {
+        let mut _messages = self.messages.write().await;
+        if let Some(_room) = _messages.get_mut(room) {
+            _room.retain(|m| m.id.as_deref() != Some(id));
+            if let Some(root) = reply_to.and_then(|root| _room.iter_mut().find(|m| m.id.as_deref() == Some(root.as_str()))) {
+                root.reply_count = (root.reply_count - 1).max(0);
+            }
+        }
+    }
+
+    /// push the messages to top of the queue and insert the message to the database <br/>
+    /// Returns the stored message carrying its ID, so it can be replied to
+    pub async fn insert(&self, room: &str, message: Message) -> Result<Message, MyError> {
+        let stored = self.db.insert_message(message, None).await?;
+        let stored = self.db.collection_to_message(stored);
+
         let mut _messages = self.messages.write().await;
-        let _room = _messages.entry(room.clone()).or_default();
-        _room.push_front(message.clone());
-        self.db.insert_message(message).await.unwrap();
+        let _room = _messages.entry(room.to_string()).or_default();
+        _room.push_front(stored.clone());
+        Ok(stored)
+    }
+
+    /// Fetch the previews of the first `MAX_PREVIEWS` links and store them on the message <br/>
+    /// Meant to be run in the background after the message is fanned out
+    pub async fn fetch_previews(&self, message_id: &str, room: &str, urls: Vec<String>) -> Vec<LinkPreview> {
+        let mut previews: Vec<LinkPreview> = Vec::new();
+        for url in urls.iter().take(MAX_PREVIEWS) {
+            if let Some(preview) = self.preview_fetcher.fetch(url).await {
+                previews.push(preview);
+            }
+        }
+        if previews.is_empty() {
+            return previews;
+        }
+
+        if let Err(e) = self.db.set_message_previews(message_id, &previews).await {
+            warn!("Error storing link previews: {:?}", e);
+        }
+
+        let mut _messages = self.messages.write().await;
+        if let Some(_room) = _messages.get_mut(room) {
+            if let Some(cached) = _room.iter_mut().find(|m| m.id.as_deref() == Some(message_id)) {
+                cached.previews = previews.clone();
+            }
+        }
+        previews
+    }
+
+    /// Name of the socket room joined by the clients watching a thread
+    pub fn thread_room(root_id: &str) -> String {
+        format!("thread:{}", root_id)
+    }
+
+    /// Insert a reply to a thread, replies are not pushed to the room queue <br/>
+    /// The reply count of the root is refreshed in the memory store as well
+    pub async fn insert_reply(&self, message: Message) -> Result<ThreadUpdate, MyError> {
+        let (reply, root) = self.db.insert_reply(message).await?;
+        let reply = self.db.collection_to_message(reply);
+        let root = self.db.collection_to_message(root);
+
+        let mut _messages = self.messages.write().await;
+        if let Some(_room) = _messages.get_mut(&root.room) {
+            if let Some(cached) = _room.iter_mut().find(|m| m.id.eq(&root.id)) {
+                cached.reply_count = root.reply_count;
+                cached.last_reply_at = root.last_reply_at;
+            }
+        }
+
+        Ok(ThreadUpdate {
+            root_id: root.id.unwrap_or_default(),
+            room: root.room,
+            reply_count: root.reply_count,
+            last_reply_at: root.last_reply_at,
+            reply,
+        })
     }
 
     /// get the messages from the room but not read from the db <br/>
@@ -89,88 +684,456 @@ impl SocketState {
         //     messages_db = self.db.get_messages(None).await.unwrap();
         // }
 
-        return if let Some(messages) = messages_db {
+        if let Some(messages) = messages_db {
             messages.clone()
         } else {
             let _room = _messages.get(room).cloned().unwrap_or_default();
             _room.into_iter().rev().collect()
-        };
+        }
     }
 
-    pub async fn insert_private_messages(&self, message: PrivateMessageReq) -> PrivateMessage {
-        let sender = message.sender.clone().unwrap_or_else(|| "".to_string());
+    /// Store the private message with its initial delivery `status` (`Sent` or `Queued`) <br/>
+    /// With `notify` the message lands in the notification inbox of the receiver too, stored together with the message
+    pub async fn insert_private_messages(&self, message: PrivateMessageReq, status: DeliveryStatus, notify: bool) -> PrivateMessage {
+        let sender = message.sender.clone().unwrap_or_default();
 
         let private_msg = PrivateMessageCollection {
             id: bson::oid::ObjectId::new(),
             sender,
             message: message.message.clone(),
             receiver: message.receiver.clone(),
+            // the attachment IDs are validated by `DB::link_attachments` before reaching here
+            attachments: message.attachments.iter()
+                .filter_map(|id| bson::oid::ObjectId::parse_str(id).ok())
+                .collect(),
+            status,
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
-        let resp = self.db.insert_private_message(private_msg).await.unwrap_or_else(|_| {
+        let stored = if notify {
+            let notification = NotificationCollection {
+                id: bson::oid::ObjectId::new(),
+                username: private_msg.receiver.clone(),
+                kind: NotificationKind::Dm,
+                sender: Some(private_msg.sender.clone()),
+                message: private_msg.message.clone(),
+                reference: Some(private_msg.id.to_hex()),
+                read: false,
+                updated_at: chrono::Utc::now(),
+                created_at: chrono::Utc::now(),
+            };
+            self.db.insert_queued_private_message(private_msg, notification).await
+        } else {
+            self.db.insert_private_message(private_msg, None).await
+        };
+        let resp = stored.unwrap_or_else(|_| {
             PrivateMessageCollection {
                 id: bson::oid::ObjectId::new(),
                 sender: "".to_string(),
                 receiver: "".to_string(),
                 message: String::from("Error: Message not sent!"),
+                attachments: vec![],
+                status: DeliveryStatus::Failed,
                 created_at: chrono::Utc::now(),
                 updated_at: chrono::Utc::now(),
             }
         });
 
+        Self::private_message_resp(resp)
+    }
+
+    /// Private messages queued while the receiver was offline, oldest first, they are `Sent` once returned
+    pub async fn undelivered_private_messages(&self, receiver: &str) -> Vec<PrivateMessage> {
+        match self.db.take_queued_private_messages(receiver).await {
+            Ok(messages) => messages.into_iter().map(Self::private_message_resp).collect(),
+            Err(e) => {
+                warn!("Error reading the private message queue: {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    /// Clear the confirmed messages from the queue of the receiver, returns the status updates for their senders
+    pub async fn confirm_private_messages(&self, receiver: &str, ids: &[String]) -> Result<Vec<(String, PrivateStatus)>, MyError> {
+        let delivered = self.db.mark_private_messages_delivered(receiver, ids).await?;
+
+        Ok(delivered.into_iter().map(|message| (message.sender, PrivateStatus {
+            id: message.id.to_hex(),
+            receiver: message.receiver,
+            status: DeliveryStatus::Delivered,
+        })).collect())
+    }
+
+    pub fn private_message_resp(message: PrivateMessageCollection) -> PrivateMessage {
         PrivateMessage {
-            message: resp.message,
-            sender: resp.sender,
-            receiver: resp.receiver,
-            date_time: resp.created_at,
+            id: Some(message.id.to_hex()),
+            message: message.message,
+            sender: message.sender,
+            receiver: message.receiver,
+            attachments: message.attachments.iter().map(|id| id.to_hex()).collect(),
+            status: message.status,
+            sender_profile: None,
+            date_time: message.created_at,
         }
     }
 
-    pub async fn handle_user(&self, user: User) -> UserResp {
-        let resp = self.db.handle_user(user).await.unwrap();
+    pub async fn handle_user(&self, user: User) -> Result<UserResp, MyError> {
+        let resp = self.db.link_user(user).await?;
 
-        UserResp {
+        Ok(UserResp {
             owned_uname: resp.owned_uname,
             cur_gen_uname: resp.cur_gen_uname,
+            session_token: None,
             updated_at: resp.updated_at,
             created_at: resp.created_at,
+        })
+    }
+
+    pub fn check_username_format(username: &str) -> Result<(), MyError> {
+        if !USERNAME.is_match(username) {
+            return Err(MyError::BadRequestError("a username has 3 to 32 letters, digits or underscores".to_string()));
+        }
+        let lowercase = username.to_lowercase();
+        if RESERVED_USERNAMES.iter().any(|reserved| lowercase.eq(reserved) || lowercase.starts_with(&format!("{}_", reserved))) {
+            return Err(MyError::BadRequestError(format!("{} is reserved", username)));
         }
+        Ok(())
     }
 
-    /// Implementation for handling the event when the user is in the private window for chat
-    pub async fn handle_private_joined(&self, user: InPrivate) -> InPrivate {
-         match self.db.handle_private_joined(user).await {
-            Ok(res) => {
-                InPrivate {
-                    in_private: res.in_private,
-                    username: res.owned_username,
+    /// Check the identity `token` of `username`, which is then followed to its current owned username when it was renamed <br/>
+    /// A username linked for the first time has to be a valid new username (see `check_new_username`)
+    pub async fn verify_user(&self, username: &str, token: Option<&str>) -> Result<VerifiedUser, MyError> {
+        let token = token.ok_or(MyError::UnauthorizedError("an identity token is required".to_string()))?;
+        self.identity.verify(token, username)?;
+        match self.db.resolve_username(username).await? {
+            Some(current) => Ok(VerifiedUser(current)),
+            None => {
+                self.check_new_username(username).await?;
+                Ok(VerifiedUser(username.to_string()))
+            }
+        }
+    }
+
+    /// Owned username taken on registration or with a rename, well formed (see `check_username_format`) and not the name
+    /// of a room: the DMs are delivered to the socket room named after the username
+    pub async fn check_new_username(&self, username: &str) -> Result<(), MyError> {
+        Self::check_username_format(username)?;
+        if self.db.room_exists(username).await? {
+            return Err(MyError::ConflictError(format!("{} is the name of a room", username)));
+        }
+        Ok(())
+    }
+
+    /// Link the socket to the verified user in `sockets` with a new session token of the HTTP API, returned to be sent
+    /// in `user_handled`. Only its digest is stored
+    pub async fn open_session(&self, socket_id: &str, user: &VerifiedUser) -> Result<String, MyError> {
+        let token = new_session_token();
+        self.db.link_socket(socket_id, user.username(), &session_digest(&token)).await?;
+        Ok(token)
+    }
+
+    /// Rename the owned username `old` linked to the connections, the sockets mapped to it follow the new name
+    pub async fn rename_user(&self, old: &str, new: &str) -> Result<UserRenamed, MyError> {
+        let new = new.trim();
+        if new.eq(old) {
+            return Err(MyError::BadRequestError("the new username must differ from the current one".to_string()));
+        }
+        self.check_new_username(new).await?;
+
+        self.db.rename_user(old, new, self.rename_policy.cooldown, self.rename_policy.grace).await?;
+        for user in self.socket_map.write().await.values_mut().filter(|user| user.as_str() == old) {
+            *user = new.to_string();
+        }
+        for focus in self.focus.write().await.values_mut() {
+            if let Focus::Dm(peer) = focus {
+                if peer.as_str() == old {
+                    *peer = new.to_string();
                 }
-            },
+            }
+        }
+
+        Ok(UserRenamed {
+            old_username: old.to_string(),
+            new_username: new.to_string(),
+            redirect_until: chrono::Utc::now() + self.rename_policy.grace,
+        })
+    }
+
+    /// Validate the `patch` of the profile of `username`, the avatar must be an image the user uploaded and did not
+    /// attach to a message, it becomes readable by everyone
+    pub async fn update_profile(&self, username: &str, patch: ProfilePatch) -> Result<ProfileResp, MyError> {
+        let too_long = [
+            ("display_name", &patch.display_name, MAX_DISPLAY_NAME),
+            ("bio", &patch.bio, MAX_BIO),
+            ("status_text", &patch.status_text, MAX_STATUS_TEXT),
+        ];
+        if let Some((field, _, max)) = too_long.iter().find(|(_, value, max)| value.as_ref().is_some_and(|value| value.chars().count() > *max)) {
+            return Err(MyError::BadRequestError(format!("{} is limited to {} characters", field, max)));
+        }
+        if let Some(timezone) = patch.timezone.as_deref().map(str::trim).filter(|timezone| !timezone.is_empty()) {
+            if !TIMEZONE.is_match(timezone) {
+                return Err(MyError::BadRequestError(format!("invalid timezone: {}", timezone)));
+            }
+        }
+        if let Some(avatar) = patch.avatar.as_deref().map(str::trim).filter(|avatar| !avatar.is_empty()) {
+            let attachment = self.db.find_attachment(avatar).await?;
+            let unbound = attachment.room.is_none() && attachment.participants.is_empty();
+            if attachment.uploader.ne(username) || !attachment.content_type.starts_with("image/") || !unbound {
+                return Err(MyError::BadRequestError("the avatar must be an image uploaded by the user and not attached to a message".to_string()));
+            }
+        }
+
+        let user = self.db.update_profile(username, &patch).await?;
+        Ok(Self::profile_resp(user))
+    }
+
+    pub fn profile_resp(user: UserCollection) -> ProfileResp {
+        let summary = Self::profile_summary(&user.profile);
+        ProfileResp {
+            username: user.owned_uname,
+            display_name: summary.display_name,
+            bio: user.profile.bio,
+            avatar: summary.avatar,
+            status_until: summary.status_text.as_ref().and(user.profile.status_until.map(|until| until.to_chrono())),
+            status_text: summary.status_text,
+            timezone: user.profile.timezone,
+            online: user.online,
+            updated_at: user.updated_at,
+        }
+    }
+
+    /// The status text is left out once expired
+    pub fn profile_summary(profile: &UserProfile) -> ProfileSummary {
+        let expired = profile.status_until.is_some_and(|until| until.to_chrono() <= chrono::Utc::now());
+        ProfileSummary {
+            display_name: profile.display_name.clone(),
+            avatar: profile.avatar.map(|id| id.to_hex()),
+            status_text: profile.status_text.clone().filter(|_| !expired),
+        }
+    }
+
+    /// Profiles of the senders of a page of messages, see `Messages`
+    pub async fn profile_summaries(&self, usernames: Vec<String>) -> HashMap<String, ProfileSummary> {
+        match self.db.profiles(usernames).await {
+            Ok(profiles) => profiles.iter().map(|(username, profile)| (username.clone(), Self::profile_summary(profile))).collect(),
             Err(e) => {
-                InPrivate {
-                    in_private: false,
-                    username: format!("ERROR: {:?}", e).to_owned(),
-                }
+                warn!("Error reading profiles: {:?}", e);
+                HashMap::new()
             }
         }
     }
 
-    /// Implementation for handling the event when the user is in the private window for chat
-    pub async fn handle_private_left(&self, user: InPrivate) -> InPrivate {
-        match self.db.handle_private_left(user).await {
-            Ok(res) => {
-                InPrivate {
-                    in_private: res.in_private,
-                    username: res.owned_username,
-                }
-            },
+    /// Profile embedded in the live message events of `username`
+    pub async fn sender_profile(&self, username: &str) -> Option<ProfileSummary> {
+        self.profile_summaries(vec![username.to_string()]).await.remove(username)
+    }
+
+    /// Rooms told about a change of `username`: its own room, the rooms its `sockets` joined, its contacts and the users
+    /// it exchanged DMs with
+    pub async fn audience(&self, sockets: Vec<SocketRef>, username: &str) -> Vec<String> {
+        let mut rooms: Vec<String> = vec![username.to_string()];
+        for socket in sockets {
+            rooms.extend(socket.rooms().unwrap_or_default().into_iter().map(|room| room.to_string()));
+        }
+        rooms.extend(self.accepted_contacts(username).await);
+        match self.db.dm_contacts(username).await {
+            Ok(contacts) => rooms.extend(contacts),
+            Err(e) => warn!("Error reading the DM contacts: {:?}", e)
+        }
+        rooms.sort();
+        rooms.dedup();
+        rooms
+    }
+
+    /// Send a friend request to `target`, which must exist and not have blocked `username`
+    pub async fn send_contact_request(&self, username: &str, target: &str) -> Result<ContactCollection, MyError> {
+        let target = self.db.resolve_username(target).await?.ok_or(MyError::NotFoundError(target.to_string()))?;
+        if target.eq(username) {
+            return Err(MyError::BadRequestError("a user can not add itself as a contact".to_string()));
+        }
+        // blocked requesters only get a generic failure, like the blocked DMs
+        if let UserRelation::Blocked = self.db.user_relation(&target, username).await? {
+            return Err(MyError::ForbiddenError("the request could not be sent".to_string()));
+        }
+        self.db.send_contact_request(username, &target).await
+    }
+
+    /// Contact list of `username` with the presence of each contact, split by state
+    pub async fn contact_list(&self, username: &str) -> Result<ContactList, MyError> {
+        let contacts = self.db.contacts(username).await?;
+        let mut profiles = self.profile_summaries(contacts.iter().map(|contact| contact.other(username)).collect()).await;
+
+        let mut list = ContactList { contacts: vec![], incoming: vec![], outgoing: vec![] };
+        for contact in contacts {
+            let other = contact.other(username);
+            let state = Self::contact_state(&contact, username);
+            let resp = ContactResp {
+                online: state == ContactState::Accepted && self.is_online(&other).await,
+                profile: profiles.remove(&other),
+                username: other,
+                state,
+                since: contact.updated_at,
+            };
+            match state {
+                ContactState::Accepted => list.contacts.push(resp),
+                ContactState::Incoming => list.incoming.push(resp),
+                ContactState::Outgoing => list.outgoing.push(resp),
+            }
+        }
+        Ok(list)
+    }
+
+    /// Owned usernames of the accepted contacts of `username`
+    pub async fn accepted_contacts(&self, username: &str) -> Vec<String> {
+        match self.db.contacts(username).await {
+            Ok(contacts) => contacts.into_iter()
+                .filter(|contact| contact.status == ContactStatus::Accepted)
+                .map(|contact| contact.other(username))
+                .collect(),
             Err(e) => {
-                InPrivate {
-                    in_private: false,
-                    username: format!("ERROR: {:?}", e).to_owned(),
-                }
+                warn!("Error reading contacts: {:?}", e);
+                vec![]
             }
         }
     }
-}
\ No newline at end of file
+
+    /// State of `contact` seen from `username`
+    pub fn contact_state(contact: &ContactCollection, username: &str) -> ContactState {
+        match contact.status {
+            ContactStatus::Accepted => ContactState::Accepted,
+            ContactStatus::Pending if contact.addressee.eq(username) => ContactState::Incoming,
+            ContactStatus::Pending => ContactState::Outgoing,
+        }
+    }
+
+    /// Create the group DM of `creator` and `req.participants`, or return the existing one of the same users <br/>
+    /// Every participant must accept DMs from the creator, as for a 1:1 DM
+    pub async fn create_group_dm(&self, creator: &str, req: GroupDmReq) -> Result<GroupDmCollection, MyError> {
+        if req.name.as_ref().is_some_and(|name| name.chars().count() > MAX_DISPLAY_NAME) {
+            return Err(MyError::BadRequestError(format!("name is limited to {} characters", MAX_DISPLAY_NAME)));
+        }
+
+        let mut participants: Vec<String> = vec![creator.to_string()];
+        for participant in &req.participants {
+            let username = self.db.resolve_username(participant).await?.ok_or(MyError::NotFoundError(participant.clone()))?;
+            participants.push(username);
+        }
+        participants.sort();
+        participants.dedup();
+        if participants.len() < 3 || participants.len() > MAX_GROUP_DM {
+            return Err(MyError::BadRequestError(format!("a group DM has 3 to {} participants", MAX_GROUP_DM)));
+        }
+
+        for participant in participants.iter().filter(|participant| participant.as_str() != creator) {
+            if !self.db.dm_allowed(participant, creator).await? {
+                return Err(MyError::ForbiddenError(format!("{} can not be added to the group", participant)));
+            }
+        }
+        // a block between any two participants, the creator included, keeps them out of the same group
+        for participant in &participants {
+            let blocking = self.db.users_listing(participant, &participants, BlockListKind::Blocked).await?;
+            if let Some(other) = blocking.first() {
+                let added = if other.eq(creator) { participant } else { other };
+                return Err(MyError::ForbiddenError(format!("{} can not be added to the group", added)));
+            }
+        }
+
+        let name = req.name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty());
+        self.db.create_group_dm(creator, participants, name).await
+    }
+
+    /// Store a group message queued for the `recipients`, the `offline` ones which did not mute the sender
+    /// also get a `dm` notification
+    pub async fn insert_group_message(&self, group: &GroupDmCollection, sender: &str, text: String, attachments: &[String], recipients: &[String], offline: &[String]) -> Result<GroupMessage, MyError> {
+        let message = GroupMessageCollection {
+            id: bson::oid::ObjectId::new(),
+            group_id: group.id,
+            sender: sender.to_string(),
+            message: text,
+            // the attachment IDs are validated by `DB::link_attachments` before reaching here
+            attachments: attachments.iter()
+                .filter_map(|id| bson::oid::ObjectId::parse_str(id).ok())
+                .collect(),
+            pending: recipients.to_vec(),
+            updated_at: chrono::Utc::now(),
+            created_at: chrono::Utc::now(),
+        };
+
+        let mut notifications: Vec<NotificationCollection> = Vec::new();
+        for username in offline {
+            if let Ok(UserRelation::Muted) = self.db.user_relation(username, sender).await {
+                continue;
+            }
+            notifications.push(NotificationCollection {
+                id: bson::oid::ObjectId::new(),
+                username: username.clone(),
+                kind: NotificationKind::Dm,
+                sender: Some(sender.to_string()),
+                message: message.message.clone(),
+                reference: Some(group.id.to_hex()),
+                read: false,
+                updated_at: chrono::Utc::now(),
+                created_at: chrono::Utc::now(),
+            });
+        }
+
+        let stored = self.db.insert_group_message(message, notifications).await?;
+        Ok(Self::group_message_resp(stored))
+    }
+
+    /// Queue of the group messages `username` has not confirmed yet, oldest first
+    pub async fn undelivered_group_messages(&self, username: &str) -> Vec<GroupMessage> {
+        match self.db.get_undelivered_group_messages(username).await {
+            Ok(messages) => messages.into_iter().map(Self::group_message_resp).collect(),
+            Err(e) => {
+                warn!("Error reading the group message queue: {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    /// Clear the confirmed messages from the queue of `username`, returns the status updates for their senders
+    pub async fn confirm_group_messages(&self, username: &str, ids: &[String]) -> Result<Vec<(String, GroupStatus)>, MyError> {
+        let delivered = self.db.mark_group_messages_delivered(username, ids).await?;
+
+        Ok(delivered.into_iter().map(|message| (message.sender, GroupStatus {
+            id: message.id.to_hex(),
+            group: message.group_id.to_hex(),
+            username: username.to_string(),
+            status: DeliveryStatus::Delivered,
+        })).collect())
+    }
+
+    pub fn group_dm_resp(group: GroupDmCollection) -> GroupDmResp {
+        GroupDmResp {
+            id: group.id.to_hex(),
+            name: group.name,
+            participants: group.participants,
+            created_by: group.created_by,
+            created_at: group.created_at,
+        }
+    }
+
+    pub fn group_message_resp(message: GroupMessageCollection) -> GroupMessage {
+        GroupMessage {
+            id: message.id.to_hex(),
+            group: message.group_id.to_hex(),
+            sender: message.sender,
+            message: message.message,
+            attachments: message.attachments.iter().map(|id| id.to_hex()).collect(),
+            pending: message.pending,
+            sender_profile: None,
+            date_time: message.created_at,
+        }
+    }
+
+    pub fn username_history_resp(entry: UsernameHistoryCollection) -> UsernameHistoryResp {
+        UsernameHistoryResp {
+            kind: entry.kind,
+            username: entry.username,
+            replaced_by: entry.replaced_by,
+            redirect_until: entry.redirect_until.map(|until| until.to_chrono()),
+            created_at: entry.created_at,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use socketioxide::extract::SocketRef;
+use tracing::Span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Set from `LOG_MESSAGE_BODIES`, message bodies are redacted from the logs unless it is `true`
+static LOG_MESSAGE_BODIES: AtomicBool = AtomicBool::new(false);
+
+/// Install the global subscriber, configured from the environment <br/>
+/// - `RUST_LOG`: env-filter directives (default `info`) <br/>
+/// - `LOG_FORMAT`: `json` (default) or `text` <br/>
+/// - `LOG_MESSAGE_BODIES`: `true` to log the message bodies instead of their length <br/>
+/// - `OTEL_EXPORTER_OTLP_ENDPOINT`: OTLP collector receiving the spans, only with the `otel` feature
+pub fn init_tracing() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    LOG_MESSAGE_BODIES.store(
+        std::env::var("LOG_MESSAGE_BODIES").map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false),
+        Ordering::Relaxed,
+    );
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = match std::env::var("LOG_FORMAT").unwrap_or_default().to_lowercase().as_str() {
+        "text" => tracing_subscriber::fmt::layer().boxed(),
+        _ => tracing_subscriber::fmt::layer().json().with_current_span(true).with_span_list(true).boxed(),
+    };
+
+    let registry = tracing_subscriber::registry().with(fmt_layer);
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel_layer()?);
+
+    registry.with(filter).try_init()?;
+    Ok(())
+}
+
+/// OTLP exporter layer, `None` when `OTEL_EXPORTER_OTLP_ENDPOINT` is not set
+#[cfg(feature = "otel")]
+fn otel_layer<S>() -> Result<Option<impl Layer<S>>, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry_otlp::WithExportConfig;
+
+    let endpoint = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) if !endpoint.is_empty() => endpoint,
+        _ => return Ok(None),
+    };
+    let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or("socketioxide-chat".to_owned());
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new("service.name", service)]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Flush the pending spans before exiting
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Span of the socket connection, created in `on_connect` and kept in the socket extensions <br/>
+/// The event handlers open their span under it, so every event carries the socket ID and the owned user
+pub fn connection_span(_socket: &SocketRef) -> Span {
+    _socket.extensions.get::<Span>().map(|span| span.clone()).unwrap_or_else(Span::none)
+}
+
+/// Message body as printed by the `Debug` impls of the payloads
+pub struct Redacted<'a>(pub &'a str);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_MESSAGE_BODIES.load(Ordering::Relaxed) {
+            fmt::Debug::fmt(self.0, f)
+        } else {
+            write!(f, "<redacted {} chars>", self.0.chars().count())
+        }
+    }
+}
+
+/// Span of an HTTP request, carrying the `x-request-id` set by `SetRequestIdLayer`
+pub fn http_span<B>(request: &axum::http::Request<B>) -> Span {
+    let request_id = request.headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!("request", method = %request.method(), uri = %request.uri(), request_id = %request_id)
+}
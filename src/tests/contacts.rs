@@ -0,0 +1,65 @@
+use reqwest::Method;
+use serde_json::json;
+use super::TestApp;
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn contact_requests_act_for_the_caller() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let alice_token = alice.session("alice").await;
+    let bob_token = bob.session("bob").await;
+
+    let (status, _) = app.http(Method::POST, "/api/contacts/requests", None, Some(json!({ "target": "bob" }))).await;
+    assert_eq!(status, 401);
+    // a `username` in the payload does not change who is acting
+    let (status, sent) = app.http(Method::POST, "/api/contacts/requests", Some(&alice_token), Some(json!({ "username": "bob", "target": "bob" }))).await;
+    assert_eq!(status, 201);
+    assert_eq!(sent, json!({ "username": "bob", "state": "outgoing" }));
+    assert_eq!(bob.recv("contact_updated").await, json!({ "username": "alice", "state": "incoming" }));
+
+    let (_, list) = app.http(Method::GET, "/api/contacts", Some(&bob_token), None).await;
+    assert_eq!(list["incoming"][0]["username"], "alice");
+    assert!(list["contacts"].as_array().unwrap().is_empty());
+
+    let (status, _) = app.http(Method::POST, "/api/contacts/requests/accept", Some(&bob_token), Some(json!({ "target": "alice" }))).await;
+    assert_eq!(status, 200);
+    let (_, list) = app.http(Method::GET, "/api/contacts", Some(&alice_token), None).await;
+    assert_eq!(list["contacts"][0]["username"], "bob");
+    assert_eq!(list["contacts"][0]["state"], "accepted");
+
+    let (status, _) = app.http(Method::DELETE, "/api/contacts", Some(&alice_token), Some(json!({ "target": "bob" }))).await;
+    assert_eq!(status, 200);
+    let (_, list) = app.http(Method::GET, "/api/contacts", Some(&bob_token), None).await;
+    assert!(list["contacts"].as_array().unwrap().is_empty());
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn only_the_user_sets_their_dm_policy() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let alice_token = alice.session("alice").await;
+    let bob_token = bob.session("bob").await;
+
+    let policy = json!({ "dm_policy": "contacts_only" });
+    let (status, _) = app.http(Method::PUT, "/api/users/alice/dm-policy", Some(&bob_token), Some(policy.clone())).await;
+    assert_eq!(status, 403);
+    let (status, _) = app.http(Method::PUT, "/api/users/alice/dm-policy", Some(&alice_token), Some(policy.clone())).await;
+    assert_eq!(status, 200);
+
+    // bob is not a contact of alice, the DM is refused
+    bob.emit("private", json!({ "receiver": "alice", "message": "hi" })).await;
+    let error = bob.recv("event_error").await;
+    assert_eq!(error["event"], "private");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
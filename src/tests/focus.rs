@@ -0,0 +1,76 @@
+use bson::doc;
+use chrono::Utc;
+use reqwest::Method;
+use serde_json::json;
+use crate::db_model::Focus;
+use super::TestApp;
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn stale_sockets_lose_their_focus_and_session_until_the_heartbeat() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let alice_token = alice.session("alice").await;
+    bob.handle("bob").await;
+
+    alice.emit("focus", json!({ "focus": { "kind": "dm", "target": "bob" } })).await;
+    alice.recv("focused").await;
+    let viewed = Focus::Dm("bob".to_string());
+    assert!(app.state.db.is_focused("alice", &viewed).await.unwrap());
+
+    // the instance holding the socket went down without a disconnect
+    let stale = Utc::now() - chrono::Duration::minutes(10);
+    app.state.db.sockets_collection.as_ref().unwrap()
+        .update_many(doc! {}, doc! {"$set": {"updated_at": stale}}, None).await.unwrap();
+    assert!(!app.state.db.is_focused("alice", &viewed).await.unwrap());
+    assert!(app.state.db.user_focus("alice").await.unwrap().is_empty());
+    let (status, _) = app.http(Method::GET, "/api/contacts", Some(&alice_token), None).await;
+    assert_eq!(status, 401);
+
+    app.state.socket_state.heartbeat().await;
+    assert!(app.state.db.is_focused("alice", &viewed).await.unwrap());
+    assert_eq!(app.state.db.user_focus("alice").await.unwrap(), vec![viewed]);
+    let (status, _) = app.http(Method::GET, "/api/contacts", Some(&alice_token), None).await;
+    assert_eq!(status, 200);
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn private_joined_and_left_are_aliases_of_focus() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    // the payload of the older clients, without the peer
+    alice.emit("private_joined", json!({ "in_private": true, "username": "alice" })).await;
+    assert_eq!(alice.recv("event_error").await["event"], "private_joined");
+
+    alice.emit("private_joined", json!({ "in_private": true, "username": "alice", "peer": "bob" })).await;
+    let joined = alice.recv("joined_private").await;
+    assert_eq!(joined["in_private"], true);
+    assert_eq!(joined["focus"], json!([{ "kind": "dm", "target": "bob" }]));
+    assert_eq!(app.state.db.user_focus("alice").await.unwrap(), vec![Focus::Dm("bob".to_string())]);
+
+    alice.emit("private_left", json!({ "in_private": false, "username": "alice" })).await;
+    let left = alice.recv("left_private").await;
+    assert_eq!(left["in_private"], false);
+    assert_eq!(left["focus"], json!([]));
+    assert!(app.state.db.user_focus("alice").await.unwrap().is_empty());
+
+    // leaving the private window keeps the focus on a room
+    alice.emit("focus", json!({ "focus": { "kind": "room", "target": "general" } })).await;
+    alice.recv("focused").await;
+    alice.emit("private_left", json!({})).await;
+    assert_eq!(alice.recv("left_private").await["focus"], json!([{ "kind": "room", "target": "general" }]));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
@@ -0,0 +1,101 @@
+use reqwest::Method;
+use serde_json::json;
+use super::TestApp;
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn group_history_is_for_the_calling_participant() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let mut carol = app.client().await;
+    let alice_token = alice.session("alice").await;
+    let carol_token = carol.session("carol").await;
+    bob.handle("bob").await;
+
+    alice.emit("create_group_dm", json!({ "participants": ["bob"], "name": "pair" })).await;
+    let group = alice.recv("group_dm").await;
+    let id = group["id"].as_str().unwrap().to_string();
+    let path = format!("/api/group-dms/{}/messages", id);
+
+    let (status, _) = app.http(Method::GET, "/api/group-dms", None, None).await;
+    assert_eq!(status, 401);
+    let (status, _) = app.http(Method::GET, &path, None, None).await;
+    assert_eq!(status, 401);
+    // a `username` in the query does not change who is reading
+    let (status, _) = app.http(Method::GET, &format!("{}?username=alice", path), Some(&carol_token), None).await;
+    assert_eq!(status, 403);
+
+    let (status, groups) = app.http(Method::GET, "/api/group-dms", Some(&alice_token), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(groups[0]["id"], id.as_str());
+    let (_, groups) = app.http(Method::GET, "/api/group-dms", Some(&carol_token), None).await;
+    assert!(groups.as_array().unwrap().is_empty());
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    carol.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn participants_blocking_each_other_can_not_share_a_group() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let mut carol = app.client().await;
+    alice.handle("alice").await;
+    let bob_token = bob.session("bob").await;
+    carol.handle("carol").await;
+
+    let (status, _) = app.http(Method::POST, "/api/blocks", Some(&bob_token), Some(json!({ "target": "carol", "list": "blocked" }))).await;
+    assert_eq!(status, 200);
+
+    alice.emit("create_group_dm", json!({ "participants": ["bob", "carol"] })).await;
+    let error = alice.recv("event_error").await;
+    assert_eq!(error["event"], "create_group_dm");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    carol.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn group_messages_skip_recipients_blocking_the_sender() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let mut carol = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+    let carol_token = carol.session("carol").await;
+
+    alice.emit("create_group_dm", json!({ "participants": ["bob", "carol"] })).await;
+    let id = alice.recv("group_dm").await["id"].as_str().unwrap().to_string();
+    carol.recv("group_dm").await;
+
+    // carol blocks bob once the group exists
+    let (status, _) = app.http(Method::POST, "/api/blocks", Some(&carol_token), Some(json!({ "target": "bob", "list": "blocked" }))).await;
+    assert_eq!(status, 200);
+
+    bob.emit("group_message", json!({ "group": id, "message": "from bob" })).await;
+    let received = alice.recv("group_resp").await;
+    assert_eq!(received["message"], "from bob");
+    assert_eq!(received["pending"], json!(["alice"]));
+
+    alice.emit("group_message", json!({ "group": id, "message": "from alice" })).await;
+    // the first group message carol gets is the one of alice
+    assert_eq!(carol.recv("group_resp").await["message"], "from alice");
+
+    let (_, history) = app.http(Method::GET, &format!("/api/group-dms/{}/messages", id), Some(&carol_token), None).await;
+    let messages: Vec<_> = history["data"].as_array().unwrap().iter().map(|m| m["message"].clone()).collect();
+    assert_eq!(messages, vec![json!("from alice")]);
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    carol.disconnect().await;
+    app.cleanup().await;
+}
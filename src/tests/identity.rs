@@ -0,0 +1,81 @@
+use reqwest::Method;
+use serde_json::json;
+use crate::auth::IdentityVerifier;
+use super::{identity_token, TestApp};
+
+#[test]
+fn identity_tokens_are_bound_to_the_username_and_the_secret() {
+    let verifier = IdentityVerifier::new(Some("secret".to_string()));
+    let expires_at = chrono::Utc::now().timestamp() + 60;
+    let token = verifier.sign("alice.smith", expires_at).unwrap();
+
+    assert!(verifier.verify(&token, "alice.smith").is_ok());
+    assert!(verifier.verify(&token, "bob").is_err());
+    assert!(IdentityVerifier::new(Some("other".to_string())).verify(&token, "alice.smith").is_err());
+    assert!(verifier.verify(&token.replace("alice.smith", "bob"), "bob").is_err());
+    assert!(verifier.verify("alice.smith", "alice.smith").is_err());
+
+    let expired = verifier.sign("alice", chrono::Utc::now().timestamp() - 1).unwrap();
+    assert!(verifier.verify(&expired, "alice").is_err());
+    // nothing is accepted while the secret is not configured
+    assert!(IdentityVerifier::new(None).sign("alice", expires_at).is_none());
+    assert!(IdentityVerifier::new(None).verify(&token, "alice.smith").is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn user_handle_needs_an_identity_token_for_the_username() {
+    let app = TestApp::spawn().await;
+    let mut mallory = app.client().await;
+
+    mallory.emit("user_handle", json!({ "username": "alice", "generated_username": mallory.username })).await;
+    assert_eq!(mallory.recv("event_error").await["event"], "user_handle");
+    mallory.emit("user_handle", json!({
+        "username": "alice",
+        "generated_username": mallory.username,
+        "token": identity_token("mallory"),
+    })).await;
+    assert_eq!(mallory.recv("event_error").await["event"], "user_handle");
+    // the generated username of another connection
+    mallory.emit("user_handle", json!({
+        "username": "mallory",
+        "generated_username": "someone-else",
+        "token": identity_token("mallory"),
+    })).await;
+    assert_eq!(mallory.recv("event_error").await["event"], "user_handle");
+    assert!(app.state.socket_state.socket_user(&app.state.io.sockets().unwrap()[0].id.to_string()).await.is_none());
+
+    let handled = mallory.handle("mallory").await;
+    assert_eq!(handled["owned_uname"], "mallory");
+
+    mallory.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn relinking_drops_the_previous_user() {
+    let app = TestApp::spawn().await;
+    let mut shared = app.client().await;
+    let mut carol = app.client().await;
+    carol.handle("carol").await;
+    let alice_token = shared.session("alice").await;
+    shared.emit("focus", json!({ "focus": { "kind": "dm", "target": "carol" } })).await;
+    shared.recv("focused").await;
+
+    let bob_token = shared.session("bob").await;
+    let (status, _) = app.http(Method::GET, "/api/contacts", Some(&alice_token), None).await;
+    assert_eq!(status, 401);
+    let (status, _) = app.http(Method::GET, "/api/contacts", Some(&bob_token), None).await;
+    assert_eq!(status, 200);
+    assert!(app.state.db.user_focus("alice").await.unwrap().is_empty());
+
+    // the DM to alice no longer reaches the socket, the next one it gets is the one to bob
+    carol.emit("private", json!({ "receiver": "alice", "message": "to alice" })).await;
+    carol.emit("private", json!({ "receiver": "bob", "message": "to bob" })).await;
+    assert_eq!(shared.recv("resp").await["message"], "to bob");
+
+    shared.disconnect().await;
+    carol.disconnect().await;
+    app.cleanup().await;
+}
@@ -0,0 +1,83 @@
+use crate::content::{parse_preview, HttpPreviewFetcher, LinkPreview};
+
+const URL: &str = "https://example.com/page";
+
+#[test]
+fn reads_the_opengraph_tags() {
+    let html = r#"<html><head>
+        <meta property="og:title" content="Example &amp; Co">
+        <META NAME="description" CONTENT='Plain description'>
+        <meta property="og:description" content="OG description" />
+        <meta property="og:image" content=https://example.com/a.png>
+        <meta property="og:site_name" content="Example">
+        <title>Ignored title</title>
+    </head></html>"#;
+
+    assert_eq!(parse_preview(URL, html), LinkPreview {
+        url: URL.to_string(),
+        title: Some("Example & Co".to_string()),
+        description: Some("OG description".to_string()),
+        image: Some("https://example.com/a.png".to_string()),
+        site_name: Some("Example".to_string()),
+    });
+}
+
+#[test]
+fn falls_back_to_the_title_tag() {
+    let preview = parse_preview(URL, "<head><TITLE lang=\"en\">  A &lt;title&gt; </TITLE></head>");
+    assert_eq!(preview.title.as_deref(), Some("A <title>"));
+}
+
+#[test]
+fn ignores_a_closing_title_before_the_opening_one() {
+    let preview = parse_preview(URL, "</title><title>Real title</title>");
+    assert_eq!(preview.title.as_deref(), Some("Real title"));
+
+    let preview = parse_preview(URL, "</title><title>never closed");
+    assert_eq!(preview.title, None);
+}
+
+#[test]
+fn keeps_the_offsets_of_non_ascii_text() {
+    // `İ` grows when lowercased with `to_lowercase`, which used to shift the offsets used to slice the html
+    let html = "<p>İİİİ</p><meta property=\"og:title\" content=\"Ünïcödé\"><title>İstanbul</title>";
+    let preview = parse_preview(URL, html);
+    assert_eq!(preview.title.as_deref(), Some("Ünïcödé"));
+
+    let preview = parse_preview(URL, "<p>İİİİ</p><title>İstanbul</title>");
+    assert_eq!(preview.title.as_deref(), Some("İstanbul"));
+}
+
+#[test]
+fn only_matches_whole_attribute_names() {
+    let preview = parse_preview(URL, r#"<meta property="og:site_name" og:name="x" content="Site">"#);
+    assert_eq!(preview.site_name.as_deref(), Some("Site"));
+    assert_eq!(preview.title, None);
+}
+
+#[test]
+fn reads_unquoted_values_up_to_the_end_of_the_tag() {
+    let preview = parse_preview(URL, "<meta property=og:image content=https://example.com/a.png/>");
+    assert_eq!(preview.image.as_deref(), Some("https://example.com/a.png"));
+}
+
+#[test]
+fn only_public_hosts_are_fetched() {
+    let public = |url: &str| HttpPreviewFetcher::is_public(&reqwest::Url::parse(url).unwrap());
+
+    assert!(public("https://example.com/page"));
+    assert!(public("http://93.184.216.34/"));
+    assert!(public("http://[2606:2800:220:1::]/"));
+    assert!(!public("ftp://example.com/"));
+    assert!(!public("http://localhost:4040/"));
+    assert!(!public("http://127.0.0.1/"));
+    assert!(!public("http://10.0.0.1/"));
+    assert!(!public("http://[::1]/"));
+    assert!(!public("http://[fd00::1]/"));
+    // IPv4-mapped IPv6 literals are the IPv4 address they map
+    assert!(!public("http://[::ffff:127.0.0.1]/"));
+    assert!(!public("http://[::ffff:10.0.0.1]/"));
+    assert!(!public("http://[::ffff:169.254.169.254]/"));
+    assert!(!public("http://[::ffff:0.0.0.0]/"));
+    assert!(public("http://[::ffff:93.184.216.34]/"));
+}
@@ -0,0 +1,70 @@
+use crate::migrations::{check_indexes, Migrator};
+use super::TestApp;
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn migrating_again_is_a_no_op() {
+    let app = TestApp::spawn().await;
+    let migrator = Migrator::new(&app.state.db).unwrap();
+
+    assert_eq!(migrator.pending().await.unwrap(), 0);
+    assert!(migrator.migrate(false).await.unwrap().is_empty());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn migrated_database_has_no_index_drift() {
+    let app = TestApp::spawn().await;
+
+    let drift = check_indexes(&app.state.db).await.unwrap();
+    assert!(drift.is_ok(), "{:?}", drift);
+    assert!(drift.unexpected.is_empty(), "{:?}", drift.unexpected);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn rollback_reverts_every_migration_and_migrate_reapplies_them() {
+    let app = TestApp::spawn().await;
+    let migrator = Migrator::new(&app.state.db).unwrap();
+    let total = migrator.status().await.unwrap().len();
+
+    let steps = migrator.rollback(0, false).await.unwrap();
+    assert_eq!(steps.len(), total);
+    assert!(steps.windows(2).all(|pair| pair[0].version > pair[1].version), "newest first");
+    assert_eq!(migrator.pending().await.unwrap(), total);
+    assert!(!check_indexes(&app.state.db).await.unwrap().is_ok());
+
+    // the dry-run plans every migration without applying any
+    let planned = migrator.migrate(true).await.unwrap();
+    assert_eq!(planned.len(), total);
+    assert!(planned.iter().all(|step| !step.applied));
+    assert_eq!(migrator.pending().await.unwrap(), total);
+
+    let applied = migrator.migrate(false).await.unwrap();
+    assert_eq!(applied.len(), total);
+    assert_eq!(migrator.pending().await.unwrap(), 0);
+    assert!(check_indexes(&app.state.db).await.unwrap().is_ok());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn partial_rollback_stops_at_the_target() {
+    let app = TestApp::spawn().await;
+    let migrator = Migrator::new(&app.state.db).unwrap();
+    let total = migrator.status().await.unwrap().len();
+
+    let steps = migrator.rollback(total as i64 - 2, false).await.unwrap();
+    assert_eq!(steps.iter().map(|step| step.version).collect::<Vec<i64>>(), vec![total as i64, total as i64 - 1]);
+    assert_eq!(migrator.pending().await.unwrap(), 2);
+
+    migrator.migrate(false).await.unwrap();
+    assert_eq!(migrator.pending().await.unwrap(), 0);
+
+    app.cleanup().await;
+}
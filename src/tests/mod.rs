@@ -0,0 +1,228 @@
+//! Integration test harness <br/>
+//! Boots the full app from `build_app` on an ephemeral port, against a database created for the test, and drives it
+//! with real Socket.IO clients. It needs a MongoDB deployment at `TEST_MONGO_URI` (falling back to `MONGO_URI`),
+//! so those tests are `#[ignore]`d and run with `cargo test -- --include-ignored`, where an unreachable MongoDB fails
+//! them. The CI workflow (`.github/workflows/ci.yml`) runs them against a MongoDB service on every push. The store is
+//! not embedded: the queries, the unique indexes and the units of work are MongoDB's own, a stand-in would not cover them.
+//! The parsers are tested on their own, without the app.
+
+mod contacts;
+mod focus;
+mod group_dms;
+mod identity;
+mod link_previews;
+mod migrations;
+mod profiles;
+mod reports;
+mod socket_flows;
+mod user_upsert;
+mod usernames;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use bson::doc;
+use dotenv::dotenv;
+use futures_util::FutureExt;
+use mongodb::options::ClientOptions;
+use rust_socketio::asynchronous::{Client, ClientBuilder};
+use rust_socketio::Payload;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use crate::{build_app, AppState};
+use crate::auth::IdentityVerifier;
+use crate::blob_store::LocalBlobStore;
+use crate::db::DB;
+use crate::health::Health;
+use crate::migrations::Migrator;
+
+/// How long a client waits for an event before failing the test
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// `IDENTITY_SECRET` of the test apps, the clients sign their identity tokens with it
+const IDENTITY_SECRET: &str = "test-identity-secret";
+/// `MODERATOR_TOKEN` of the test apps, whose only moderator is `moderator`
+pub const MODERATOR_TOKEN: &str = "test-moderator-token";
+/// `ADMIN_TOKEN` of the test apps
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// The app served on `127.0.0.1`, dropped with `cleanup`
+pub struct TestApp {
+    pub addr: SocketAddr,
+    pub state: Arc<AppState>,
+    uri: String,
+    database: String,
+}
+
+impl TestApp {
+    /// Serve the app against a fresh database, panics when MongoDB is unreachable
+    pub async fn spawn() -> TestApp {
+        dotenv().ok();
+        let uri = std::env::var("TEST_MONGO_URI")
+            .or(std::env::var("MONGO_URI"))
+            .unwrap_or("mongodb://localhost:27017".to_owned());
+        if !mongo_reachable(&uri).await {
+            panic!("MongoDB is not reachable, set TEST_MONGO_URI to a running deployment");
+        }
+
+        let database = format!("socketioxide_test_{}", bson::oid::ObjectId::new().to_hex());
+        let mut db = DB::connect(&uri, &database).await.expect("connecting the test database");
+        Migrator::new(&db).unwrap().migrate(false).await.expect("migrating the test database");
+        db.detect_transactions().await.expect("probing the transaction support");
+        let blob_store = LocalBlobStore::new(std::env::temp_dir().join(&database)).await.expect("creating the blob dir");
+        // every test sets the same values, the concurrent writes are harmless
+        std::env::set_var("IDENTITY_SECRET", IDENTITY_SECRET);
+        std::env::set_var("MODERATOR_TOKEN", MODERATOR_TOKEN);
+        std::env::set_var("MODERATORS", "moderator");
+        std::env::set_var("ADMIN_TOKEN", ADMIN_TOKEN);
+        // ready as in `main` once the migrated indexes are checked
+        let health = Arc::new(Health::new());
+        if crate::migrations::check_indexes(&db).await.expect("checking the indexes").is_ok() {
+            health.set_indexes_ensured();
+        }
+        let (app, state) = build_app(db, health, Arc::new(blob_store));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("binding an ephemeral port");
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service()).await.ok();
+        });
+
+        TestApp { addr, state, uri, database }
+    }
+
+    /// Connect a new client, which already received its generated `username`
+    pub async fn client(&self) -> TestClient {
+        TestClient::connect(self.addr).await
+    }
+
+    /// Call the HTTP API with the session token returned in `user_handled`, if any, returns the status and the JSON body
+    pub async fn http(&self, method: reqwest::Method, path: &str, token: Option<&str>, body: Option<Value>) -> (u16, Value) {
+        self.http_with(method, path, token, &[], body).await
+    }
+
+    /// `http` with extra headers, e.g. the `X-Moderator-Token` of the moderation API
+    pub async fn http_with(&self, method: reqwest::Method, path: &str, token: Option<&str>, headers: &[(&str, &str)], body: Option<Value>) -> (u16, Value) {
+        let mut request = reqwest::Client::new().request(method, format!("http://{}{}", self.addr, path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.header("content-type", "application/json").body(body.to_string());
+        }
+        let response = request.send().await.expect("sending the request");
+        let status = response.status().as_u16();
+        let text = response.text().await.unwrap_or_default();
+        (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    /// Drop the database and the blob dir of the test
+    pub async fn cleanup(self) {
+        if let Ok(client) = mongodb::Client::with_uri_str(&self.uri).await {
+            client.database(&self.database).drop(None).await.ok();
+        }
+        tokio::fs::remove_dir_all(std::env::temp_dir().join(&self.database)).await.ok();
+    }
+}
+
+async fn mongo_reachable(uri: &str) -> bool {
+    let Ok(mut options) = ClientOptions::parse(uri).await else {
+        return false;
+    };
+    options.server_selection_timeout = Some(Duration::from_secs(2));
+    let Ok(client) = mongodb::Client::with_options(options) else {
+        return false;
+    };
+    client.database("admin").run_command(doc! {"ping": 1}, None).await.is_ok()
+}
+
+/// Socket.IO client recording every event it receives, in order
+pub struct TestClient {
+    client: Client,
+    events: mpsc::UnboundedReceiver<(String, Value)>,
+    /// generated username received on connect
+    pub username: String,
+}
+
+impl TestClient {
+    async fn connect(addr: SocketAddr) -> TestClient {
+        let (tx, events) = mpsc::unbounded_channel();
+        let client = ClientBuilder::new(format!("http://{}", addr))
+            .reconnect(false)
+            .on_any(move |event, payload, _| {
+                let tx = tx.clone();
+                async move {
+                    if let Payload::Text(values) = payload {
+                        tx.send((String::from(event), values.into_iter().next().unwrap_or(Value::Null))).ok();
+                    }
+                }.boxed()
+            })
+            .connect()
+            .await
+            .expect("connecting the socket.io client");
+
+        let mut client = TestClient { client, events, username: String::new() };
+        client.username = client.recv("username").await.as_str().unwrap_or_default().to_string();
+        client
+    }
+
+    pub async fn emit(&self, event: &str, data: Value) {
+        self.client.emit(event, data).await.expect("emitting the event");
+    }
+
+    /// Wait for the next `event`, the other events received meanwhile are discarded
+    pub async fn recv(&mut self, event: &str) -> Value {
+        let wait = async {
+            while let Some((name, data)) = self.events.recv().await {
+                if name.eq(event) {
+                    return data;
+                }
+            }
+            panic!("the client closed while waiting for {}", event);
+        };
+        tokio::time::timeout(TIMEOUT, wait).await.unwrap_or_else(|_| panic!("timed out waiting for {}", event))
+    }
+
+    /// Link the owned `username` to the connection with `user_handle`, see `session_token` for the HTTP API
+    pub async fn handle(&mut self, username: &str) -> Value {
+        self.emit("user_handle", serde_json::json!({
+            "username": username,
+            "generated_username": self.username,
+            "token": identity_token(username),
+        })).await;
+        self.recv("user_handled").await
+    }
+
+    /// `handle` the owned `username` and return the session token of the HTTP API
+    pub async fn session(&mut self, username: &str) -> String {
+        self.handle(username).await["session_token"].as_str().expect("user_handled carries a session token").to_string()
+    }
+
+    pub async fn disconnect(self) {
+        self.client.disconnect().await.ok();
+    }
+}
+
+/// Identity token of `username` valid for an hour, as the login service would issue it
+pub fn identity_token(username: &str) -> String {
+    let expires_at = chrono::Utc::now().timestamp() + 3600;
+    IdentityVerifier::new(Some(IDENTITY_SECRET.to_string())).sign(username, expires_at).unwrap()
+}
+
+/// Poll `condition` until it holds or the timeout elapses
+pub async fn eventually<F, Fut>(mut condition: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let started = std::time::Instant::now();
+    while started.elapsed() < TIMEOUT {
+        if condition().await {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
@@ -0,0 +1,86 @@
+use bson::doc;
+use bson::oid::ObjectId;
+use reqwest::Method;
+use serde_json::json;
+use crate::db_model::AttachmentCollection;
+use crate::errors::MyError;
+use super::TestApp;
+
+async fn attachment(app: &TestApp, uploader: &str, content_type: &str, room: Option<&str>) -> String {
+    let attachment = AttachmentCollection {
+        id: ObjectId::new(),
+        name: "file".to_string(),
+        size: 4,
+        content_type: content_type.to_string(),
+        checksum: String::new(),
+        uploader: uploader.to_string(),
+        storage_key: ObjectId::new().to_hex(),
+        room: room.map(String::from),
+        participants: vec![],
+        public: false,
+        updated_at: chrono::Utc::now(),
+        created_at: chrono::Utc::now(),
+    };
+    app.state.db.attachments_collection.as_ref().unwrap().insert_one(&attachment, None).await.unwrap();
+    attachment.id.to_hex()
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn only_the_owner_updates_a_profile() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let token = alice.session("alice").await;
+    bob.handle("bob").await;
+
+    let patch = json!({ "display_name": "Alice" });
+    let (status, _) = app.http(Method::PATCH, "/api/users/alice", None, Some(patch.clone())).await;
+    assert_eq!(status, 401);
+    let (status, _) = app.http(Method::PATCH, "/api/users/bob", Some(&token), Some(patch.clone())).await;
+    assert_eq!(status, 403);
+
+    let (status, profile) = app.http(Method::PATCH, "/api/users/alice", Some(&token), Some(patch)).await;
+    assert_eq!(status, 200);
+    assert_eq!(profile["display_name"], "Alice");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn avatars_are_unbound_images_of_the_user() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let token = alice.session("alice").await;
+    bob.handle("bob").await;
+
+    let rejected = [
+        attachment(&app, "bob", "image/png", None).await,
+        attachment(&app, "alice", "image/png", Some("general")).await,
+        attachment(&app, "alice", "application/pdf", None).await,
+    ];
+    for id in &rejected {
+        let (status, _) = app.http(Method::PATCH, "/api/users/alice", Some(&token), Some(json!({ "avatar": id }))).await;
+        assert_eq!(status, 400, "avatar {}", id);
+    }
+    let attachments = app.state.db.attachments_collection.as_ref().unwrap();
+    assert_eq!(attachments.count_documents(doc! {"public": true}, None).await.unwrap(), 0);
+
+    let avatar = attachment(&app, "alice", "image/png", None).await;
+    let (status, profile) = app.http(Method::PATCH, "/api/users/alice", Some(&token), Some(json!({ "avatar": avatar }))).await;
+    assert_eq!(status, 200);
+    assert_eq!(profile["avatar"], avatar.as_str());
+    assert!(app.state.db.find_attachment(&avatar).await.unwrap().public);
+
+    // a public avatar can not be attached to a conversation afterwards
+    let linked = app.state.db.link_attachments(&[avatar], "alice", Some("general".to_string()), vec![]).await;
+    assert!(matches!(linked, Err(MyError::ForbiddenError(_))));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
@@ -0,0 +1,175 @@
+use bson::doc;
+use bson::oid::ObjectId;
+use reqwest::Method;
+use serde_json::json;
+use crate::db_model::{HeldMessageCollection, ReportAction, ReportStatus, ReviewStatus};
+use crate::moderation::MessageKind;
+use crate::errors::MyError;
+use super::{TestApp, TestClient, MODERATOR_TOKEN};
+
+/// `alice` posts in `general` and `bob` reports the message, returns the ID of the message and of the report
+async fn reported_message(alice: &mut TestClient, bob: &mut TestClient) -> (String, String) {
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+    alice.emit("join_room", json!({ "room": "general", "message": "" })).await;
+    alice.recv("messages").await;
+    alice.emit("message", json!({ "room": "general", "message": "buy now" })).await;
+    let message_id = alice.recv("response").await["id"].as_str().unwrap().to_string();
+
+    bob.emit("report", json!({ "kind": "message", "target": message_id, "reason": "spam" })).await;
+    let report = bob.recv("report_received").await;
+    assert_eq!(report["reported_user"], "alice");
+    (message_id, report["id"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn resolving_with_delete_removes_the_message_and_audits_the_action() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let (message_id, report_id) = reported_message(&mut alice, &mut bob).await;
+
+    let db = &app.state.db;
+    let (report, removed) = db.resolve_report_with_action(&report_id, "moderator", ReportAction::DeleteMessage, None, chrono::Utc::now()).await.unwrap();
+    assert_eq!(report.status, ReportStatus::Resolved);
+    assert_eq!(report.moderator.as_deref(), Some("moderator"));
+    assert!(removed.is_some());
+
+    let oid = ObjectId::parse_str(&message_id).unwrap();
+    let message = db.messages_collection.as_ref().unwrap().find_one(doc! {"_id": oid}, None).await.unwrap();
+    assert!(message.is_none());
+
+    let audit = db.get_admin_audit(10, 1).await.unwrap();
+    assert!(audit.iter().any(|entry| entry.action.eq("resolve_report") && entry.target.as_deref() == Some(report_id.as_str())));
+
+    // a resolved report can not be resolved again
+    let again = db.resolve_report_with_action(&report_id, "moderator", ReportAction::Dismiss, None, chrono::Utc::now()).await;
+    assert!(matches!(again, Err(MyError::ConflictError(_))));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn a_claimed_report_is_only_resolved_by_its_moderator() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let (message_id, report_id) = reported_message(&mut alice, &mut bob).await;
+
+    let db = &app.state.db;
+    db.claim_report(&report_id, "first").await.unwrap();
+    assert!(matches!(db.claim_report(&report_id, "second").await, Err(MyError::ConflictError(_))));
+
+    let other = db.resolve_report_with_action(&report_id, "second", ReportAction::DeleteMessage, None, chrono::Utc::now()).await;
+    assert!(matches!(other, Err(MyError::ConflictError(_))));
+    // nothing of the rejected resolution was applied
+    let oid = ObjectId::parse_str(&message_id).unwrap();
+    assert!(db.messages_collection.as_ref().unwrap().find_one(doc! {"_id": oid}, None).await.unwrap().is_some());
+    assert_eq!(db.find_report(&report_id).await.unwrap().status, ReportStatus::Claimed);
+
+    let (report, _) = db.resolve_report_with_action(&report_id, "first", ReportAction::Dismiss, None, chrono::Utc::now()).await.unwrap();
+    assert_eq!(report.action, Some(ReportAction::Dismiss));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn deleting_a_user_report_is_rejected_before_anything_is_written() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    bob.emit("report", json!({ "kind": "user", "target": "alice", "reason": "rude" })).await;
+    let report_id = bob.recv("report_received").await["id"].as_str().unwrap().to_string();
+
+    let db = &app.state.db;
+    let res = db.resolve_report_with_action(&report_id, "moderator", ReportAction::DeleteMessage, None, chrono::Utc::now()).await;
+    assert!(matches!(res, Err(MyError::BadRequestError(_))));
+    assert_eq!(db.find_report(&report_id).await.unwrap().status, ReportStatus::Open);
+    assert!(db.get_admin_audit(10, 1).await.unwrap().is_empty());
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn the_review_queue_needs_the_moderator_token_and_a_moderator_session() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let mut moderator = app.client().await;
+    reported_message(&mut alice, &mut bob).await;
+    let bob_token = bob.session("bob").await;
+    let moderator_session = moderator.session("moderator").await;
+    let secret = [("x-moderator-token", MODERATOR_TOKEN)];
+
+    let (status, _) = app.http(Method::GET, "/api/moderation/reports", Some(&moderator_session), None).await;
+    assert_eq!(status, 401);
+    let (status, _) = app.http_with(Method::GET, "/api/moderation/reports", Some(&moderator_session), &[("x-moderator-token", "guess")], None).await;
+    assert_eq!(status, 401);
+    // the token alone does not make bob a moderator
+    let (status, _) = app.http_with(Method::GET, "/api/moderation/reports", Some(&bob_token), &secret, None).await;
+    assert_eq!(status, 403);
+
+    let (status, reports) = app.http_with(Method::GET, "/api/moderation/reports", Some(&moderator_session), &secret, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(reports[0]["reporter"], "bob");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    moderator.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn a_held_message_of_a_restricted_sender_is_not_released() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut moderator = app.client().await;
+    alice.handle("alice").await;
+    let moderator_session = moderator.session("moderator").await;
+    let secret = [("x-moderator-token", MODERATOR_TOKEN)];
+
+    let now = chrono::Utc::now();
+    let held = HeldMessageCollection {
+        id: ObjectId::new(),
+        kind: MessageKind::Room,
+        sender: "alice".to_string(),
+        target: "general".to_string(),
+        message: "held for review".to_string(),
+        reply_to: None,
+        attachments: vec![],
+        reason: "blocklist".to_string(),
+        status: ReviewStatus::Pending,
+        reviewed_by: None,
+        updated_at: now,
+        created_at: now,
+    };
+    let db = &app.state.db;
+    db.held_messages_collection.as_ref().unwrap().insert_one(&held, None).await.unwrap();
+    // muted while the message waited for a moderator
+    db.mute_user("alice", now + chrono::Duration::hours(1), None).await.unwrap();
+
+    let path = format!("/api/moderation/held/{}/approve", held.id.to_hex());
+    let (status, _) = app.http_with(Method::POST, &path, Some(&moderator_session), &secret, None).await;
+    assert_eq!(status, 409);
+    let (_, pending) = app.http_with(Method::GET, "/api/moderation/held", Some(&moderator_session), &secret, None).await;
+    assert_eq!(pending[0]["id"], held.id.to_hex().as_str());
+    assert!(db.messages_collection.as_ref().unwrap().find_one(doc! {"room": "general"}, None).await.unwrap().is_none());
+
+    alice.disconnect().await;
+    moderator.disconnect().await;
+    app.cleanup().await;
+}
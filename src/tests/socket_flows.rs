@@ -0,0 +1,221 @@
+use reqwest::Method;
+use serde_json::json;
+use crate::db_model::RoomRole;
+use super::{eventually, TestApp, ADMIN_TOKEN};
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn username_on_connect() {
+    let app = TestApp::spawn().await;
+
+    let client = app.client().await;
+    assert!(!client.username.is_empty());
+    assert_eq!(app.state.io.sockets().unwrap().len(), 1);
+
+    client.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn join_room_returns_messages() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+
+    alice.emit("join_room", json!({ "sender": alice.username, "room": "general", "message": "" })).await;
+    let messages = alice.recv("messages").await;
+    assert_eq!(messages["messages"].as_array().unwrap().len(), 0);
+
+    alice.emit("message", json!({ "sender": alice.username, "room": "general", "message": "hello" })).await;
+    alice.recv("response").await;
+
+    bob.emit("join_room", json!({ "sender": bob.username, "room": "general", "message": "" })).await;
+    let messages = bob.recv("messages").await;
+    let messages = messages["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["message"], "hello");
+    assert_eq!(messages[0]["sender"], alice.username.as_str());
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn message_fans_out_to_the_room() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let mut carol = app.client().await;
+
+    for client in [&mut alice, &mut bob] {
+        client.emit("join_room", json!({ "sender": client.username, "room": "general", "message": "" })).await;
+        client.recv("messages").await;
+    }
+    carol.emit("join_room", json!({ "sender": carol.username, "room": "random", "message": "" })).await;
+    carol.recv("messages").await;
+
+    let sender = alice.username.clone();
+    alice.emit("message", json!({ "sender": sender, "room": "general", "message": "hi all" })).await;
+    for client in [&mut alice, &mut bob] {
+        let response = client.recv("response").await;
+        assert_eq!(response["message"], "hi all");
+        assert_eq!(response["room"], "general");
+        assert_eq!(response["sender"], sender.as_str());
+    }
+
+    // carol is in another room, the next event she gets is her own room's response
+    carol.emit("message", json!({ "sender": carol.username, "room": "random", "message": "elsewhere" })).await;
+    assert_eq!(carol.recv("response").await["message"], "elsewhere");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    carol.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn private_message_reaches_both_sides() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    alice.emit("private", json!({ "receiver": "bob", "message": "psst" })).await;
+
+    let resp = bob.recv("resp").await;
+    assert_eq!(resp["message"], "psst");
+    assert_eq!(resp["sender"], "alice");
+    assert_eq!(resp["status"], "sent");
+
+    let resp_back = alice.recv("resp_back").await;
+    assert_eq!(resp_back["receiver"], "bob");
+    assert_eq!(resp_back["id"], resp["id"]);
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn private_message_to_unknown_user_is_rejected() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    alice.handle("alice").await;
+
+    alice.emit("private", json!({ "receiver": "nobody", "message": "psst" })).await;
+    let error = alice.recv("event_error").await;
+    assert_eq!(error["event"], "private");
+
+    alice.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn disconnect_cleans_up_the_socket() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    alice.handle("alice").await;
+    assert_eq!(app.state.socket_state.socket_map.read().await.len(), 1);
+
+    alice.disconnect().await;
+
+    let state = app.state.clone();
+    assert!(eventually(|| {
+        let state = state.clone();
+        async move {
+            state.io.sockets().unwrap().is_empty() && state.socket_state.socket_map.read().await.is_empty()
+        }
+    }).await);
+    assert!(app.state.io.within("alice").sockets().unwrap().is_empty());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn only_an_owned_username_becomes_the_room_owner() {
+    let app = TestApp::spawn().await;
+    let mut guest = app.client().await;
+    let mut alice = app.client().await;
+    alice.handle("alice").await;
+
+    guest.emit("join_room", json!({ "room": "lobby", "message": "" })).await;
+    guest.recv("messages").await;
+    let db = &app.state.db;
+    assert_eq!(db.room_member_role("lobby", &guest.username).await.unwrap(), Some(RoomRole::Member));
+
+    alice.emit("join_room", json!({ "room": "lobby", "message": "" })).await;
+    alice.recv("messages").await;
+    assert_eq!(db.room_member_role("lobby", "alice").await.unwrap(), Some(RoomRole::Owner));
+
+    guest.disconnect().await;
+    alice.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn the_stored_sockets_are_only_listed_to_the_admins() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let alice_token = alice.session("alice").await;
+
+    let (status, _) = app.http(Method::GET, "/api/sockets-list", Some(&alice_token), None).await;
+    assert_eq!(status, 404);
+    let (status, _) = app.http(Method::GET, "/api/admin/sockets-list", Some(&alice_token), None).await;
+    assert_eq!(status, 401);
+    let (status, sockets) = app.http(Method::GET, "/api/admin/sockets-list", Some(ADMIN_TOKEN), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(sockets["total_records"], 1);
+
+    alice.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn readiness_covers_mongodb_the_indexes_and_the_adapter() {
+    let app = TestApp::spawn().await;
+    let client = app.client().await;
+
+    let (status, report) = app.http(Method::GET, "/api/status", None, None).await;
+    let checks = report["checks"].as_array().unwrap();
+    let names: Vec<_> = checks.iter().map(|check| check["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["mongodb", "indexes", "socketio_adapter"]);
+    let adapter = checks.iter().find(|check| check["name"] == "socketio_adapter").unwrap();
+    assert_eq!(adapter["ok"], true);
+    assert_eq!(report["sockets"], 1);
+    assert_eq!(status, 200);
+    let (status, _) = app.http(Method::GET, "/readyz", None, None).await;
+    assert_eq!(status, 200);
+
+    client.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn the_room_of_a_user_can_not_be_joined() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut mallory = app.client().await;
+    alice.handle("alice").await;
+
+    for room in ["alice", "thread:65f0c3e2a1b4c5d6e7f80912"] {
+        mallory.emit("join_room", json!({ "room": room, "message": "" })).await;
+        assert_eq!(mallory.recv("event_error").await["event"], "join_room");
+    }
+    // only the socket of alice is in her room
+    assert_eq!(app.state.io.within("alice").sockets().unwrap().len(), 1);
+
+    alice.disconnect().await;
+    mallory.disconnect().await;
+    app.cleanup().await;
+}
@@ -0,0 +1,70 @@
+use bson::doc;
+use futures_util::future::join_all;
+use crate::model::User;
+use super::TestApp;
+
+const RACERS: usize = 32;
+
+async fn count_users(app: &TestApp, username: &str) -> u64 {
+    app.state.db.users_collection.as_ref().unwrap()
+        .count_documents(doc! {"owned_uname": username}, None)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn concurrent_upserts_create_a_single_user() {
+    let app = TestApp::spawn().await;
+
+    let results = join_all((0..RACERS).map(|i| {
+        let db = app.state.db.clone();
+        async move {
+            db.link_user(User {
+                username: "racer".to_string(),
+                generated_username: format!("generated-{}", i),
+            }).await
+        }
+    })).await;
+
+    for res in &results {
+        let user = res.as_ref().expect("every upsert links the user");
+        assert_eq!(user.owned_uname, "racer");
+    }
+    assert_eq!(count_users(&app, "racer").await, 1);
+
+    // the last write wins the generated username and keeps the previous one
+    let user = app.state.db.users_collection.as_ref().unwrap()
+        .find_one(doc! {"owned_uname": "racer"}, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(user.cur_gen_uname.starts_with("generated-"));
+    assert!(user.last_username.starts_with("generated-"));
+    assert_ne!(user.cur_gen_uname, user.last_username);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn concurrent_joins_link_every_socket() {
+    let app = TestApp::spawn().await;
+
+    let mut clients = Vec::with_capacity(RACERS / 4);
+    for _ in 0..RACERS / 4 {
+        clients.push(app.client().await);
+    }
+    let handled = join_all(clients.iter_mut().map(|client| client.handle("joiner"))).await;
+
+    for user in &handled {
+        assert_eq!(user["owned_uname"], "joiner");
+    }
+    assert_eq!(count_users(&app, "joiner").await, 1);
+    assert_eq!(app.state.io.within("joiner").sockets().unwrap().len(), clients.len());
+
+    for client in clients {
+        client.disconnect().await;
+    }
+    app.cleanup().await;
+}
@@ -0,0 +1,197 @@
+use chrono::Duration;
+use reqwest::Method;
+use serde_json::json;
+use crate::db_model::{ReportAction, ReportKind};
+use crate::errors::MyError;
+use crate::model::Restriction;
+use crate::socket_state::SocketState;
+use super::{identity_token, TestApp};
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn rename_moves_the_moderation_references() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    bob.emit("report", json!({ "kind": "user", "target": "alice", "reason": "rude" })).await;
+    let report_id = bob.recv("report_received").await["id"].as_str().unwrap().to_string();
+
+    let db = &app.state.db;
+    db.rename_user("alice", "alicia", Duration::zero(), Duration::hours(1)).await.unwrap();
+    db.rename_user("bob", "robert", Duration::zero(), Duration::hours(1)).await.unwrap();
+
+    let report = db.find_report(&report_id).await.unwrap();
+    assert_eq!(report.kind, ReportKind::User);
+    assert_eq!(report.reporter, "robert");
+    assert_eq!(report.reported_user, "alicia");
+    assert_eq!(report.target, "alicia");
+
+    // the action reaches the user under the new name
+    db.resolve_report_with_action(&report_id, "moderator", ReportAction::Ban, None, chrono::Utc::now()).await.unwrap();
+    assert_eq!(db.user_restriction("alicia").await.unwrap(), Some(Restriction::Banned));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn rename_is_refused_during_the_cooldown() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    alice.handle("alice").await;
+
+    let db = &app.state.db;
+    db.rename_user("alice", "alicia", Duration::hours(1), Duration::hours(1)).await.unwrap();
+    let again = db.rename_user("alicia", "ali", Duration::hours(1), Duration::hours(1)).await;
+    assert!(matches!(again, Err(MyError::ConflictError(_))));
+    assert_eq!(db.resolve_username("alicia").await.unwrap().as_deref(), Some("alicia"));
+
+    // without a cooldown the next rename goes through
+    db.rename_user("alicia", "ali", Duration::zero(), Duration::hours(1)).await.unwrap();
+
+    alice.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn old_name_redirects_during_the_grace_period() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    let db = &app.state.db;
+    db.rename_user("alice", "alicia", Duration::zero(), Duration::hours(1)).await.unwrap();
+    assert_eq!(db.resolve_username("alice").await.unwrap().as_deref(), Some("alicia"));
+
+    // nobody else can take the redirecting name, its owner can take it back
+    let taken = db.rename_user("bob", "alice", Duration::zero(), Duration::hours(1)).await;
+    assert!(matches!(taken, Err(MyError::ConflictError(_))));
+    db.rename_user("alicia", "alice", Duration::zero(), Duration::hours(1)).await.unwrap();
+    assert_eq!(db.resolve_username("alice").await.unwrap().as_deref(), Some("alice"));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn old_name_is_released_after_the_grace_period() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    let db = &app.state.db;
+    db.rename_user("alice", "alicia", Duration::zero(), Duration::zero()).await.unwrap();
+    assert_eq!(db.resolve_username("alice").await.unwrap(), None);
+
+    db.rename_user("bob", "alice", Duration::zero(), Duration::zero()).await.unwrap();
+    assert_eq!(db.resolve_username("alice").await.unwrap().as_deref(), Some("alice"));
+    assert_eq!(db.resolve_username("alicia").await.unwrap().as_deref(), Some("alicia"));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn private_messages_to_the_old_name_reach_the_renamed_user() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    alice.handle("alice").await;
+    bob.handle("bob").await;
+
+    alice.emit("rename_user", json!({ "username": "alicia" })).await;
+    let renamed = alice.recv("user_renamed").await;
+    assert_eq!(renamed["old_username"], "alice");
+    assert_eq!(renamed["new_username"], "alicia");
+
+    bob.emit("private", json!({ "receiver": "alice", "message": "still there?" })).await;
+    let resp = alice.recv("resp").await;
+    assert_eq!(resp["receiver"], "alicia");
+    assert_eq!(resp["message"], "still there?");
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}
+
+#[test]
+fn usernames_can_not_look_like_the_routing_rooms() {
+    for valid in ["alice", "Bob_2", "ali", "thread2", "administrator"] {
+        assert!(SocketState::check_username_format(valid).is_ok(), "{} is valid", valid);
+    }
+    let invalid = [
+        "al", "a".repeat(33).as_str(), "alice smith", "thread:65f0c3e2a1b4c5d6e7f80912", "gentle-fox", "alice.smith", "",
+        "admin", "System", "thread_1", "room_general", "Group_x",
+    ].map(String::from);
+    for name in &invalid {
+        assert!(SocketState::check_username_format(name).is_err(), "{} is invalid", name);
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn registration_and_rename_validate_the_username() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut other = app.client().await;
+    alice.handle("alice").await;
+    alice.emit("join_room", json!({ "room": "general", "message": "" })).await;
+    alice.recv("messages").await;
+
+    for name in ["thread:abc", "gentle-fox", "system", "general"] {
+        alice.emit("rename_user", json!({ "username": name })).await;
+        assert_eq!(alice.recv("event_error").await["event"], "rename_user", "{} is refused", name);
+    }
+    assert_eq!(app.state.db.resolve_username("alice").await.unwrap().as_deref(), Some("alice"));
+
+    // a new user can not register under the name of a room either
+    other.emit("user_handle", json!({
+        "username": "general",
+        "generated_username": other.username,
+        "token": identity_token("general"),
+    })).await;
+    assert_eq!(other.recv("event_error").await["event"], "user_handle");
+
+    alice.disconnect().await;
+    other.disconnect().await;
+    app.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs MongoDB at TEST_MONGO_URI"]
+async fn only_the_user_reads_its_rename_trail() {
+    let app = TestApp::spawn().await;
+    let mut alice = app.client().await;
+    let mut bob = app.client().await;
+    let alice_token = alice.session("alice").await;
+    let bob_token = bob.session("bob").await;
+    alice.emit("rename_user", json!({ "username": "alicia" })).await;
+    alice.recv("user_renamed").await;
+
+    let (status, _) = app.http(Method::GET, "/api/users/alicia/history", None, None).await;
+    assert_eq!(status, 401);
+    let (status, _) = app.http(Method::GET, "/api/users/alicia/history", Some(&bob_token), None).await;
+    assert_eq!(status, 403);
+    // the old name redirects to the caller
+    let (status, history) = app.http(Method::GET, "/api/users/alice/history", Some(&alice_token), None).await;
+    assert_eq!(status, 200);
+    assert!(history.as_array().unwrap().iter().any(|entry| entry["username"] == "alice"));
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+    app.cleanup().await;
+}